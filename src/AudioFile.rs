@@ -0,0 +1,830 @@
+//! # Audio File
+//! Reading and writing audio files without any external dependencies.
+//!
+//! `WavWriter` streams planar blocks straight to disk, so renders of any length only ever hold one block in memory.
+//! Samples can be written as 16 or 24-bit integers or as 32-bit floats, with any number of channels.
+//!
+//! `AudioFileReader` reads WAV and AIFF/AIFF-C files frame by frame, so long files can be streamed instead of loaded whole.
+//! It understands 8 to 32-bit integer and 32/64-bit float samples in either byte order
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::AudioToolbox::{Error, ErrorCodes};
+
+/// Sample encodings supported when writing audio files
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SampleFormat {
+    Int16,
+    Int24,
+    Float32
+}
+
+impl SampleFormat {
+    pub fn get_bits_per_sample(&self) -> usize {
+        match self {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Float32 => 32
+        }
+    }
+
+    fn get_bytes_per_sample(&self) -> usize {
+        self.get_bits_per_sample() / 8
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Tail of the KSDATAFORMAT_SUBTYPE GUIDs used by WAVE_FORMAT_EXTENSIBLE.  The format tag goes in the first two bytes
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+
+/// Largest fmt or COMM chunk that is read into memory.  Real ones are a few dozen bytes, so anything bigger is a corrupt header
+const MAX_FORMAT_CHUNK_SIZE: u32 = 65_536;
+
+
+/// Writes a WAV file block by block.  The header is written up front with empty sizes, which `finalize()` fills in.
+/// Files with more than two channels use WAVE_FORMAT_EXTENSIBLE, as the WAV specification asks for
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    num_channels: usize,
+    sample_format: SampleFormat,
+    num_frames: usize,
+    data_chunk_position: u64,
+    fact_chunk_position: Option<u64>
+}
+
+impl WavWriter {
+    /// Create (or overwrite) a WAV file
+    pub fn create<P: AsRef<Path>>(path: P, num_channels: usize, sampling_freq: f32, sample_format: SampleFormat) -> Result<WavWriter, Error> {
+        if num_channels == 0 || num_channels > u16::MAX as usize {
+            return Err(Error {
+                code: ErrorCodes::InvalidChannelCount,
+                message: format!("Cannot write a WAV file with {} channels", num_channels)
+            });
+        }
+
+        if sampling_freq < 1.0 {
+            return Err(Error {
+                code: ErrorCodes::InvalidSamplingFrequency,
+                message: format!("Cannot write a WAV file at {} Hz", sampling_freq)
+            });
+        }
+
+        let file = match File::create(path.as_ref()) {
+            Ok(file) => file,
+            Err(e) => return Err(file_error(path.as_ref(), e))
+        };
+
+        let mut wav_writer = WavWriter {
+            writer: BufWriter::new(file),
+            num_channels,
+            sample_format,
+            num_frames: 0,
+            data_chunk_position: 0,
+            fact_chunk_position: None
+        };
+
+        let header = wav_writer.build_header(sampling_freq.round() as u32);
+        wav_writer.write_bytes(&header)?;
+
+        Ok(wav_writer)
+    }
+
+    pub fn get_number_of_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the number of frames (samples per channel) written so far
+    pub fn get_number_of_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Write `num_frames` frames from a planar buffer in which channel `c` starts at `c * channel_stride`.
+    /// This is the layout `AudioGraph::process_block()` produces, with `channel_stride` being the buffer size
+    pub fn write_planar(&mut self, buffer: &[f32], channel_stride: usize, num_frames: usize) -> Result<(), Error> {
+        let mut bytes = Vec::with_capacity(num_frames * self.num_channels * self.sample_format.get_bytes_per_sample());
+
+        for frame in 0..num_frames {
+            for channel in 0..self.num_channels {
+                let sample = buffer.get(channel * channel_stride + frame).copied().unwrap_or(0.0);
+                self.encode_sample(sample, &mut bytes);
+            }
+        }
+
+        self.write_bytes(&bytes)?;
+        self.num_frames += num_frames;
+
+        Ok(())
+    }
+
+    /// Write frames from an interleaved buffer
+    pub fn write_interleaved(&mut self, buffer: &[f32]) -> Result<(), Error> {
+        let num_frames = buffer.len() / self.num_channels;
+        let mut bytes = Vec::with_capacity(num_frames * self.num_channels * self.sample_format.get_bytes_per_sample());
+
+        for sample in &buffer[..num_frames * self.num_channels] {
+            self.encode_sample(*sample, &mut bytes);
+        }
+
+        self.write_bytes(&bytes)?;
+        self.num_frames += num_frames;
+
+        Ok(())
+    }
+
+    /// Fill in the chunk sizes and flush the file.  A writer that is dropped without being finalized leaves a file with empty sizes behind
+    pub fn finalize(mut self) -> Result<(), Error> {
+        let data_size = (self.num_frames * self.num_channels * self.sample_format.get_bytes_per_sample()) as u64;
+
+        //  Chunks have to be padded to an even size
+        if data_size % 2 == 1 {
+            self.write_bytes(&[0])?;
+        }
+
+        //  The RIFF sizes are 32 bits wide, so anything past 4 GiB cannot be described
+        let riff_size = self.data_chunk_position + 4 + data_size + data_size % 2 - 8;
+        if riff_size > u32::MAX as u64 {
+            return Err(Error {
+                code: ErrorCodes::FileAccessFailed,
+                message: format!("{} bytes of audio data do not fit in a WAV file", data_size)
+            });
+        }
+
+        let data_chunk_position = self.data_chunk_position;
+        let num_frames = self.num_frames as u32;
+
+        self.write_at(4, &(riff_size as u32).to_le_bytes())?;
+        self.write_at(data_chunk_position, &(data_size as u32).to_le_bytes())?;
+        if let Some(fact_chunk_position) = self.fact_chunk_position {
+            self.write_at(fact_chunk_position, &num_frames.to_le_bytes())?;
+        }
+
+        match self.writer.flush() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error {
+                code: ErrorCodes::FileAccessFailed,
+                message: format!("Could not finish writing WAV file: {}", e)
+            })
+        }
+    }
+
+    fn build_header(&mut self, sampling_freq: u32) -> Vec<u8> {
+        let bits_per_sample = self.sample_format.get_bits_per_sample() as u16;
+        let block_align = (self.num_channels * self.sample_format.get_bytes_per_sample()) as u16;
+        let format_tag = match self.sample_format {
+            SampleFormat::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM
+        };
+        let is_extensible = self.num_channels > 2;
+
+        let mut header = vec![];
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&(if is_extensible { 40u32 } else { 16u32 }).to_le_bytes());
+        header.extend_from_slice(&(if is_extensible { WAVE_FORMAT_EXTENSIBLE } else { format_tag }).to_le_bytes());
+        header.extend_from_slice(&(self.num_channels as u16).to_le_bytes());
+        header.extend_from_slice(&sampling_freq.to_le_bytes());
+        header.extend_from_slice(&(sampling_freq * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+        if is_extensible {
+            header.extend_from_slice(&22u16.to_le_bytes());
+            header.extend_from_slice(&bits_per_sample.to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(&format_tag.to_le_bytes());
+            header.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        }
+
+        //  Files that are not plain PCM carry a fact chunk with the number of frames
+        if format_tag != WAVE_FORMAT_PCM || is_extensible {
+            header.extend_from_slice(b"fact");
+            header.extend_from_slice(&4u32.to_le_bytes());
+            self.fact_chunk_position = Some(header.len() as u64);
+            header.extend_from_slice(&0u32.to_le_bytes());
+        }
+
+        header.extend_from_slice(b"data");
+        self.data_chunk_position = header.len() as u64;
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        header
+    }
+
+    fn encode_sample(&self, sample: f32, bytes: &mut Vec<u8>) {
+        match self.sample_format {
+            SampleFormat::Int16 => {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                bytes.extend_from_slice(&value.to_le_bytes());
+            },
+            SampleFormat::Int24 => {
+                let value = (sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32;
+                bytes.extend_from_slice(&value.to_le_bytes()[..3]);
+            },
+            SampleFormat::Float32 => {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match self.writer.write_all(bytes) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error {
+                code: ErrorCodes::FileAccessFailed,
+                message: format!("Could not write to WAV file: {}", e)
+            })
+        }
+    }
+
+    fn write_at(&mut self, position: u64, bytes: &[u8]) -> Result<(), Error> {
+        let result = self.writer.seek(SeekFrom::Start(position))
+                                .and_then(|_| self.writer.write_all(bytes))
+                                .and_then(|_| self.writer.seek(SeekFrom::End(0)).map(|_| ()));
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error {
+                code: ErrorCodes::FileAccessFailed,
+                message: format!("Could not update WAV header: {}", e)
+            })
+        }
+    }
+}
+
+/// Format of an audio file opened with `AudioFileReader`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AudioFileInfo {
+    pub num_channels: usize,
+    pub sampling_freq: f32,
+    /// Length of the file in frames (samples per channel)
+    pub num_frames: usize,
+    pub bits_per_sample: usize,
+    pub is_float: bool
+}
+
+impl AudioFileInfo {
+    pub fn get_duration(&self) -> f32 {
+        self.num_frames as f32 / self.sampling_freq
+    }
+}
+
+/// How the samples of a file are stored
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct SampleEncoding {
+    bytes_per_sample: usize,
+    is_float: bool,
+    big_endian: bool
+}
+
+/// Reads the samples of a WAV or AIFF file, converting them to f32 in the range [-1, 1]
+pub struct AudioFileReader {
+    reader: BufReader<File>,
+    info: AudioFileInfo,
+    encoding: SampleEncoding,
+    data_start: u64,
+    position: usize
+}
+
+impl AudioFileReader {
+    /// Open a WAV or AIFF file and read its header.  The format is recognised by the contents, not the file extension
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AudioFileReader, Error> {
+        let file = match File::open(path.as_ref()) {
+            Ok(file) => file,
+            Err(e) => return Err(file_error(path.as_ref(), e))
+        };
+
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 12];
+        read_exact(&mut reader, &mut magic)?;
+
+        let (info, encoding, data_start) = match (&magic[0..4], &magic[8..12]) {
+            (b"RIFF", b"WAVE") => parse_wav_chunks(&mut reader)?,
+            (b"FORM", b"AIFF") => parse_aiff_chunks(&mut reader, false)?,
+            (b"FORM", b"AIFC") => parse_aiff_chunks(&mut reader, true)?,
+            _ => return Err(invalid_file(String::from("Not a WAV or AIFF file")))
+        };
+
+        let mut audio_file_reader = AudioFileReader {
+            reader,
+            info,
+            encoding,
+            data_start,
+            position: 0
+        };
+        audio_file_reader.seek(0)?;
+
+        Ok(audio_file_reader)
+    }
+
+    pub fn get_info(&self) -> AudioFileInfo {
+        self.info
+    }
+
+    /// Get the frame the next read starts at
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    /// Move to a frame.  Positions past the end of the file are clamped to the end
+    pub fn seek(&mut self, frame: usize) -> Result<(), Error> {
+        let frame = frame.min(self.info.num_frames);
+        let bytes_per_frame = (self.encoding.bytes_per_sample * self.info.num_channels) as u64;
+
+        if let Err(e) = self.reader.seek(SeekFrom::Start(self.data_start + frame as u64 * bytes_per_frame)) {
+            return Err(Error {
+                code: ErrorCodes::FileAccessFailed,
+                message: format!("Could not seek in audio file: {}", e)
+            });
+        }
+
+        self.position = frame;
+        Ok(())
+    }
+
+    /// Read interleaved frames into `buffer`, as many as fit or are left in the file.  Returns the number of frames read, 0 at the end of the file
+    pub fn read_interleaved(&mut self, buffer: &mut [f32]) -> Result<usize, Error> {
+        let num_channels = self.info.num_channels;
+        let num_frames = (buffer.len() / num_channels).min(self.info.num_frames - self.position);
+
+        let mut bytes = vec![0u8; num_frames * num_channels * self.encoding.bytes_per_sample];
+        read_exact(&mut self.reader, &mut bytes)?;
+
+        for (sample, sample_bytes) in buffer.iter_mut().zip(bytes.chunks_exact(self.encoding.bytes_per_sample)) {
+            *sample = decode_sample(sample_bytes, self.encoding);
+        }
+
+        self.position += num_frames;
+        Ok(num_frames)
+    }
+}
+
+/// Read a whole audio file into memory, one vector of samples per channel
+pub fn read_audio_file<P: AsRef<Path>>(path: P) -> Result<(Vec<Vec<f32>>, AudioFileInfo), Error> {
+    let mut reader = AudioFileReader::open(path)?;
+    let info = reader.get_info();
+
+    let mut interleaved = vec![0.0; info.num_frames * info.num_channels];
+    reader.read_interleaved(&mut interleaved)?;
+
+    let channels = (0..info.num_channels).map(|channel| {
+        interleaved.iter().skip(channel).step_by(info.num_channels).copied().collect()
+    }).collect();
+
+    Ok((channels, info))
+}
+
+fn parse_wav_chunks(reader: &mut BufReader<File>) -> Result<(AudioFileInfo, SampleEncoding, u64), Error> {
+    let mut format: Option<(usize, f32, usize, bool)> = None;
+
+    loop {
+        let (chunk_id, chunk_size) = match read_chunk_header(reader, false)? {
+            Some(header) => header,
+            None => return Err(invalid_file(String::from("WAV file has no data chunk")))
+        };
+
+        match &chunk_id {
+            b"fmt " => {
+                if chunk_size > MAX_FORMAT_CHUNK_SIZE {
+                    return Err(invalid_file(format!("WAV fmt chunk of {} bytes is too long", chunk_size)));
+                }
+
+                let mut fmt = vec![0u8; chunk_size as usize];
+                read_exact(reader, &mut fmt)?;
+                if fmt.len() < 16 {
+                    return Err(invalid_file(String::from("WAV fmt chunk is too short")));
+                }
+
+                let mut format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let num_channels = u16::from_le_bytes([fmt[2], fmt[3]]) as usize;
+                let sampling_freq = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]) as f32;
+                let bits_per_sample = u16::from_le_bytes([fmt[14], fmt[15]]) as usize;
+
+                if format_tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+                    format_tag = u16::from_le_bytes([fmt[24], fmt[25]]);
+                }
+
+                let is_float = match format_tag {
+                    WAVE_FORMAT_PCM => false,
+                    WAVE_FORMAT_IEEE_FLOAT => true,
+                    _ => return Err(unsupported_file(format!("WAV format tag {} is not supported", format_tag)))
+                };
+
+                format = Some((num_channels, sampling_freq, bits_per_sample, is_float));
+                skip_bytes(reader, chunk_size as u64 % 2)?;
+            },
+
+            b"data" => {
+                let (num_channels, sampling_freq, bits_per_sample, is_float) = match format {
+                    Some(format) => format,
+                    None => return Err(invalid_file(String::from("WAV data chunk comes before the fmt chunk")))
+                };
+
+                let encoding = SampleEncoding { bytes_per_sample: bits_per_sample.div_ceil(8), is_float, big_endian: false };
+                let data_start = get_position(reader)?;
+                let info = build_info(reader, num_channels, sampling_freq, data_start, chunk_size as u64, bits_per_sample, encoding)?;
+
+                return Ok((info, encoding, data_start));
+            },
+
+            _ => { skip_bytes(reader, chunk_size as u64 + chunk_size as u64 % 2)?; }
+        }
+    }
+}
+
+fn parse_aiff_chunks(reader: &mut BufReader<File>, is_aifc: bool) -> Result<(AudioFileInfo, SampleEncoding, u64), Error> {
+    let mut format: Option<(usize, f32, usize, usize, SampleEncoding)> = None;
+    let mut sound_data: Option<(u64, u64)> = None;
+
+    while let Some((chunk_id, chunk_size)) = read_chunk_header(reader, true)? {
+
+        match &chunk_id {
+            b"COMM" => {
+                if chunk_size > MAX_FORMAT_CHUNK_SIZE {
+                    return Err(invalid_file(format!("AIFF COMM chunk of {} bytes is too long", chunk_size)));
+                }
+
+                let mut comm = vec![0u8; chunk_size as usize];
+                read_exact(reader, &mut comm)?;
+                if comm.len() < 18 || (is_aifc && comm.len() < 22) {
+                    return Err(invalid_file(String::from("AIFF COMM chunk is too short")));
+                }
+
+                let num_channels = u16::from_be_bytes([comm[0], comm[1]]) as usize;
+                let num_frames = u32::from_be_bytes([comm[2], comm[3], comm[4], comm[5]]) as usize;
+                let bits_per_sample = u16::from_be_bytes([comm[6], comm[7]]) as usize;
+                let sampling_freq = decode_extended_float(&comm[8..18]) as f32;
+                let bytes_per_sample = bits_per_sample.div_ceil(8);
+
+                let compression = if is_aifc { [comm[18], comm[19], comm[20], comm[21]] } else { *b"NONE" };
+                let encoding = match &compression {
+                    b"NONE" | b"twos" => SampleEncoding { bytes_per_sample, is_float: false, big_endian: true },
+                    b"sowt" => SampleEncoding { bytes_per_sample, is_float: false, big_endian: false },
+                    b"fl32" | b"FL32" => SampleEncoding { bytes_per_sample: 4, is_float: true, big_endian: true },
+                    b"fl64" | b"FL64" => SampleEncoding { bytes_per_sample: 8, is_float: true, big_endian: true },
+                    _ => return Err(unsupported_file(format!("AIFF-C compression type {} is not supported", String::from_utf8_lossy(&compression))))
+                };
+
+                format = Some((num_channels, sampling_freq, num_frames, bits_per_sample, encoding));
+                skip_bytes(reader, chunk_size as u64 % 2)?;
+            },
+
+            b"SSND" => {
+                //  The chunk starts with an offset and a block size
+                if chunk_size < 8 {
+                    return Err(invalid_file(String::from("AIFF SSND chunk is too short")));
+                }
+
+                let mut header = [0u8; 8];
+                read_exact(reader, &mut header)?;
+                let offset = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+
+                let data_start = get_position(reader)? + offset;
+                sound_data = Some((data_start, (chunk_size as u64).saturating_sub(8 + offset)));
+                skip_bytes(reader, (chunk_size as u64).saturating_sub(8) + chunk_size as u64 % 2)?;
+            },
+
+            _ => { skip_bytes(reader, chunk_size as u64 + chunk_size as u64 % 2)?; }
+        }
+    }
+
+    match (format, sound_data) {
+        (Some((num_channels, sampling_freq, num_frames, bits_per_sample, encoding)), Some((data_start, data_size))) => {
+            let mut info = build_info(reader, num_channels, sampling_freq, data_start, data_size, bits_per_sample, encoding)?;
+            info.num_frames = info.num_frames.min(num_frames);
+
+            Ok((info, encoding, data_start))
+        },
+        _ => Err(invalid_file(String::from("AIFF file needs both a COMM and an SSND chunk")))
+    }
+}
+
+fn build_info(reader: &BufReader<File>, num_channels: usize, sampling_freq: f32, data_start: u64, data_size: u64, bits_per_sample: usize, encoding: SampleEncoding) -> Result<AudioFileInfo, Error> {
+    if num_channels == 0 || sampling_freq <= 0.0 {
+        return Err(invalid_file(format!("Invalid audio format: {} channels at {} Hz", num_channels, sampling_freq)));
+    }
+
+    let is_supported = if encoding.is_float {
+        encoding.bytes_per_sample == 4 || encoding.bytes_per_sample == 8
+    } else {
+        (1..=4).contains(&encoding.bytes_per_sample)
+    };
+
+    if !is_supported {
+        return Err(unsupported_file(format!("{}-bit {} samples are not supported", bits_per_sample, if encoding.is_float { "float" } else { "integer" })));
+    }
+
+    //  Truncated or damaged files can claim far more data than they hold, so only count the bytes actually left in the file
+    let file_length = match reader.get_ref().metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Could not read audio file: {}", e)
+        })
+    };
+    let data_size = data_size.min(file_length.saturating_sub(data_start));
+
+    Ok(AudioFileInfo {
+        num_channels,
+        sampling_freq,
+        num_frames: (data_size / (num_channels * encoding.bytes_per_sample) as u64) as usize,
+        bits_per_sample,
+        is_float: encoding.is_float
+    })
+}
+
+fn decode_sample(bytes: &[u8], encoding: SampleEncoding) -> f32 {
+    //  Put the bytes in big-endian order so that every integer size can be decoded the same way
+    let mut big_endian = [0u8; 8];
+    for (index, byte) in bytes.iter().enumerate() {
+        let target = if encoding.big_endian { index } else { bytes.len() - 1 - index };
+        big_endian[target] = *byte;
+    }
+
+    if encoding.is_float {
+        return match bytes.len() {
+            4 => f32::from_be_bytes([big_endian[0], big_endian[1], big_endian[2], big_endian[3]]),
+            _ => f64::from_be_bytes(big_endian) as f32
+        };
+    }
+
+    //  WAV stores 8-bit samples unsigned
+    if bytes.len() == 1 && !encoding.big_endian {
+        return (big_endian[0] as f32 - 128.0) / 128.0;
+    }
+
+    let value = i32::from_be_bytes([big_endian[0], big_endian[1], big_endian[2], big_endian[3]]);
+    value as f32 / 2_147_483_648.0
+}
+
+/// Decode the 80-bit IEEE 754 extended precision float AIFF uses for the sampling frequency
+fn decode_extended_float(bytes: &[u8]) -> f64 {
+    let sign = if bytes[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (((bytes[0] & 0x7F) as i32) << 8) | bytes[1] as i32;
+    let mantissa = u64::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8], bytes[9]]);
+
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+fn read_chunk_header(reader: &mut BufReader<File>, big_endian: bool) -> Result<Option<([u8; 4], u32)>, Error> {
+    let mut header = [0u8; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Could not read audio file: {}", e)
+        })
+    }
+
+    let size_bytes = [header[4], header[5], header[6], header[7]];
+    let size = if big_endian { u32::from_be_bytes(size_bytes) } else { u32::from_le_bytes(size_bytes) };
+
+    Ok(Some(([header[0], header[1], header[2], header[3]], size)))
+}
+
+fn read_exact(reader: &mut BufReader<File>, buffer: &mut [u8]) -> Result<(), Error> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(invalid_file(String::from("Audio file ends unexpectedly"))),
+        Err(e) => Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Could not read audio file: {}", e)
+        })
+    }
+}
+
+fn skip_bytes(reader: &mut BufReader<File>, num_bytes: u64) -> Result<(), Error> {
+    match reader.seek_relative(num_bytes as i64) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Could not read audio file: {}", e)
+        })
+    }
+}
+
+fn get_position(reader: &mut BufReader<File>) -> Result<u64, Error> {
+    match reader.stream_position() {
+        Ok(position) => Ok(position),
+        Err(e) => Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Could not read audio file: {}", e)
+        })
+    }
+}
+
+fn invalid_file(message: String) -> Error {
+    Error {
+        code: ErrorCodes::AudioFileInvalid,
+        message
+    }
+}
+
+fn unsupported_file(message: String) -> Error {
+    Error {
+        code: ErrorCodes::AudioFileUnsupported,
+        message
+    }
+}
+
+fn file_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: ErrorCodes::FileAccessFailed,
+        message: format!("Could not open {}: {}", path.display(), e)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(bytes: &[u8], position: usize) -> u16 {
+        u16::from_le_bytes([bytes[position], bytes[position + 1]])
+    }
+
+    fn read_u32(bytes: &[u8], position: usize) -> u32 {
+        u32::from_le_bytes([bytes[position], bytes[position + 1], bytes[position + 2], bytes[position + 3]])
+    }
+
+    #[test]
+    fn write_wav_files() {
+        let path = std::env::temp_dir().join("audio_graph_write_wav_files.wav");
+
+        //  Two frames of stereo 16-bit PCM, written from a planar buffer with a stride of 4
+        let mut writer = match WavWriter::create(&path, 2, 48_000.0, SampleFormat::Int16) {
+            Ok(writer) => writer,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        if writer.write_planar(&[1.0, -1.0, 0.0, 0.0, 0.5, 2.0, 0.0, 0.0], 4, 2).is_err() { panic!(); }
+        if writer.finalize().is_err() { panic!(); }
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(read_u32(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(read_u16(&bytes, 20), WAVE_FORMAT_PCM);
+        assert_eq!(read_u16(&bytes, 22), 2);
+        assert_eq!(read_u32(&bytes, 24), 48_000);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(read_u32(&bytes, 40), 8);
+        assert_eq!(read_u16(&bytes, 44) as i16, i16::MAX);
+        assert_eq!(read_u16(&bytes, 46) as i16, 16_384);
+        assert_eq!(read_u16(&bytes, 48) as i16, -i16::MAX);
+        assert_eq!(read_u16(&bytes, 50) as i16, i16::MAX);
+
+        //  One frame of mono 24-bit PCM is padded to an even chunk size
+        let mut writer = WavWriter::create(&path, 1, 44_100.0, SampleFormat::Int24).ok().unwrap();
+        if writer.write_interleaved(&[-1.0]).is_err() { panic!(); }
+        if writer.finalize().is_err() { panic!(); }
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(read_u32(&bytes, 40), 3);
+        assert_eq!(&bytes[44..47], &[0x01, 0x00, 0x80]);
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(read_u32(&bytes, 4), 40);
+
+        //  Four channels of float use the extensible format and a fact chunk
+        let mut writer = WavWriter::create(&path, 4, 44_100.0, SampleFormat::Float32).ok().unwrap();
+        if writer.write_interleaved(&[0.25; 8]).is_err() { panic!(); }
+        if writer.finalize().is_err() { panic!(); }
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(read_u16(&bytes, 20), WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(read_u16(&bytes, 44), WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(&bytes[60..64], b"fact");
+        assert_eq!(read_u32(&bytes, 68), 2);
+        assert_eq!(&bytes[72..76], b"data");
+        assert_eq!(read_u32(&bytes, 76), 32);
+        assert_eq!(f32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]), 0.25);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn read_audio_files() {
+        let path = std::env::temp_dir().join("audio_graph_read_audio_files.wav");
+
+        //  Three channels are written as WAVE_FORMAT_EXTENSIBLE
+        let mut writer = WavWriter::create(&path, 3, 22_050.0, SampleFormat::Int24).ok().unwrap();
+        if writer.write_planar(&[0.5, -0.5, 0.25, -0.25, 1.0, 0.0], 2, 2).is_err() { panic!(); }
+        if writer.finalize().is_err() { panic!(); }
+
+        let (channels, info) = match read_audio_file(&path) {
+            Ok(result) => result,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        assert_eq!(info, AudioFileInfo { num_channels: 3, sampling_freq: 22_050.0, num_frames: 2, bits_per_sample: 24, is_float: false });
+        assert_eq!(channels, vec![vec![0.5, -0.5], vec![0.25, -0.25], vec![8_388_607.0 / 8_388_608.0, 0.0]]);
+
+        //  Stereo 16-bit AIFF at 44.1 kHz, seeking to the second of two frames
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"FORM");
+        bytes.extend_from_slice(&46u32.to_be_bytes());
+        bytes.extend_from_slice(b"AIFFCOMM");
+        bytes.extend_from_slice(&18u32.to_be_bytes());
+        bytes.extend_from_slice(&[0, 2, 0, 0, 0, 2, 0, 16, 0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"SSND");
+        bytes.extend_from_slice(&16u32.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0x00, 0xC0, 0x00, 0x20, 0x00, 0x80, 0x00]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut reader = match AudioFileReader::open(&path) {
+            Ok(reader) => reader,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        assert_eq!(reader.get_info().sampling_freq, 44_100.0);
+        assert_eq!(reader.get_info().num_frames, 2);
+
+        let mut frames = [0.0; 4];
+        if reader.seek(1).is_err() { panic!(); }
+        assert!(matches!(reader.read_interleaved(&mut frames), Ok(1)));
+        assert_eq!(frames[..2], [0.25, -1.0]);
+        assert!(matches!(reader.read_interleaved(&mut frames), Ok(0)));
+
+        std::fs::write(&path, b"not an audio file").unwrap();
+        match AudioFileReader::open(&path) {
+            Err(e) => { assert_eq!(e.code, ErrorCodes::AudioFileInvalid); },
+            Ok(_) => panic!()
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reject_malformed_headers() {
+        let path = std::env::temp_dir().join("audio_graph_reject_malformed_headers.aiff");
+
+        let comm = [0, 1, 0, 0, 0, 1, 0, 16, 0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0];
+        let mut files = vec![];
+
+        //  A data chunk claiming 4 GB only holds the frames actually in the file
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 0x01, 0, 2, 0, 16, 0]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0x00, 0x40, 0x00, 0xC0]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let (channels, info) = match read_audio_file(&path) {
+            Ok(result) => result,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        assert_eq!(info.num_frames, 2);
+        assert_eq!(channels, vec![vec![0.5, -0.5]]);
+
+        //  An SSND chunk too short for its offset and block size
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"FORM");
+        bytes.extend_from_slice(&30u32.to_be_bytes());
+        bytes.extend_from_slice(b"AIFFCOMM");
+        bytes.extend_from_slice(&18u32.to_be_bytes());
+        bytes.extend_from_slice(&comm);
+        bytes.extend_from_slice(b"SSND");
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        files.push(bytes);
+
+        //  A COMM chunk claiming 4 GB
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"FORM");
+        bytes.extend_from_slice(&30u32.to_be_bytes());
+        bytes.extend_from_slice(b"AIFFCOMM");
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        bytes.extend_from_slice(&comm);
+        files.push(bytes);
+
+        //  A WAV fmt chunk claiming 4 GB
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&28u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 0x01, 0, 2, 0, 16, 0]);
+        files.push(bytes);
+
+        for bytes in files {
+            std::fs::write(&path, &bytes).unwrap();
+            match AudioFileReader::open(&path) {
+                Err(e) => { assert_eq!(e.code, ErrorCodes::AudioFileInvalid); },
+                Ok(_) => panic!()
+            }
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,357 @@
+//! # Builder
+//! A fluent way to describe a graph by node name instead of wiring it up call by call.
+//!
+//! ```
+//! use audio_graph::Builder::GraphBuilder;
+//! use audio_graph::AudioToolbox::AudioRuntimeParameters;
+//! use audio_graph::ModelNodes::*;
+//!
+//! //  [osc] -> [fx] -> [Output]
+//! let mut graph = GraphBuilder::new()
+//!     .node("osc", TestGenNode::new())
+//!     .node("fx", TestFXNode::new())
+//!     .chain(["osc", "fx", "out"])
+//!     .build()?;
+//!
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 44_100.0, buffer_size: 512 })?;
+//!
+//! let mut buffer = [0.0; 512];
+//! graph.process_block(&mut buffer)?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+//!
+//! The `graph!` macro describes the same graph declaratively.  Connections refer to nodes by identifier, so a misspelled node fails to compile:
+//!
+//! ```
+//! use audio_graph::graph;
+//! use audio_graph::ModelNodes::*;
+//!
+//! let graph = graph! {
+//!     nodes {
+//!         osc = TestGenNode::new(),
+//!         fx = TestFXNode::new()
+//!     }
+//!     connections {
+//!         osc -> fx -> out
+//!     }
+//! }?;
+//!
+//! assert_eq!(graph.find_node_by_name("fx"), Some(2));
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+//!
+//! ```compile_fail
+//! use audio_graph::graph;
+//! use audio_graph::ModelNodes::*;
+//!
+//! let graph = graph! {
+//!     nodes {
+//!         osc = TestGenNode::new()
+//!     }
+//!     connections {
+//!         oscc -> out
+//!     }
+//! };
+//! ```
+
+use super::AudioToolbox::{AudioGraph, AudioNode, Error, ErrorCodes};
+
+/// Name that refers to the output node in connections made through the builder.
+/// The output node can also be referred to by its own name, `AudioToolbox::OUTPUT_NODE_NAME`
+pub const OUTPUT_ALIAS: &str = "out";
+
+
+/// Collects named nodes and the connections between them, then creates an `AudioGraph` from them with `build()`.
+/// Nothing is checked until `build()` is called, which reports the first problem it finds
+pub struct GraphBuilder {
+    nodes: Vec<(String, Box<dyn AudioNode>)>,
+    connections: Vec<PendingConnection>
+}
+
+struct PendingConnection {
+    node_out: String,
+    node_in: String,
+    node_in_input_port: Option<usize>
+}
+
+impl Default for GraphBuilder {
+    fn default() -> GraphBuilder {
+        GraphBuilder::new()
+    }
+}
+
+impl GraphBuilder {
+    pub fn new() -> GraphBuilder {
+        GraphBuilder {
+            nodes: vec![],
+            connections: vec![]
+        }
+    }
+
+    /// Add a node under a name.  Nodes are added to the graph in the order given here, so the first node gets ID 1
+    pub fn node<N>(self, name: &str, node: N) -> GraphBuilder where N: AudioNode + 'static {
+        self.boxed_node(name, Box::new(node))
+    }
+
+    /// Add a node that is already boxed, e.g. one created by a `Patch::NodeRegistry`
+    pub fn boxed_node(mut self, name: &str, node: Box<dyn AudioNode>) -> GraphBuilder {
+        self.nodes.push((String::from(name), node));
+        self
+    }
+
+    /// Connect the output of `node_out` to the next free input of `node_in`.  Use `OUTPUT_ALIAS` as `node_in` to connect to the output node
+    pub fn connect(mut self, node_out: &str, node_in: &str) -> GraphBuilder {
+        self.connections.push(PendingConnection {
+            node_out: String::from(node_out),
+            node_in: String::from(node_in),
+            node_in_input_port: None
+        });
+        self
+    }
+
+    /// Connect the output of `node_out` to a specific input port of `node_in`
+    pub fn connect_to_port(mut self, node_out: &str, node_in: &str, node_in_input_port: usize) -> GraphBuilder {
+        self.connections.push(PendingConnection {
+            node_out: String::from(node_out),
+            node_in: String::from(node_in),
+            node_in_input_port: Some(node_in_input_port)
+        });
+        self
+    }
+
+    /// Connect a series of nodes one after the other: `chain(["osc", "lpf", "out"])` connects osc to lpf and lpf to the output
+    pub fn chain<'a, I>(mut self, names: I) -> GraphBuilder where I: IntoIterator<Item = &'a str> {
+        let mut previous: Option<&str> = None;
+
+        for name in names {
+            if let Some(node_out) = previous {
+                self = self.connect(node_out, name);
+            }
+            previous = Some(name);
+        }
+
+        self
+    }
+
+    /// Create the graph: add every node under its name, then make every connection in the order they were given.
+    /// The graph is returned unprepared so the caller can still change it before calling `AudioGraph::prepare()`
+    pub fn build(self) -> Result<AudioGraph, Error> {
+        let mut graph = AudioGraph::new();
+
+        for (name, node) in self.nodes {
+            if name == OUTPUT_ALIAS {
+                return Err(Error {
+                    code: ErrorCodes::NodeNameInvalid,
+                    message: format!("\"{}\" is reserved for the output node", OUTPUT_ALIAS)
+                });
+            }
+
+            graph.add_new_named_node(&name, node)?;
+        }
+
+        for connection in &self.connections {
+            let node_out_id = GraphBuilder::resolve(&graph, connection, &connection.node_out)?;
+            let node_in_id = GraphBuilder::resolve(&graph, connection, &connection.node_in)?;
+
+            if node_in_id == 0 {
+                graph.connect_node_to_output(node_out_id)?;
+                continue;
+            }
+
+            //  Without an explicit port, take the lowest one no other node is connected to.  A full node falls back to port 0 so that connect_node() reports it
+            let node_in_input_port = match connection.node_in_input_port {
+                Some(port) => port,
+                None => {
+                    let used_ports: Vec<usize> = graph.get_connections().iter()
+                        .filter(|existing| existing.node_in_id == node_in_id)
+                        .map(|existing| existing.node_in_input_port)
+                        .collect();
+                    let num_inputs = graph.get_node(node_in_id).map_or(0, |node| node.get_number_of_inputs());
+
+                    (0..num_inputs).find(|port| !used_ports.contains(port)).unwrap_or(0)
+                }
+            };
+
+            graph.connect_node(node_out_id, node_in_id, node_in_input_port)?;
+        }
+
+        Ok(graph)
+    }
+
+    fn resolve(graph: &AudioGraph, connection: &PendingConnection, name: &str) -> Result<usize, Error> {
+        if name == OUTPUT_ALIAS {
+            return Ok(0);
+        }
+
+        match graph.find_node_by_name(name) {
+            Some(node_id) => Ok(node_id),
+            None => Err(Error {
+                code: ErrorCodes::NodeNameUnknown,
+                message: format!("Connection {} -> {} refers to unknown node \"{}\"", connection.node_out, connection.node_in, name)
+            })
+        }
+    }
+}
+
+
+/// Declare a graph as a list of named nodes followed by chains of connections, and build it with `GraphBuilder`.
+/// Evaluates to `Result<AudioGraph, Error>`.  Every name used in a connection must be declared under `nodes` (or be `out`), otherwise the macro fails to compile.
+/// Port and input checks still happen when the graph is built
+#[macro_export]
+macro_rules! graph {
+    (
+        nodes { $($name:ident = $node:expr),* $(,)? }
+        connections { $($first:ident $(-> $rest:ident)+),* $(,)? }
+    ) => {{
+        let builder = $crate::Builder::GraphBuilder::new()
+            $(.node(stringify!($name), $node))*;
+
+        $(
+            #[allow(unused_variables)]
+            let $name = stringify!($name);
+        )*
+
+        builder
+            $(.chain([$crate::__graph_node_name!($first) $(, $crate::__graph_node_name!($rest))+]))*
+            .build()
+    }};
+}
+
+/// Resolves a node identifier used in `graph!` connections to its name
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __graph_node_name {
+    (out) => { $crate::Builder::OUTPUT_ALIAS };
+    ($name:ident) => { $name };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioToolbox::AudioRuntimeParameters;
+    use crate::ModelNodes::*;
+
+    #[test]
+    fn build_graph_by_name() {
+        //  [g1] -> [fx] -> [mixer] -> [Output]
+        //  [g2] ---------> [     ]
+        let mut graph = match GraphBuilder::new()
+            .node("mixer", TestMixerNode::new(2))
+            .node("g1", TestGenNode::new())
+            .node("fx", TestFXNode::new())
+            .node("g2", TestGenNode::new())
+            .chain(["g1", "fx", "mixer", "out"])
+            .connect("g2", "mixer")
+            .build() {
+            Ok(graph) => graph,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        assert_eq!(graph.find_node_by_name("mixer"), Some(1));
+        assert_eq!(graph.get_connections().len(), 4);
+
+        let runtime_params = AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [1.5; 4]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+
+        //  The macro builds the same graph
+        let macro_graph = crate::graph! {
+            nodes {
+                mixer = TestMixerNode::new(2),
+                g1 = TestGenNode::new(),
+                fx = TestFXNode::new(),
+                g2 = TestGenNode::new(),
+            }
+            connections {
+                g1 -> fx -> mixer -> out,
+                g2 -> mixer,
+            }
+        };
+
+        match macro_graph {
+            Ok(macro_graph) => { assert_eq!(macro_graph.export_json(), graph.export_json()); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    #[test]
+    fn fill_free_ports_after_explicit_ones() {
+        //  [a] -> port 1 of [mixer] -> [Output], so [b] takes the free port 0
+        let graph = match GraphBuilder::new()
+            .node("mixer", TestMixerNode::new(2))
+            .node("a", TestGenNode::new())
+            .node("b", TestGenNode::new())
+            .connect_to_port("a", "mixer", 1)
+            .connect("b", "mixer")
+            .connect("mixer", "out")
+            .build() {
+            Ok(graph) => graph,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        let ports: Vec<(usize, usize)> = graph.get_connections().iter()
+            .filter(|connection| connection.node_in_id == 1)
+            .map(|connection| (connection.node_out_id, connection.node_in_input_port))
+            .collect();
+        assert_eq!(ports, vec![(2, 1), (3, 0)]);
+
+        //  Once every port is taken, the next connection fails
+        let result = GraphBuilder::new()
+            .node("mixer", TestMixerNode::new(2))
+            .node("a", TestGenNode::new())
+            .node("b", TestGenNode::new())
+            .node("c", TestGenNode::new())
+            .connect_to_port("a", "mixer", 1)
+            .connect("b", "mixer")
+            .connect("c", "mixer")
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_invalid_wiring() {
+        let result = GraphBuilder::new()
+            .node("osc", TestGenNode::new())
+            .chain(["osc", "lpf", "out"])
+            .build();
+
+        match result {
+            Err(e) => {
+                assert_eq!(e.code, ErrorCodes::NodeNameUnknown);
+                assert_eq!(e.message, "Connection osc -> lpf refers to unknown node \"lpf\"");
+            },
+            Ok(_) => panic!()
+        }
+
+        //  Generators have no inputs
+        let result = GraphBuilder::new()
+            .node("osc", TestGenNode::new())
+            .node("fx", TestFXNode::new())
+            .chain(["fx", "osc"])
+            .build();
+
+        match result {
+            Err(e) => { assert_eq!(e.code, ErrorCodes::NodeInputPortInvalid); },
+            Ok(_) => panic!()
+        }
+
+        let result = GraphBuilder::new()
+            .node("osc", TestGenNode::new())
+            .node("osc", TestGenNode::new())
+            .build();
+
+        match result {
+            Err(e) => { assert_eq!(e.code, ErrorCodes::NodeNameAlreadyExists); },
+            Ok(_) => panic!()
+        }
+    }
+}
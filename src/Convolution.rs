@@ -0,0 +1,529 @@
+//! # Convolution
+//! Convolution with an impulse response, for convolution reverbs, cabinet simulation and long FIR filters.
+//!
+//! `ConvolutionNode` uses uniformly partitioned overlap-save convolution: the impulse response is cut into partitions of equal length whose spectra
+//! are computed once, and every time a partition's worth of input has arrived its spectrum is multiplied with all of them at once.
+//! Collecting that input would delay the output by a partition, so the first partition is instead convolved sample by sample in direct form,
+//! and the FFT part only covers the rest of the impulse response, whose output is not needed until a partition later.  The node adds no latency.
+//!
+//! ```no_run
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::Convolution::ConvolutionNode;
+//! use audio_graph::Generators::FilePlayerNode;
+//!
+//! //  [dry.wav] -> [hall.wav] -> [Output], in stereo
+//! let mut graph = AudioGraph::new();
+//! graph.set_number_of_output_channels(2)?;
+//! let player_id = graph.add_new_node(Box::new(FilePlayerNode::open("dry.wav")?))?;
+//! let reverb_id = graph.add_new_node(Box::new(ConvolutionNode::open("hall.wav")?))?;
+//! graph.connect_node(player_id, reverb_id, 0)?;
+//! graph.connect_node_to_output(reverb_id)?;
+//! graph.set_node_parameters(reverb_id, &[0.3, 0.0])?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 64 })?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters, Error, ErrorCodes};
+use super::AudioFile::read_audio_file;
+use super::Generators::interpolate_hermite;
+
+/// Length of the partitions the impulse response is cut into, unless told otherwise.
+/// Longer partitions make the FFT part cheaper but the direct-form head, which is as long as a partition, more expensive
+const DEFAULT_PARTITION_SIZE: usize = 256;
+
+const CONVOLUTION_PARAMETER_NAMES: [&str; 2] = ["mix", "gain_db"];
+
+/// Zero crossings on either side of the centre of the windowed sinc that band-limits responses before they are downsampled
+const RESAMPLING_FILTER_ZERO_CROSSINGS: usize = 16;
+
+
+/// In-place radix-2 complex FFT of a fixed size
+struct Fft {
+    size: usize,
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+    bit_reversed: Vec<usize>
+}
+
+impl Fft {
+    /// `size` has to be a power of two
+    fn new(size: usize) -> Fft {
+        let bits = size.trailing_zeros();
+
+        Fft {
+            size,
+            cos: (0..size / 2).map(|k| (2.0 * PI * k as f64 / size as f64).cos() as f32).collect(),
+            sin: (0..size / 2).map(|k| (2.0 * PI * k as f64 / size as f64).sin() as f32).collect(),
+            bit_reversed: (0..size).map(|index| if bits == 0 { 0 } else { index.reverse_bits() >> (usize::BITS - bits) }).collect()
+        }
+    }
+
+    /// Transform `real` and `imaginary` in place.  The inverse transform is not scaled by `1 / size`
+    fn transform(&self, real: &mut [f32], imaginary: &mut [f32], inverse: bool) {
+        for index in 0..self.size {
+            let reversed = self.bit_reversed[index];
+            if reversed > index {
+                real.swap(index, reversed);
+                imaginary.swap(index, reversed);
+            }
+        }
+
+        let sign = if inverse { 1.0 } else { -1.0 };
+        let mut length = 2;
+        while length <= self.size {
+            let half = length / 2;
+            let step = self.size / length;
+
+            for start in (0..self.size).step_by(length) {
+                for k in 0..half {
+                    let (twiddle_real, twiddle_imaginary) = (self.cos[k * step], sign * self.sin[k * step]);
+                    let (a, b) = (start + k, start + k + half);
+
+                    let product_real = real[b] * twiddle_real - imaginary[b] * twiddle_imaginary;
+                    let product_imaginary = real[b] * twiddle_imaginary + imaginary[b] * twiddle_real;
+                    real[b] = real[a] - product_real;
+                    imaginary[b] = imaginary[a] - product_imaginary;
+                    real[a] += product_real;
+                    imaginary[a] += product_imaginary;
+                }
+            }
+
+            length *= 2;
+        }
+    }
+}
+
+/// One channel of the impulse response, ready for convolution: the head in direct form and the spectra of the partitions after it
+struct PreparedResponse {
+    head: Vec<f32>,
+    spectra: Vec<(Vec<f32>, Vec<f32>)>
+}
+
+/// Convolution state of one channel
+struct ConvolutionChannel {
+    /// The previous partition of input followed by the one being collected
+    window: Vec<f32>,
+    position: usize,
+    /// Spectra of the most recent input windows, one per partition of the impulse response, used as a ring buffer
+    input_spectra: Vec<(Vec<f32>, Vec<f32>)>,
+    spectrum_index: usize,
+    /// Output of the FFT part for the partition being collected
+    tail: Vec<f32>
+}
+
+/// Resample `samples` by `ratio` (source rate over destination rate) with cubic interpolation, scaled to keep the gain of the response the same.
+/// When downsampling, everything above the new Nyquist frequency is filtered out first so that it does not alias
+fn resample_response(samples: &[f32], ratio: f32) -> Vec<f32> {
+    //  The transition band sits just below the new Nyquist frequency
+    let filtered;
+    let samples = if ratio > 1.0 {
+        filtered = low_pass(samples, 0.45 / ratio as f64);
+        &filtered
+    } else {
+        samples
+    };
+
+    let sample = |index: isize| if index >= 0 { samples.get(index as usize).copied().unwrap_or(0.0) } else { 0.0 };
+    let length = (samples.len() as f32 / ratio).ceil() as usize;
+
+    (0..length).map(|index| {
+        let position = index as f32 * ratio;
+        let whole = position.floor() as isize;
+        ratio * interpolate_hermite(&[sample(whole - 1), sample(whole), sample(whole + 1), sample(whole + 2)], position - whole as f32)
+    }).collect()
+}
+
+/// Filter `samples` with a Blackman-windowed sinc low-pass at `cutoff` (a fraction of the sampling frequency) and unity gain at DC.
+/// The filter is centred on each sample, so the result is neither delayed nor longer than the input
+fn low_pass(samples: &[f32], cutoff: f64) -> Vec<f32> {
+    let half_length = (RESAMPLING_FILTER_ZERO_CROSSINGS as f64 / (2.0 * cutoff)).ceil() as isize;
+
+    let taps: Vec<f64> = (-half_length..=half_length).map(|offset| {
+        let x = offset as f64;
+        let sinc = if offset == 0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+        let phase = PI * (x / half_length as f64 + 1.0);
+        sinc * (0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos())
+    }).collect();
+    let dc_gain: f64 = taps.iter().sum();
+
+    (0..samples.len() as isize).map(|index| {
+        let sum: f64 = taps.iter().zip(-half_length..=half_length).map(|(tap, offset)| {
+            match samples.get((index - offset) as usize) {
+                Some(sample) if index >= offset => tap * *sample as f64,
+                _ => 0.0
+            }
+        }).sum();
+
+        (sum / dc_gain) as f32
+    }).collect()
+}
+
+
+/// Convolves its input with an impulse response of any length, with no added latency (see the module documentation).
+/// Takes as many channels as the impulse response has unless told otherwise; channel `c` is convolved with channel `c` of the response,
+/// wrapping around when the response has fewer channels, so a mono response applies to every channel.
+/// Responses loaded from a file are resampled to the graph's sampling frequency when the graph is prepared.
+///
+/// The impulse response cannot be stored in a patch, so the node has no type name.
+///
+/// Parameters, in order: `mix` (0 dry to 1 wet) and `gain_db` of the wet signal
+pub struct ConvolutionNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    buffer_size: usize,
+    response: Vec<Vec<f32>>,
+    response_sampling_freq: Option<f32>,
+    partition_size: usize,
+    mix: f32,
+    gain_db: f32,
+    fft: Fft,
+    prepared: Vec<PreparedResponse>,
+    channels: Vec<ConvolutionChannel>,
+    scratch: (Vec<f32>, Vec<f32>),
+    accumulator: (Vec<f32>, Vec<f32>)
+}
+
+impl ConvolutionNode {
+    /// Convolve with a mono impulse response, which is used as is at any sampling frequency
+    pub fn from_slice(response: &[f32]) -> ConvolutionNode {
+        ConvolutionNode::from_channels(vec![response.to_vec()])
+    }
+
+    /// Convolve with an impulse response of one or more channels, which is used as is at any sampling frequency
+    pub fn from_channels(response: Vec<Vec<f32>>) -> ConvolutionNode {
+        let response = if response.is_empty() { vec![vec![]] } else { response };
+
+        ConvolutionNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_channels: response.len(),
+            buffer_size: 0,
+            response,
+            response_sampling_freq: None,
+            partition_size: DEFAULT_PARTITION_SIZE,
+            mix: 1.0,
+            gain_db: 0.0,
+            fft: Fft::new(1),
+            prepared: vec![],
+            channels: vec![],
+            scratch: (vec![], vec![]),
+            accumulator: (vec![], vec![])
+        }
+    }
+
+    /// Load the impulse response from a WAV or AIFF file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ConvolutionNode, Error> {
+        let (response, info) = read_audio_file(&path)?;
+
+        if info.num_frames == 0 {
+            return Err(Error {
+                code: ErrorCodes::AudioFileInvalid,
+                message: format!("The impulse response {} is empty", path.as_ref().display())
+            });
+        }
+
+        let mut convolution_node = ConvolutionNode::from_channels(response);
+        convolution_node.response_sampling_freq = Some(info.sampling_freq);
+
+        Ok(convolution_node)
+    }
+
+    /// Change the number of channels convolved.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+    }
+
+    /// Change the length of the partitions, rounded up to a power of two.  Takes effect the next time the graph is prepared
+    pub fn set_partition_size(&mut self, partition_size: usize) {
+        self.partition_size = partition_size.max(1).next_power_of_two();
+    }
+
+    /// Get the length of the impulse response in samples, at its own sampling frequency
+    pub fn get_response_length(&self) -> usize {
+        self.response.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    /// Split a channel of the impulse response into its head and the spectra of the partitions after it
+    fn prepare_response(&self, response: &[f32]) -> PreparedResponse {
+        let partition_size = self.partition_size;
+        let head_length = response.len().min(partition_size);
+
+        let spectra = response[head_length..].chunks(partition_size).map(|partition| {
+            let mut real = vec![0.0; 2 * partition_size];
+            let mut imaginary = vec![0.0; 2 * partition_size];
+            real[..partition.len()].copy_from_slice(partition);
+            self.fft.transform(&mut real, &mut imaginary, false);
+            (real, imaginary)
+        }).collect();
+
+        PreparedResponse { head: response[..head_length].to_vec(), spectra }
+    }
+
+    /// Run the FFT part over the input window of `channel`, which has just been filled, leaving its output for the next partition in `tail`
+    fn convolve_partition(&mut self, channel: usize) {
+        let partition_size = self.partition_size;
+        let prepared = &self.prepared[channel % self.prepared.len()];
+        let state = &mut self.channels[channel];
+        let num_partitions = prepared.spectra.len();
+
+        let (input_real, input_imaginary) = &mut state.input_spectra[state.spectrum_index];
+        input_real.copy_from_slice(&state.window);
+        input_imaginary.iter_mut().for_each(|value| *value = 0.0);
+        self.fft.transform(input_real, input_imaginary, false);
+
+        //  The newest input spectrum meets the first partition after the head, the one before it the second partition, and so on
+        let (accumulator_real, accumulator_imaginary) = &mut self.accumulator;
+        accumulator_real.iter_mut().for_each(|value| *value = 0.0);
+        accumulator_imaginary.iter_mut().for_each(|value| *value = 0.0);
+        for (partition, (response_real, response_imaginary)) in prepared.spectra.iter().enumerate() {
+            let (input_real, input_imaginary) = &state.input_spectra[(state.spectrum_index + num_partitions - partition) % num_partitions];
+
+            for bin in 0..2 * partition_size {
+                accumulator_real[bin] += input_real[bin] * response_real[bin] - input_imaginary[bin] * response_imaginary[bin];
+                accumulator_imaginary[bin] += input_real[bin] * response_imaginary[bin] + input_imaginary[bin] * response_real[bin];
+            }
+        }
+        state.spectrum_index = (state.spectrum_index + 1) % num_partitions;
+
+        //  Overlap-save: the first half of the circular convolution wraps around, the second half is the output
+        let (scratch_real, scratch_imaginary) = &mut self.scratch;
+        scratch_real.copy_from_slice(accumulator_real);
+        scratch_imaginary.copy_from_slice(accumulator_imaginary);
+        self.fft.transform(scratch_real, scratch_imaginary, true);
+
+        let scale = 1.0 / (2 * partition_size) as f32;
+        for (tail, value) in state.tail.iter_mut().zip(&scratch_real[partition_size..]) {
+            *tail = value * scale;
+        }
+    }
+}
+
+impl AudioNode for ConvolutionNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+
+        let partition_size = self.partition_size;
+        self.fft = Fft::new(2 * partition_size);
+
+        let ratio = self.response_sampling_freq.map_or(1.0, |sampling_freq| sampling_freq / audio_runtime_params.sampling_freq);
+        self.prepared = self.response.iter().map(|response| {
+            if ratio == 1.0 {
+                self.prepare_response(response)
+            } else {
+                self.prepare_response(&resample_response(response, ratio))
+            }
+        }).collect();
+
+        self.channels = (0..self.num_channels).map(|channel| {
+            let num_partitions = self.prepared[channel % self.prepared.len()].spectra.len();
+
+            ConvolutionChannel {
+                window: vec![0.0; 2 * partition_size],
+                position: 0,
+                input_spectra: vec![(vec![0.0; 2 * partition_size], vec![0.0; 2 * partition_size]); num_partitions],
+                spectrum_index: 0,
+                tail: vec![0.0; partition_size]
+            }
+        }).collect();
+
+        self.scratch = (vec![0.0; 2 * partition_size], vec![0.0; 2 * partition_size]);
+        self.accumulator = (vec![0.0; 2 * partition_size], vec![0.0; 2 * partition_size]);
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(mix) = parameters.first() { self.mix = mix.clamp(0.0, 1.0); }
+        if let Some(gain_db) = parameters.get(1) { self.gain_db = *gain_db; }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.mix, self.gain_db]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &CONVOLUTION_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        for state in &mut self.channels {
+            state.window.iter_mut().for_each(|sample| *sample = 0.0);
+            state.tail.iter_mut().for_each(|sample| *sample = 0.0);
+            for (real, imaginary) in &mut state.input_spectra {
+                real.iter_mut().for_each(|value| *value = 0.0);
+                imaginary.iter_mut().for_each(|value| *value = 0.0);
+            }
+            state.position = 0;
+            state.spectrum_index = 0;
+        }
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let partition_size = self.partition_size;
+        let wet = self.mix * 10.0f32.powf(self.gain_db / 20.0);
+
+        for channel in 0..self.channels.len() {
+            let has_tail = !self.prepared[channel % self.prepared.len()].spectra.is_empty();
+
+            for index in 0..buffer_size {
+                let sample = &mut buffer[channel * buffer_size + index];
+                let head = &self.prepared[channel % self.prepared.len()].head;
+                let state = &mut self.channels[channel];
+
+                let position = state.position;
+                state.window[partition_size + position] = *sample;
+
+                let mut output = state.tail[position];
+                for (tap, input) in head.iter().zip(state.window[position + 1..=partition_size + position].iter().rev()) {
+                    output += tap * input;
+                }
+
+                *sample = (1.0 - self.mix) * *sample + wet * output;
+
+                state.position += 1;
+                if state.position == partition_size {
+                    if has_tail {
+                        self.convolve_partition(channel);
+                    }
+
+                    let state = &mut self.channels[channel];
+                    state.window.copy_within(partition_size.., 0);
+                    state.position = 0;
+                }
+            }
+        }
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioFile::{SampleFormat, WavWriter};
+
+    fn convolve_directly(input: &[f32], response: &[f32]) -> Vec<f32> {
+        (0..input.len()).map(|index| {
+            response.iter().enumerate().take(index + 1).map(|(tap, value)| value * input[index - tap]).sum()
+        }).collect()
+    }
+
+    #[test]
+    fn convolve_in_partitions() {
+        //  Responses spanning the head and several partitions, processed in blocks smaller than a partition
+        let left: Vec<f32> = (0..1_000).map(|index| (index as f32 * 0.37).sin() * (-(index as f32) / 300.0).exp()).collect();
+        let right: Vec<f32> = (0..150).map(|index| (index as f32 * 1.3).cos() / (index + 1) as f32).collect();
+        let input: Vec<f32> = (0..1_200).map(|index| ((index * 7_919) % 101) as f32 / 50.0 - 1.0).collect();
+
+        let mut convolution = ConvolutionNode::from_channels(vec![left.clone(), right.clone()]);
+        convolution.set_partition_size(64);
+        convolution.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 40 });
+        assert_eq!(convolution.get_number_of_output_channels(), 2);
+        assert_eq!(convolution.latency_samples(), 0);
+
+        let mut output = vec![vec![]; 2];
+        for block in input.chunks(40) {
+            let mut buffer = [block, block].concat();
+            convolution.process_block(&mut buffer);
+            output[0].extend_from_slice(&buffer[..40]);
+            output[1].extend_from_slice(&buffer[40..]);
+        }
+
+        for (channel, response) in [left, right].iter().enumerate() {
+            for (sample, expected) in output[channel].iter().zip(convolve_directly(&input, response)) {
+                assert!((sample - expected).abs() < 1e-4);
+            }
+        }
+
+        //  Half wet at -6 dB with a unit impulse: a quarter of the input plus half of it
+        let mut convolution = ConvolutionNode::from_slice(&[1.0]);
+        convolution.change_parameters(&[0.5, -6.0206]);
+        convolution.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 4 });
+        let mut buffer = [1.0, -2.0, 0.0, 4.0];
+        convolution.process_block(&mut buffer);
+        for (sample, expected) in buffer.iter().zip([0.75, -1.5, 0.0, 3.0]) {
+            assert!((sample - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn resample_response_without_aliasing() {
+        let tone = |frequency: f32| (0..4_000).map(|index| (2.0 * std::f32::consts::PI * frequency * index as f32).sin()).collect::<Vec<f32>>();
+        let peak = |samples: &[f32]| samples[500..1_500].iter().fold(0.0, |peak: f32, sample| sample.abs().max(peak));
+
+        //  Halving the rate keeps a tone well below the new Nyquist frequency, scaled by the ratio like the rest of the response
+        assert!((peak(&resample_response(&tone(0.0625), 2.0)) - 2.0).abs() < 0.02);
+
+        //  A tone above the new Nyquist frequency is removed instead of folding back down
+        assert!(peak(&resample_response(&tone(0.4), 2.0)) < 0.01);
+    }
+
+    #[test]
+    fn load_impulse_response_from_file() {
+        let path = std::env::temp_dir().join("audio_graph_load_impulse_response.wav");
+
+        //  100 samples of 0.5 at 24 kHz, a response with a gain of 50 at DC
+        let mut writer = WavWriter::create(&path, 1, 24_000.0, SampleFormat::Float32).ok().unwrap();
+        if writer.write_interleaved(&[0.5; 100]).is_err() { panic!(); }
+        if writer.finalize().is_err() { panic!(); }
+
+        let mut convolution = match ConvolutionNode::open(&path) {
+            Ok(convolution) => convolution,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        assert_eq!(convolution.get_response_length(), 100);
+
+        //  At 48 kHz the response is twice as long and half as loud, which keeps its gain
+        convolution.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 512 });
+        let mut buffer = [1.0; 512];
+        convolution.process_block(&mut buffer);
+        assert!((buffer[500] - 50.0).abs() < 0.5);
+
+        match ConvolutionNode::open(std::env::temp_dir().join("audio_graph_missing_impulse_response.wav")) {
+            Ok(_) => panic!(),
+            Err(e) => assert_eq!(e.code, ErrorCodes::FileAccessFailed)
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! # Delays
+//! Delay effects built on a circular delay line whose memory is allocated when the graph is prepared, so processing never allocates.
+//!
+//! The delay time is given in milliseconds or as a note value at a tempo, and glides to new values instead of jumping, like a tape delay.
+//! Reading between samples is needed whenever the time is modulated or moving; pick the interpolation by how the delay is used (see `DelayInterpolation`).
+//!
+//! ```
+//! use audio_graph::AudioToolbox::{AudioNode, AudioRuntimeParameters};
+//! use audio_graph::Delays::{DelayNode, DelayTime, NoteModifier, NoteValue};
+//!
+//! //  Dotted eighth echoes at 120 BPM, bouncing between the two channels
+//! let mut delay = DelayNode::new(DelayTime::Synced { tempo: 120.0, note: NoteValue::Eighth, modifier: NoteModifier::Dotted }, 0.4, 0.3);
+//! delay.set_number_of_channels(2);
+//! delay.set_ping_pong(true);
+//! delay.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 });
+//!
+//! assert_eq!(delay.get_time().to_milliseconds(), 375.0);
+//! ```
+
+use std::f64::consts::PI;
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters};
+use super::Generators::interpolate_hermite;
+
+/// Longest delay time, in milliseconds, of a `DelayNode` unless told otherwise
+const DEFAULT_MAX_DELAY: f32 = 2_000.0;
+
+/// Shortest delay in samples.  Cubic interpolation reads one sample on either side of the pair it interpolates between
+const MIN_DELAY_SAMPLES: f32 = 2.0;
+
+/// Time constant with which the delay time follows parameter changes
+const DELAY_TIME_SMOOTHING: f32 = 0.05;
+
+/// Largest amount of feedback, which keeps the echoes from building up forever
+const MAX_FEEDBACK: f32 = 0.99;
+
+const DELAY_PARAMETER_NAMES: [&str; 12] = ["time", "feedback", "mix", "sync", "tempo", "note", "note_modifier", "interpolation", "ping_pong", "mod_rate", "mod_depth", "max_delay"];
+
+
+/// Note lengths for tempo-synced delay times.  Stored in the `note` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoteValue {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    ThirtySecond
+}
+
+impl NoteValue {
+    fn from_parameter(value: f32) -> NoteValue {
+        match value.round() as i32 {
+            0 => NoteValue::Whole,
+            1 => NoteValue::Half,
+            3 => NoteValue::Eighth,
+            4 => NoteValue::Sixteenth,
+            5 => NoteValue::ThirtySecond,
+            _ => NoteValue::Quarter
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            NoteValue::Whole => 0.0,
+            NoteValue::Half => 1.0,
+            NoteValue::Quarter => 2.0,
+            NoteValue::Eighth => 3.0,
+            NoteValue::Sixteenth => 4.0,
+            NoteValue::ThirtySecond => 5.0
+        }
+    }
+
+    /// Length in beats (quarter notes)
+    fn get_beats(self) -> f32 {
+        match self {
+            NoteValue::Whole => 4.0,
+            NoteValue::Half => 2.0,
+            NoteValue::Quarter => 1.0,
+            NoteValue::Eighth => 0.5,
+            NoteValue::Sixteenth => 0.25,
+            NoteValue::ThirtySecond => 0.125
+        }
+    }
+}
+
+/// Modifiers of a `NoteValue`.  Stored in the `note_modifier` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoteModifier {
+    Straight,
+    /// One and a half times as long
+    Dotted,
+    /// Two thirds as long
+    Triplet
+}
+
+impl NoteModifier {
+    fn from_parameter(value: f32) -> NoteModifier {
+        match value.round() as i32 {
+            1 => NoteModifier::Dotted,
+            2 => NoteModifier::Triplet,
+            _ => NoteModifier::Straight
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            NoteModifier::Straight => 0.0,
+            NoteModifier::Dotted => 1.0,
+            NoteModifier::Triplet => 2.0
+        }
+    }
+
+    fn get_factor(self) -> f32 {
+        match self {
+            NoteModifier::Straight => 1.0,
+            NoteModifier::Dotted => 1.5,
+            NoteModifier::Triplet => 2.0 / 3.0
+        }
+    }
+}
+
+/// Delay time of a `DelayNode`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DelayTime {
+    Milliseconds(f32),
+    /// A note value at `tempo` beats per minute
+    Synced { tempo: f32, note: NoteValue, modifier: NoteModifier }
+}
+
+impl DelayTime {
+    pub fn to_milliseconds(&self) -> f32 {
+        match *self {
+            DelayTime::Milliseconds(time) => time,
+            DelayTime::Synced { tempo, note, modifier } => 60_000.0 / tempo.max(1.0) * note.get_beats() * modifier.get_factor()
+        }
+    }
+}
+
+/// How a `DelayNode` reads between samples.  Stored in the `interpolation` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DelayInterpolation {
+    /// Cheapest, but dulls the highs when the delay sits between samples
+    Linear,
+    /// 4-point Hermite.  Keeps more of the highs; the default
+    Cubic,
+    /// First order all-pass (Thiran).  Flat magnitude response, which suits delays inside feedback loops, but smears fast modulation
+    AllPass
+}
+
+impl DelayInterpolation {
+    fn from_parameter(value: f32) -> DelayInterpolation {
+        match value.round() as i32 {
+            0 => DelayInterpolation::Linear,
+            2 => DelayInterpolation::AllPass,
+            _ => DelayInterpolation::Cubic
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            DelayInterpolation::Linear => 0.0,
+            DelayInterpolation::Cubic => 1.0,
+            DelayInterpolation::AllPass => 2.0
+        }
+    }
+}
+
+
+/// Delay with feedback and a wet/dry mix, processing any number of channels with the same settings.
+/// The delay time can be modulated by a sine LFO (`mod_rate` in Hz, `mod_depth` in ms) for chorus, flanger and tape wobble effects.
+/// With two channels and ping-pong on, both channels are fed into the first delay line and the feedback crosses over, so the echoes alternate between the channels.
+///
+/// The delay line is allocated in `init()` to hold `max_delay` milliseconds; longer times are clamped to it.
+///
+/// Parameters, in order: `time` in ms, `feedback` (-0.99 to 0.99), `mix` (0 dry to 1 wet), `sync` (1 to use the note value instead of `time`), `tempo` in BPM,
+/// `note` (index of `NoteValue`), `note_modifier` (index of `NoteModifier`), `interpolation` (index of `DelayInterpolation`), `ping_pong`, `mod_rate`, `mod_depth`
+/// and `max_delay` in ms (takes effect the next time the graph is prepared)
+pub struct DelayNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    sampling_freq: f32,
+    buffer_size: usize,
+    time: f32,
+    sync: bool,
+    tempo: f32,
+    note: NoteValue,
+    note_modifier: NoteModifier,
+    feedback: f32,
+    mix: f32,
+    interpolation: DelayInterpolation,
+    ping_pong: bool,
+    mod_rate: f32,
+    mod_depth: f32,
+    max_delay: f32,
+    lines: Vec<Vec<f32>>,
+    mask: usize,
+    write_position: usize,
+    delay_samples: f32,
+    smoothing_coefficient: f32,
+    lfo_phase: f64,
+    allpass_states: Vec<f32>,
+    wet: Vec<f32>
+}
+
+impl DelayNode {
+    pub fn new(time: DelayTime, feedback: f32, mix: f32) -> DelayNode {
+        let mut delay_node = DelayNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_channels: 1,
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            time: 0.0,
+            sync: false,
+            tempo: 120.0,
+            note: NoteValue::Quarter,
+            note_modifier: NoteModifier::Straight,
+            feedback: feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK),
+            mix: mix.clamp(0.0, 1.0),
+            interpolation: DelayInterpolation::Cubic,
+            ping_pong: false,
+            mod_rate: 0.0,
+            mod_depth: 0.0,
+            max_delay: DEFAULT_MAX_DELAY,
+            lines: vec![],
+            mask: 0,
+            write_position: 0,
+            delay_samples: MIN_DELAY_SAMPLES,
+            smoothing_coefficient: 0.0,
+            lfo_phase: 0.0,
+            allpass_states: vec![0.0],
+            wet: vec![0.0]
+        };
+        delay_node.set_time(time);
+        delay_node.max_delay = delay_node.max_delay.max(time.to_milliseconds());
+
+        delay_node
+    }
+
+    /// Change the number of channels delayed.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+        self.allpass_states = vec![0.0; self.num_channels];
+        self.wet = vec![0.0; self.num_channels];
+    }
+
+    /// Change the longest delay time, in milliseconds.  Takes effect the next time the graph is prepared
+    pub fn set_max_delay(&mut self, max_delay: f32) {
+        self.max_delay = max_delay.max(0.0);
+    }
+
+    pub fn set_time(&mut self, time: DelayTime) {
+        match time {
+            DelayTime::Milliseconds(time) => {
+                self.time = time.max(0.0);
+                self.sync = false;
+            },
+            DelayTime::Synced { tempo, note, modifier } => {
+                self.tempo = tempo;
+                self.note = note;
+                self.note_modifier = modifier;
+                self.sync = true;
+            }
+        }
+    }
+
+    pub fn get_time(&self) -> DelayTime {
+        if self.sync {
+            DelayTime::Synced { tempo: self.tempo, note: self.note, modifier: self.note_modifier }
+        } else {
+            DelayTime::Milliseconds(self.time)
+        }
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: DelayInterpolation) {
+        self.interpolation = interpolation;
+    }
+
+    /// Bounce the echoes between the channels.  Only has an effect with two channels
+    pub fn set_ping_pong(&mut self, ping_pong: bool) {
+        self.ping_pong = ping_pong;
+    }
+
+    fn get_max_delay_samples(&self) -> f32 {
+        ((self.lines.first().map_or(0, Vec::len) as f32) - MIN_DELAY_SAMPLES - 1.0).max(MIN_DELAY_SAMPLES)
+    }
+
+    fn get_target_delay_samples(&self) -> f32 {
+        (self.get_time().to_milliseconds() * 0.001 * self.sampling_freq).clamp(MIN_DELAY_SAMPLES, self.get_max_delay_samples())
+    }
+
+    /// Read `delay` samples back from the sample about to be written to the line of `channel`
+    fn read(&mut self, channel: usize, delay: f32) -> f32 {
+        let line = &self.lines[channel];
+        let tap = |samples_back: usize| line[self.write_position.wrapping_sub(samples_back) & self.mask];
+
+        let whole = delay.floor();
+        let mut fraction = delay - whole;
+        let mut samples_back = whole as usize;
+
+        match self.interpolation {
+            DelayInterpolation::Linear => tap(samples_back) + fraction * (tap(samples_back + 1) - tap(samples_back)),
+            DelayInterpolation::Cubic => {
+                interpolate_hermite(&[tap(samples_back - 1), tap(samples_back), tap(samples_back + 1), tap(samples_back + 2)], fraction)
+            },
+            DelayInterpolation::AllPass => {
+                //  The all-pass approximates fractional delays between 0.5 and 1.5 samples best
+                if fraction < 0.5 {
+                    samples_back -= 1;
+                    fraction += 1.0;
+                }
+
+                let coefficient = (1.0 - fraction) / (1.0 + fraction);
+                let state = &mut self.allpass_states[channel];
+                *state = coefficient * (tap(samples_back) - *state) + tap(samples_back + 1);
+                *state
+            }
+        }
+    }
+}
+
+impl AudioNode for DelayNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.smoothing_coefficient = (-1.0 / (DELAY_TIME_SMOOTHING * self.sampling_freq)).exp();
+
+        //  Room for the longest delay plus the samples the interpolation reads around it
+        let length = ((self.max_delay * 0.001 * self.sampling_freq).ceil() as usize + MIN_DELAY_SAMPLES as usize + 2).next_power_of_two();
+        self.lines = vec![vec![0.0; length]; self.num_channels];
+        self.mask = length - 1;
+
+        self.reset();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("delay")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(time) = parameters.first() { self.time = time.max(0.0); }
+        if let Some(feedback) = parameters.get(1) { self.feedback = feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK); }
+        if let Some(mix) = parameters.get(2) { self.mix = mix.clamp(0.0, 1.0); }
+        if let Some(sync) = parameters.get(3) { self.sync = *sync >= 0.5; }
+        if let Some(tempo) = parameters.get(4) { self.tempo = tempo.max(1.0); }
+        if let Some(note) = parameters.get(5) { self.note = NoteValue::from_parameter(*note); }
+        if let Some(note_modifier) = parameters.get(6) { self.note_modifier = NoteModifier::from_parameter(*note_modifier); }
+        if let Some(interpolation) = parameters.get(7) { self.interpolation = DelayInterpolation::from_parameter(*interpolation); }
+        if let Some(ping_pong) = parameters.get(8) { self.ping_pong = *ping_pong >= 0.5; }
+        if let Some(mod_rate) = parameters.get(9) { self.mod_rate = mod_rate.max(0.0); }
+        if let Some(mod_depth) = parameters.get(10) { self.mod_depth = mod_depth.max(0.0); }
+        if let Some(max_delay) = parameters.get(11) { self.set_max_delay(*max_delay); }
+
+        //  Before the first block there is nothing to glide from
+        if self.buffer_size == 0 {
+            self.delay_samples = self.get_target_delay_samples();
+        }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![
+            self.time,
+            self.feedback,
+            self.mix,
+            if self.sync { 1.0 } else { 0.0 },
+            self.tempo,
+            self.note.to_parameter(),
+            self.note_modifier.to_parameter(),
+            self.interpolation.to_parameter(),
+            if self.ping_pong { 1.0 } else { 0.0 },
+            self.mod_rate,
+            self.mod_depth,
+            self.max_delay
+        ]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &DELAY_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        for line in &mut self.lines {
+            line.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        self.allpass_states.iter_mut().for_each(|state| *state = 0.0);
+        self.write_position = 0;
+        self.lfo_phase = 0.0;
+        self.delay_samples = self.get_target_delay_samples();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        if self.lines.is_empty() {
+            return buffer;
+        }
+
+        let buffer_size = self.buffer_size;
+        let target_delay = self.get_target_delay_samples();
+        let max_delay = self.get_max_delay_samples();
+        let mod_depth = self.mod_depth * 0.001 * self.sampling_freq;
+        let lfo_increment = 2.0 * PI * self.mod_rate as f64 / self.sampling_freq as f64;
+        let ping_pong = self.ping_pong && self.num_channels == 2;
+
+        for index in 0..buffer_size {
+            self.delay_samples = target_delay + (self.delay_samples - target_delay) * self.smoothing_coefficient;
+
+            let mut delay = self.delay_samples;
+            if mod_depth > 0.0 {
+                delay += mod_depth * self.lfo_phase.sin() as f32;
+                self.lfo_phase = (self.lfo_phase + lfo_increment) % (2.0 * PI);
+            }
+            let delay = delay.clamp(MIN_DELAY_SAMPLES, max_delay);
+
+            for channel in 0..self.num_channels {
+                self.wet[channel] = self.read(channel, delay);
+            }
+
+            if ping_pong {
+                let input = 0.5 * (buffer[index] + buffer[buffer_size + index]);
+                self.lines[0][self.write_position] = input + self.feedback * self.wet[1];
+                self.lines[1][self.write_position] = self.feedback * self.wet[0];
+            } else {
+                for channel in 0..self.num_channels {
+                    self.lines[channel][self.write_position] = buffer[channel * buffer_size + index] + self.feedback * self.wet[channel];
+                }
+            }
+
+            for channel in 0..self.num_channels {
+                let sample = &mut buffer[channel * buffer_size + index];
+                *sample = (1.0 - self.mix) * *sample + self.mix * self.wet[channel];
+            }
+
+            self.write_position = (self.write_position + 1) & self.mask;
+        }
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(delay: &mut DelayNode, buffer: &mut [f32]) {
+        delay.init(&AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: buffer.len() / delay.get_number_of_output_channels() });
+        delay.process_block(buffer);
+    }
+
+    #[test]
+    fn echo_with_feedback() {
+        //  10 ms at 1 kHz, half feedback, fully wet
+        let mut delay = DelayNode::new(DelayTime::Milliseconds(10.0), 0.5, 1.0);
+        let mut buffer = vec![0.0; 40];
+        buffer[0] = 1.0;
+        run(&mut delay, &mut buffer);
+        for (index, sample) in buffer.iter().enumerate() {
+            let expected = match index { 10 => 1.0, 20 => 0.5, 30 => 0.25, _ => 0.0 };
+            assert!((sample - expected).abs() < 1e-6);
+        }
+
+        //  Every interpolation puts a delay of 10.5 samples halfway between the samples around it
+        for interpolation in [DelayInterpolation::Linear, DelayInterpolation::Cubic, DelayInterpolation::AllPass] {
+            let mut delay = DelayNode::new(DelayTime::Milliseconds(10.5), 0.0, 1.0);
+            delay.set_interpolation(interpolation);
+            let mut buffer: Vec<f32> = (0..40).map(|index| index as f32).collect();
+            run(&mut delay, &mut buffer);
+            assert!((buffer[35] - 24.5).abs() < 1e-3);
+        }
+
+        //  Times past the allocation are clamped, and the parameters round-trip
+        let mut delay = DelayNode::new(DelayTime::Milliseconds(10.0), 0.0, 1.0);
+        delay.change_parameters(&[50.0, 0.0, 1.0, 0.0, 120.0, 2.0, 0.0, 1.0, 0.0, 0.0, 0.0, 20.0]);
+        let mut buffer = vec![0.0; 40];
+        buffer[0] = 1.0;
+        run(&mut delay, &mut buffer);
+        assert!(buffer[..20].iter().all(|sample| *sample == 0.0));
+        assert!(buffer[20..].iter().any(|sample| *sample != 0.0));
+        assert_eq!(delay.get_parameters(), vec![50.0, 0.0, 1.0, 0.0, 120.0, 2.0, 0.0, 1.0, 0.0, 0.0, 0.0, 20.0]);
+    }
+
+    #[test]
+    fn ping_pong_between_channels() {
+        //  A sixteenth note at 1500 BPM is 10 ms
+        let mut delay = DelayNode::new(DelayTime::Synced { tempo: 1_500.0, note: NoteValue::Sixteenth, modifier: NoteModifier::Straight }, 0.5, 1.0);
+        delay.set_number_of_channels(2);
+        delay.set_ping_pong(true);
+
+        let mut buffer = vec![0.0; 80];
+        buffer[0] = 1.0;
+        buffer[40] = 1.0;
+        run(&mut delay, &mut buffer);
+
+        let (left, right) = buffer.split_at(40);
+        for index in 0..40 {
+            let (expected_left, expected_right) = match index { 10 => (1.0, 0.0), 20 => (0.0, 0.5), 30 => (0.25, 0.0), _ => (0.0, 0.0) };
+            assert!((left[index] - expected_left).abs() < 1e-6);
+            assert!((right[index] - expected_right).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,691 @@
+//! # Dynamics
+//! Nodes that control the level of a signal: compressors, limiters, expanders and gates.
+//!
+//! `DynamicsNode` is a feed-forward processor with a soft-knee gain computer and a smooth-branching detector (Giannoulis, Massberg and Reiss,
+//! "Digital Dynamic Range Compressor Design", 2012).  The level can be taken from the node's own input or from a sidechain signal on input port 1,
+//! and the audio can be delayed by a lookahead so the gain reacts before transients arrive.
+//!
+//! `TruePeakLimiterNode` is a brickwall limiter for the master output.  It estimates the peaks between samples by oversampling, as in ITU-R BS.1770,
+//! so the signal stays below the ceiling after digital to analogue conversion or lossy encoding.
+//!
+//! Both report their lookahead as latency, so the graph keeps parallel branches aligned with them.
+//!
+//! ```
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::Dynamics::{DynamicsMode, DynamicsNode, DYNAMICS_SIDECHAIN_INPUT};
+//! use audio_graph::Generators::{NoiseColor, NoiseNode, OscillatorNode, Waveform};
+//!
+//! //  Duck [noise] whenever [osc] plays: [noise] -> [compressor] <- sidechain [osc], [compressor] -> [Output]
+//! let mut graph = AudioGraph::new();
+//! let noise_id = graph.add_new_node(Box::new(NoiseNode::new(NoiseColor::Pink, 1)))?;
+//! let osc_id = graph.add_new_node(Box::new(OscillatorNode::new(Waveform::Sine, 60.0)))?;
+//! let compressor_id = graph.add_new_node(Box::new(DynamicsNode::new(DynamicsMode::Compressor, -30.0, 8.0)))?;
+//! graph.connect_node(noise_id, compressor_id, 0)?;
+//! graph.connect_node(osc_id, compressor_id, DYNAMICS_SIDECHAIN_INPUT)?;
+//! graph.connect_node_to_output(compressor_id)?;
+//!
+//! //  Compressor, -30 dB, 8:1, 6 dB knee, 5 ms attack, 150 ms release, no make-up gain, 80 dB range, level from the sidechain
+//! graph.set_node_parameters(compressor_id, &[0.0, -30.0, 8.0, 6.0, 5.0, 150.0, 0.0, 80.0, 1.0])?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 })?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use std::f64::consts::PI;
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters};
+
+/// Input port of `DynamicsNode` taking the sidechain signal.  It has as many channels as the main input, placed after them
+pub const DYNAMICS_SIDECHAIN_INPUT: usize = 1;
+
+/// Levels at or below this many dB are treated as silence
+pub(crate) const SILENCE_DB: f32 = -144.0;
+
+/// Taps on either side of the interpolated point of the true-peak detector's interpolation filter
+const TRUE_PEAK_HALF_TAPS: usize = 4;
+
+/// Oversampling of the true-peak detector
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+
+const DYNAMICS_PARAMETER_NAMES: [&str; 10] = ["mode", "threshold", "ratio", "knee", "attack", "release", "makeup", "range", "sidechain", "lookahead"];
+
+const TRUE_PEAK_LIMITER_PARAMETER_NAMES: [&str; 3] = ["ceiling", "release", "lookahead"];
+
+
+pub(crate) fn db_to_linear(value: f32) -> f32 {
+    if value > SILENCE_DB {
+        10.0f32.powf(value / 20.0)
+    } else {
+        0.0
+    }
+}
+
+pub(crate) fn linear_to_db(value: f32) -> f32 {
+    if value > 0.0 {
+        (20.0 * value.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+/// Coefficient of a one-pole smoother that covers about two thirds of a step in `time` milliseconds
+fn time_coefficient(time: f32, sampling_freq: f32) -> f32 {
+    if time > 0.0 {
+        (-1.0 / (time * 0.001 * sampling_freq)).exp()
+    } else {
+        0.0
+    }
+}
+
+
+/// What `DynamicsNode` does to the level.  Stored in the `mode` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DynamicsMode {
+    /// Turn the level down above the threshold, by `ratio`
+    Compressor,
+    /// Keep the level at the threshold.  A compressor with an infinite ratio; `ratio` is ignored
+    Limiter,
+    /// Turn the level down below the threshold, by `ratio`
+    Expander,
+    /// Turn the signal down by the whole range below the threshold.  An expander with an infinite ratio; `ratio` is ignored
+    Gate
+}
+
+impl DynamicsMode {
+    fn from_parameter(value: f32) -> DynamicsMode {
+        match value.round() as i32 {
+            1 => DynamicsMode::Limiter,
+            2 => DynamicsMode::Expander,
+            3 => DynamicsMode::Gate,
+            _ => DynamicsMode::Compressor
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            DynamicsMode::Compressor => 0.0,
+            DynamicsMode::Limiter => 1.0,
+            DynamicsMode::Expander => 2.0,
+            DynamicsMode::Gate => 3.0
+        }
+    }
+}
+
+
+/// Compressor, limiter, expander or gate (see `DynamicsMode`), processing any number of channels with the same gain.
+/// The level is the peak across all channels of either the main input or, with `sidechain` on, the sidechain input (`DYNAMICS_SIDECHAIN_INPUT`).
+/// Attack is how quickly the gain moves away from unity once the level crosses the threshold (turning down for compressors, opening up for gates),
+/// release how quickly it comes back.
+///
+/// Parameters, in order: `mode` (index of `DynamicsMode`), `threshold` in dB, `ratio` (e.g. 4 for 4:1), `knee` width in dB, `attack` and `release` in ms,
+/// `makeup` gain in dB, `range` (largest attenuation of expanders and gates, in dB), `sidechain` (1 to take the level from the sidechain input)
+/// and `lookahead` in ms (takes effect the next time the graph is prepared)
+pub struct DynamicsNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    sampling_freq: f32,
+    buffer_size: usize,
+    mode: DynamicsMode,
+    threshold: f32,
+    ratio: f32,
+    knee: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    range: f32,
+    sidechain: bool,
+    lookahead: f32,
+    lookahead_samples: usize,
+    lookahead_lines: Vec<Vec<f32>>,
+    lookahead_position: usize,
+    gain_reduction: f32
+}
+
+impl DynamicsNode {
+    pub fn new(mode: DynamicsMode, threshold: f32, ratio: f32) -> DynamicsNode {
+        DynamicsNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 2,
+            next_available_input: 0,
+            num_channels: 1,
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            mode,
+            threshold,
+            ratio: ratio.max(1.0),
+            knee: 0.0,
+            attack: 5.0,
+            release: 100.0,
+            makeup: 0.0,
+            range: 80.0,
+            sidechain: false,
+            lookahead: 0.0,
+            lookahead_samples: 0,
+            lookahead_lines: vec![],
+            lookahead_position: 0,
+            gain_reduction: 0.0
+        }
+    }
+
+    /// Change the number of channels processed, of both the main and the sidechain input.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+    }
+
+    /// Get the current gain reduction in dB (0 or negative), without the make-up gain.  For metering
+    pub fn get_gain_reduction(&self) -> f32 {
+        self.gain_reduction
+    }
+
+    /// Static curve: the gain change in dB for an input at `level` dB
+    fn compute_gain(&self, level: f32) -> f32 {
+        let half_knee = 0.5 * self.knee;
+        let over = level - self.threshold;
+
+        match self.mode {
+            DynamicsMode::Compressor | DynamicsMode::Limiter => {
+                let slope = if self.mode == DynamicsMode::Limiter { 1.0 } else { 1.0 - 1.0 / self.ratio };
+                if over <= -half_knee {
+                    0.0
+                } else if over < half_knee {
+                    -slope * (over + half_knee).powi(2) / (2.0 * self.knee)
+                } else {
+                    -slope * over
+                }
+            },
+            DynamicsMode::Expander | DynamicsMode::Gate => {
+                let gain = if over >= half_knee {
+                    0.0
+                } else if self.mode == DynamicsMode::Gate {
+                    -self.range
+                } else if over > -half_knee {
+                    -(self.ratio - 1.0) * (over - half_knee).powi(2) / (2.0 * self.knee)
+                } else {
+                    (self.ratio - 1.0) * over
+                };
+
+                gain.max(-self.range)
+            }
+        }
+    }
+}
+
+impl AudioNode for DynamicsNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+
+        self.lookahead_samples = (self.lookahead * 0.001 * self.sampling_freq).round() as usize;
+        self.lookahead_lines = vec![vec![0.0; self.lookahead_samples]; self.num_channels];
+        self.reset();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("dynamics")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_input_port_channel(&self, port: usize) -> usize {
+        port * self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn latency_samples(&self) -> usize {
+        self.lookahead_samples
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(mode) = parameters.first() { self.mode = DynamicsMode::from_parameter(*mode); }
+        if let Some(threshold) = parameters.get(1) { self.threshold = *threshold; }
+        if let Some(ratio) = parameters.get(2) { self.ratio = ratio.max(1.0); }
+        if let Some(knee) = parameters.get(3) { self.knee = knee.max(0.0); }
+        if let Some(attack) = parameters.get(4) { self.attack = attack.max(0.0); }
+        if let Some(release) = parameters.get(5) { self.release = release.max(0.0); }
+        if let Some(makeup) = parameters.get(6) { self.makeup = *makeup; }
+        if let Some(range) = parameters.get(7) { self.range = range.max(0.0); }
+        if let Some(sidechain) = parameters.get(8) { self.sidechain = *sidechain >= 0.5; }
+        if let Some(lookahead) = parameters.get(9) { self.lookahead = lookahead.max(0.0); }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![
+            self.mode.to_parameter(),
+            self.threshold,
+            self.ratio,
+            self.knee,
+            self.attack,
+            self.release,
+            self.makeup,
+            self.range,
+            if self.sidechain { 1.0 } else { 0.0 },
+            self.lookahead
+        ]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &DYNAMICS_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        for line in &mut self.lookahead_lines {
+            line.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        self.lookahead_position = 0;
+        self.gain_reduction = 0.0;
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let attack = time_coefficient(self.attack, self.sampling_freq);
+        let release = time_coefficient(self.release, self.sampling_freq);
+        let detector_offset = if self.sidechain { self.num_channels * buffer_size } else { 0 };
+        let turns_down_when_loud = matches!(self.mode, DynamicsMode::Compressor | DynamicsMode::Limiter);
+
+        for index in 0..buffer_size {
+            let mut peak = 0.0f32;
+            for channel in 0..self.num_channels {
+                peak = peak.max(buffer[detector_offset + channel * buffer_size + index].abs());
+            }
+
+            //  Smooth the gain rather than the level, so attack and release keep their meaning whatever the ratio
+            let target = self.compute_gain(linear_to_db(peak));
+            let attacking = (target < self.gain_reduction) == turns_down_when_loud;
+            let coefficient = if attacking { attack } else { release };
+            self.gain_reduction = target + (self.gain_reduction - target) * coefficient;
+
+            let gain = db_to_linear(self.gain_reduction + self.makeup);
+            for channel in 0..self.num_channels {
+                let sample = &mut buffer[channel * buffer_size + index];
+
+                if self.lookahead_samples > 0 {
+                    let delayed = &mut self.lookahead_lines[channel][self.lookahead_position];
+                    std::mem::swap(sample, delayed);
+                }
+
+                *sample *= gain;
+            }
+
+            if self.lookahead_samples > 0 {
+                self.lookahead_position = (self.lookahead_position + 1) % self.lookahead_samples;
+            }
+        }
+
+        buffer
+    }
+}
+
+
+/// Brickwall limiter that keeps the true peak level of the output below `ceiling`, processing any number of channels with the same gain.
+///
+/// The detector estimates the signal between samples with a 4 times oversampling interpolation filter.  Over the lookahead the required gain is held
+/// at its minimum and then averaged, so the gain ramps down smoothly and reaches its lowest just as the peak passes; after that it recovers with
+/// the release time.  Whatever the interpolation misses is caught by clipping the samples at the ceiling.
+///
+/// Parameters, in order: `ceiling` in dBTP, `release` in ms and `lookahead` in ms (takes effect the next time the graph is prepared)
+pub struct TruePeakLimiterNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    sampling_freq: f32,
+    buffer_size: usize,
+    ceiling: f32,
+    release: f32,
+    lookahead: f32,
+    /// Interpolation filter taps for each point between two samples
+    phases: Vec<[f32; 2 * TRUE_PEAK_HALF_TAPS]>,
+    /// Input history of every channel, long enough for the interpolation filter and the audio delay
+    history: Vec<Vec<f32>>,
+    history_position: usize,
+    window: usize,
+    /// Gain each sample needs, for the last `window + 1` samples
+    required_gains: Vec<f32>,
+    /// Held and released gains of the last `window` samples, and their sum
+    held_gains: Vec<f32>,
+    held_sum: f64,
+    gain_position: usize,
+    released_gain: f32,
+    gain: f32
+}
+
+impl TruePeakLimiterNode {
+    pub fn new(ceiling: f32, release: f32) -> TruePeakLimiterNode {
+        TruePeakLimiterNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_channels: 1,
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            ceiling: ceiling.min(0.0),
+            release: release.max(0.0),
+            lookahead: 1.5,
+            phases: vec![],
+            history: vec![],
+            history_position: 0,
+            window: 1,
+            required_gains: vec![],
+            held_gains: vec![],
+            held_sum: 0.0,
+            gain_position: 0,
+            released_gain: 1.0,
+            gain: 1.0
+        }
+    }
+
+    /// Change the number of channels limited.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+    }
+
+    /// Get the current gain reduction in dB (0 or negative).  For metering
+    pub fn get_gain_reduction(&self) -> f32 {
+        linear_to_db(self.gain)
+    }
+
+    /// Design the interpolation filter: a Blackman-windowed sinc, one set of taps for each fraction between two samples
+    fn design_phases() -> Vec<[f32; 2 * TRUE_PEAK_HALF_TAPS]> {
+        (1..TRUE_PEAK_OVERSAMPLING).map(|phase| {
+            let fraction = phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            let mut taps = [0.0; 2 * TRUE_PEAK_HALF_TAPS];
+
+            //  Tap `tap` weighs the sample at offset `tap - HALF_TAPS + 1` from the one before the interpolated point
+            for (tap, value) in taps.iter_mut().enumerate() {
+                let distance = fraction - (tap as f64 - TRUE_PEAK_HALF_TAPS as f64 + 1.0);
+                let sinc = if distance == 0.0 { 1.0 } else { (PI * distance).sin() / (PI * distance) };
+                let position = (distance / TRUE_PEAK_HALF_TAPS as f64 + 1.0) * 0.5;
+                let window = 0.42 - 0.5 * (2.0 * PI * position).cos() + 0.08 * (4.0 * PI * position).cos();
+                *value = (sinc * window) as f32;
+            }
+
+            taps
+        }).collect()
+    }
+
+    /// Length of every channel's input history
+    fn get_history_length(&self) -> usize {
+        self.latency_samples() + TRUE_PEAK_HALF_TAPS + 1
+    }
+}
+
+impl AudioNode for TruePeakLimiterNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+
+        self.phases = TruePeakLimiterNode::design_phases();
+        self.window = ((self.lookahead * 0.001 * self.sampling_freq).round() as usize).max(1);
+        self.history = vec![vec![0.0; self.get_history_length()]; self.num_channels];
+        self.required_gains = vec![1.0; self.window + 1];
+        self.held_gains = vec![1.0; self.window];
+        self.reset();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("true_peak_limiter")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// The interpolation filter looks `TRUE_PEAK_HALF_TAPS` samples ahead, and the gain of a sample is settled `window - 1` samples after that
+    fn latency_samples(&self) -> usize {
+        TRUE_PEAK_HALF_TAPS + self.window - 1
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(ceiling) = parameters.first() { self.ceiling = ceiling.min(0.0); }
+        if let Some(release) = parameters.get(1) { self.release = release.max(0.0); }
+        if let Some(lookahead) = parameters.get(2) { self.lookahead = lookahead.max(0.0); }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.ceiling, self.release, self.lookahead]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &TRUE_PEAK_LIMITER_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        for history in &mut self.history {
+            history.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        self.required_gains.iter_mut().for_each(|gain| *gain = 1.0);
+        self.held_gains.iter_mut().for_each(|gain| *gain = 1.0);
+        self.held_sum = self.held_gains.len() as f64;
+        self.history_position = 0;
+        self.gain_position = 0;
+        self.released_gain = 1.0;
+        self.gain = 1.0;
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        if self.history.is_empty() {
+            return buffer;
+        }
+
+        let buffer_size = self.buffer_size;
+        let ceiling = db_to_linear(self.ceiling);
+        let release = time_coefficient(self.release, self.sampling_freq);
+        let history_length = self.get_history_length();
+        let delay = self.latency_samples();
+
+        for index in 0..buffer_size {
+            //  Peak of the stretch between the samples HALF_TAPS and HALF_TAPS - 1 back, both ends included
+            let mut peak = 0.0f32;
+            for (channel, history) in self.history.iter_mut().enumerate() {
+                history[self.history_position] = buffer[channel * buffer_size + index];
+                let sample = |offset: usize| history[(self.history_position + history_length - offset) % history_length];
+
+                peak = peak.max(sample(TRUE_PEAK_HALF_TAPS).abs()).max(sample(TRUE_PEAK_HALF_TAPS - 1).abs());
+                for taps in &self.phases {
+                    let mut interpolated = 0.0;
+                    for (tap, value) in taps.iter().enumerate() {
+                        interpolated += value * sample(2 * TRUE_PEAK_HALF_TAPS - 1 - tap);
+                    }
+                    peak = peak.max(interpolated.abs());
+                }
+            }
+
+            //  The peak limits both samples at the ends of the stretch, so hold the minimum over one more sample than the window
+            let window = self.window;
+            self.required_gains[self.gain_position % (window + 1)] = if peak > ceiling { ceiling / peak } else { 1.0 };
+            let held = self.required_gains.iter().fold(1.0f32, |held, gain| held.min(*gain));
+
+            self.released_gain = if held < self.released_gain { held } else { held + (self.released_gain - held) * release };
+
+            let slot = self.gain_position % window;
+            self.held_sum += (self.released_gain - self.held_gains[slot]) as f64;
+            self.held_gains[slot] = self.released_gain;
+            self.gain = ((self.held_sum / window as f64) as f32).min(1.0);
+            self.gain_position = (self.gain_position + 1) % (window * (window + 1));
+
+            for (channel, history) in self.history.iter().enumerate() {
+                let delayed = history[(self.history_position + history_length - delay) % history_length];
+                buffer[channel * buffer_size + index] = (delayed * self.gain).clamp(-ceiling, ceiling);
+            }
+
+            self.history_position = (self.history_position + 1) % history_length;
+        }
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioToolbox::AudioGraph;
+    use crate::ModelNodes::{TestFXNode, TestGenNode};
+
+    #[test]
+    fn compress_expand_and_gate() {
+        let steady_output = |node: &mut DynamicsNode, level: f32| {
+            node.init(&AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: 200 });
+            let mut buffer = [level; 200];
+            node.process_block(&mut buffer)[199]
+        };
+
+        //  -10 dB into a 4:1 compressor at -20 dB comes out at -17.5 dB, plus 2 dB of make-up gain
+        let mut compressor = DynamicsNode::new(DynamicsMode::Compressor, -20.0, 4.0);
+        compressor.change_parameters(&[0.0, -20.0, 4.0, 0.0, 1.0, 10.0, 2.0]);
+        assert!((linear_to_db(steady_output(&mut compressor, db_to_linear(-10.0))) + 15.5).abs() < 0.01);
+        assert!((compressor.get_gain_reduction() + 7.5).abs() < 0.01);
+
+        //  Halfway into a 10 dB knee the curve has eased in by a quarter of the full reduction
+        compressor.change_parameters(&[0.0, -20.0, 4.0, 10.0, 1.0, 10.0, 0.0]);
+        assert!((linear_to_db(steady_output(&mut compressor, db_to_linear(-20.0))) + 20.9375).abs() < 0.01);
+
+        //  The limiter holds the threshold, a 1:2 expander doubles the distance below it and the gate drops by the range
+        let mut limiter = DynamicsNode::new(DynamicsMode::Limiter, -6.0, 1.0);
+        limiter.change_parameters(&[1.0, -6.0, 1.0, 0.0, 0.0]);
+        assert!((linear_to_db(steady_output(&mut limiter, 1.0)) + 6.0).abs() < 0.01);
+        let mut expander = DynamicsNode::new(DynamicsMode::Expander, -20.0, 2.0);
+        expander.change_parameters(&[2.0, -20.0, 2.0, 0.0, 1.0, 10.0]);
+        assert!((linear_to_db(steady_output(&mut expander, db_to_linear(-30.0))) + 40.0).abs() < 0.01);
+        let mut gate = DynamicsNode::new(DynamicsMode::Gate, -20.0, 1.0);
+        gate.change_parameters(&[3.0, -20.0, 1.0, 0.0, 1.0, 10.0, 0.0, 40.0]);
+        assert!((linear_to_db(steady_output(&mut gate, db_to_linear(-30.0))) + 70.0).abs() < 0.01);
+        assert!((linear_to_db(steady_output(&mut gate, db_to_linear(-10.0))) + 10.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn duck_from_sidechain_with_lookahead() {
+        //  [gen] -> [fx] -> [fx] -> [limiter] <- sidechain [gen], [limiter] -> [Output].
+        //  The main input is 0.25 (-12 dB), but the sidechain's 1.0 (0 dB) sets the gain to -20 dB
+        let mut limiter = DynamicsNode::new(DynamicsMode::Limiter, -20.0, 1.0);
+        limiter.change_parameters(&[1.0, -20.0, 1.0, 0.0, 0.0, 100.0, 0.0, 80.0, 1.0, 3.0]);
+
+        let mut graph = AudioGraph::new();
+        let main_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let fx1_id = graph.add_new_node(Box::new(TestFXNode::new())).ok().unwrap();
+        let fx2_id = graph.add_new_node(Box::new(TestFXNode::new())).ok().unwrap();
+        let sidechain_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let limiter_id = graph.add_new_node(Box::new(limiter)).ok().unwrap();
+        if graph.connect_node(main_id, fx1_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx1_id, fx2_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx2_id, limiter_id, 0).is_err() { panic!(); }
+        if graph.connect_node(sidechain_id, limiter_id, DYNAMICS_SIDECHAIN_INPUT).is_err() { panic!(); }
+        if graph.connect_node_to_output(limiter_id).is_err() { panic!(); }
+        if graph.prepare(AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: 8 }).is_err() { panic!(); }
+
+        //  3 ms of lookahead at 1 kHz
+        assert_eq!(graph.get_node(limiter_id).map(|node| node.latency_samples()), Some(3));
+
+        let mut buffer = [0.0; 8];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => {
+                assert_eq!(buffer[..3], [0.0; 3]);
+                assert!(buffer[3..].iter().all(|sample| (sample - 0.025).abs() < 1e-6));
+            },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    #[test]
+    fn limit_true_peaks() {
+        let sampling_freq = 48_000.0;
+        let mut limiter = TruePeakLimiterNode::new(-1.0, 50.0);
+        limiter.set_number_of_channels(2);
+        limiter.init(&AudioRuntimeParameters { sampling_freq, buffer_size: 4_800 });
+        assert_eq!(limiter.latency_samples(), 4 + 72 - 1);
+
+        //  A quarter of the sampling frequency, sampled 45 degrees off its peaks: the samples only reach 0.71 but the waveform reaches 1.
+        //  The other channel carries loud noise
+        let mut buffer: Vec<f32> = (0..4_800).map(|index| (0.5 * std::f32::consts::PI * index as f32 + 0.25 * std::f32::consts::PI).sin()).collect();
+        let mut seed = 1u32;
+        buffer.extend((0..4_800).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            4.0 * (seed as f32 / u32::MAX as f32 - 0.5)
+        }));
+        limiter.process_block(&mut buffer);
+
+        let ceiling = db_to_linear(-1.0);
+        assert!(buffer.iter().all(|sample| sample.abs() <= ceiling));
+
+        //  The noise sets the gain, and no sample of the sine's output exceeds what the ceiling allows for its true peak
+        let sine_peak = buffer[100..4_800].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(sine_peak <= ceiling * std::f32::consts::FRAC_1_SQRT_2 * 1.01);
+        assert!(limiter.get_gain_reduction() < -6.0);
+
+        //  On its own, the sine is turned down until its true peak sits at the ceiling
+        limiter.set_number_of_channels(1);
+        limiter.init(&AudioRuntimeParameters { sampling_freq, buffer_size: 4_800 });
+        let mut buffer: Vec<f32> = (0..4_800).map(|index| (0.5 * std::f32::consts::PI * index as f32 + 0.25 * std::f32::consts::PI).sin()).collect();
+        limiter.process_block(&mut buffer);
+        let sine_peak = buffer[2_400..].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!((sine_peak / (ceiling * std::f32::consts::FRAC_1_SQRT_2) - 1.0).abs() < 0.03);
+    }
+}
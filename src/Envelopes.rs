@@ -0,0 +1,530 @@
+//! # Envelopes
+//! Envelope generators that turn gate events into control signals.
+//!
+//! Envelopes output their level at audio rate, so they can be connected wherever a signal is expected: into the amplitude input of an
+//! `OscillatorNode` to use it as a VCA, into its frequency input to sweep the pitch, and so on.
+//! A gate is open while it is opened with `AudioGraph::set_node_gate()` or the signal on the gate input is at least 0.5.  Opening the gate (re)starts the envelope
+//! from wherever it currently is, so retriggering never clicks.
+//!
+//! ```
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::Envelopes::AdsrNode;
+//! use audio_graph::Generators::{OscillatorNode, Waveform, OSCILLATOR_AMPLITUDE_INPUT};
+//!
+//! //  [adsr] -> amplitude input of [osc] -> [Output]
+//! let mut graph = AudioGraph::new();
+//! let adsr_id = graph.add_new_node(Box::new(AdsrNode::new(0.01, 0.2, 0.6, 0.5)))?;
+//! let osc_id = graph.add_new_node(Box::new(OscillatorNode::new(Waveform::Saw, 110.0)))?;
+//! graph.set_node_parameters(osc_id, &[1.0, 110.0, 0.0])?;
+//! graph.connect_node(adsr_id, osc_id, OSCILLATOR_AMPLITUDE_INPUT)?;
+//! graph.connect_node_to_output(osc_id)?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 })?;
+//!
+//! //  Note on
+//! graph.set_node_gate(adsr_id, true)?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters};
+
+/// Curve of the decay and release segments of `AdsrNode`, which makes them fall quickly at first like an analogue envelope
+const ADSR_DECAY_CURVE: f32 = -5.0;
+
+/// Gate signals at or above this level open the gate
+const GATE_THRESHOLD: f32 = 0.5;
+
+const ADSR_PARAMETER_NAMES: [&str; 5] = ["attack", "decay", "sustain", "release", "trigger_mode"];
+
+
+/// One segment of an envelope: move from the level the previous segment ended at to `level` in `time` seconds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EnvelopeSegment {
+    pub level: f32,
+    pub time: f32,
+    /// Shape of the segment.  0 is a straight line; positive values start slowly and speed up, negative values start quickly and slow down
+    pub curve: f32
+}
+
+impl EnvelopeSegment {
+    pub fn new(level: f32, time: f32, curve: f32) -> EnvelopeSegment {
+        EnvelopeSegment {
+            level,
+            time: time.max(0.0),
+            curve
+        }
+    }
+}
+
+/// Map progress through a segment (0 to 1) onto the segment's curve
+fn shape_progress(progress: f32, curve: f32) -> f32 {
+    if curve.abs() < 1e-3 {
+        return progress;
+    }
+
+    ((curve * progress).exp() - 1.0) / (curve.exp() - 1.0)
+}
+
+/// The state machine shared by all envelope nodes.
+/// The envelope runs through its segments one after the other.  When it has a sustain segment, it stays at the end of it while the gate is open
+/// and skips to the segment after it when the gate closes; without one, the envelope always runs to the end once triggered
+struct EnvelopeCore {
+    segments: Vec<EnvelopeSegment>,
+    sustain_segment: Option<usize>,
+    sampling_freq: f32,
+    gate: bool,
+    segment: Option<usize>,
+    held: bool,
+    level: f32,
+    start_level: f32,
+    position: f32
+}
+
+impl EnvelopeCore {
+    fn new(segments: Vec<EnvelopeSegment>, sustain_segment: Option<usize>) -> EnvelopeCore {
+        EnvelopeCore {
+            segments,
+            sustain_segment,
+            sampling_freq: 44_100.0,
+            gate: false,
+            segment: None,
+            held: false,
+            level: 0.0,
+            start_level: 0.0,
+            position: 0.0
+        }
+    }
+
+    fn reset(&mut self) {
+        self.gate = false;
+        self.segment = None;
+        self.held = false;
+        self.level = 0.0;
+    }
+
+    fn start_segment(&mut self, segment: usize) {
+        if segment < self.segments.len() {
+            self.segment = Some(segment);
+            self.start_level = self.level;
+            self.position = 0.0;
+        } else {
+            self.segment = None;
+        }
+        self.held = false;
+    }
+
+    fn set_gate(&mut self, gate: bool) {
+        if gate == self.gate {
+            return;
+        }
+        self.gate = gate;
+
+        if gate {
+            self.start_segment(0);
+            return;
+        }
+
+        //  Closing the gate releases the envelope, unless it has already moved past the sustain segment
+        if let (Some(sustain_segment), Some(segment)) = (self.sustain_segment, self.segment) {
+            if segment <= sustain_segment {
+                self.start_segment(sustain_segment + 1);
+            }
+        }
+    }
+
+    fn next_level(&mut self) -> f32 {
+        let segment_index = match self.segment {
+            Some(segment_index) => segment_index,
+            None => return self.level
+        };
+
+        if self.held {
+            return self.level;
+        }
+
+        let segment = self.segments[segment_index];
+        let length = (segment.time * self.sampling_freq).round();
+        self.position += 1.0;
+
+        let progress = if length <= 1.0 { 1.0 } else { (self.position / length).min(1.0) };
+        self.level = self.start_level + (segment.level - self.start_level) * shape_progress(progress, segment.curve);
+
+        if progress >= 1.0 {
+            self.level = segment.level;
+
+            if self.sustain_segment == Some(segment_index) && self.gate {
+                self.held = true;
+            } else {
+                self.start_segment(segment_index + 1);
+            }
+        }
+
+        self.level
+    }
+
+    /// Fill the first channel of `buffer` with the envelope, reading the gate input from the same channel sample by sample
+    fn process(&mut self, buffer: &mut [f32], buffer_size: usize, gate_open: bool) {
+        for sample in buffer[..buffer_size].iter_mut() {
+            self.set_gate(gate_open || *sample >= GATE_THRESHOLD);
+            *sample = self.next_level();
+        }
+    }
+}
+
+
+/// Classic attack-decay-sustain-release envelope with a gate input.
+/// The attack rises linearly to 1, decay and release fall along an exponential-like curve.  The gate is opened and closed with `set_gate()`.
+///
+/// Parameters, in order:
+/// - `attack`, `decay`, `release`: segment times in seconds
+/// - `sustain`: level held while the gate stays open
+/// - `trigger_mode`: 1 to run attack, decay and release on every gate opening regardless of how long the gate stays open, e.g. for drum triggers
+pub struct AdsrNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    buffer_size: usize,
+    gate: bool,
+    trigger_mode: bool,
+    core: EnvelopeCore
+}
+
+impl AdsrNode {
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> AdsrNode {
+        let segments = vec![
+            EnvelopeSegment::new(1.0, attack, 0.0),
+            EnvelopeSegment::new(sustain, decay, ADSR_DECAY_CURVE),
+            EnvelopeSegment::new(0.0, release, ADSR_DECAY_CURVE)
+        ];
+
+        AdsrNode {
+            node_type: AudioNodeType::Generator,
+            num_inputs: 1,
+            next_available_input: 0,
+            buffer_size: 0,
+            gate: false,
+            trigger_mode: false,
+            core: EnvelopeCore::new(segments, Some(1))
+        }
+    }
+
+    /// Get the current output level of the envelope
+    pub fn get_level(&self) -> f32 {
+        self.core.level
+    }
+}
+
+impl AudioNode for AdsrNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.core.sampling_freq = audio_runtime_params.sampling_freq;
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("adsr")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        let segments = &mut self.core.segments;
+        if let Some(attack) = parameters.first() { segments[0].time = attack.max(0.0); }
+        if let Some(decay) = parameters.get(1) { segments[1].time = decay.max(0.0); }
+        if let Some(sustain) = parameters.get(2) { segments[1].level = *sustain; }
+        if let Some(release) = parameters.get(3) { segments[2].time = release.max(0.0); }
+        if let Some(trigger_mode) = parameters.get(4) { self.trigger_mode = *trigger_mode >= 0.5; }
+
+        self.core.sustain_segment = if self.trigger_mode { None } else { Some(1) };
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        let segments = &self.core.segments;
+
+        vec![
+            segments[0].time,
+            segments[1].time,
+            segments[1].level,
+            segments[2].time,
+            if self.trigger_mode { 1.0 } else { 0.0 }
+        ]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &ADSR_PARAMETER_NAMES
+    }
+
+    fn set_gate(&mut self, open: bool) {
+        self.gate = open;
+    }
+
+    fn reset(&mut self) {
+        self.core.reset();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        self.core.process(buffer, self.buffer_size, self.gate);
+        buffer
+    }
+}
+
+
+/// Envelope made of any number of segments, each with its own target level, time and curve, and optionally a sustain segment.
+///
+/// Parameters, in order: `sustain_segment` (-1 for none), then `level`, `time` and `curve` for every segment.
+/// Passing only the first leaves the segments as they are.  The gate is opened and closed with `set_gate()`
+pub struct EnvelopeNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    buffer_size: usize,
+    gate: bool,
+    parameter_names: Vec<&'static str>,
+    core: EnvelopeCore
+}
+
+impl EnvelopeNode {
+    /// Create an envelope.  While the gate is open it holds at the end of `sustain_segment`; without a sustain segment every opening of the gate runs the whole envelope
+    pub fn new(segments: Vec<EnvelopeSegment>, sustain_segment: Option<usize>) -> EnvelopeNode {
+        let mut envelope_node = EnvelopeNode {
+            node_type: AudioNodeType::Generator,
+            num_inputs: 1,
+            next_available_input: 0,
+            buffer_size: 0,
+            gate: false,
+            parameter_names: vec![],
+            core: EnvelopeCore::new(vec![], None)
+        };
+        envelope_node.set_segments(segments, sustain_segment);
+
+        envelope_node
+    }
+
+    pub fn get_segments(&self) -> &[EnvelopeSegment] {
+        &self.core.segments
+    }
+
+    /// Get the current output level of the envelope
+    pub fn get_level(&self) -> f32 {
+        self.core.level
+    }
+
+    fn set_segments(&mut self, segments: Vec<EnvelopeSegment>, sustain_segment: Option<usize>) {
+        self.core.sustain_segment = sustain_segment.filter(|segment| *segment < segments.len());
+        self.core.segments = segments;
+
+        //  A running envelope may now be past its last segment
+        if self.core.segment.is_some_and(|segment| segment >= self.core.segments.len()) {
+            self.core.segment = None;
+        }
+
+        self.parameter_names = vec!["sustain_segment"];
+        for _ in 0..self.core.segments.len() {
+            self.parameter_names.extend_from_slice(&["level", "time", "curve"]);
+        }
+    }
+}
+
+impl AudioNode for EnvelopeNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.core.sampling_freq = audio_runtime_params.sampling_freq;
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("envelope")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        let sustain_segment = match parameters.first() {
+            Some(sustain_segment) if *sustain_segment >= 0.0 => Some(sustain_segment.round() as usize),
+            Some(_) => None,
+            None => return
+        };
+
+        let segments = match parameters.len() {
+            1 => self.core.segments.clone(),
+            _ => parameters[1..].chunks_exact(3).map(|segment| EnvelopeSegment::new(segment[0], segment[1], segment[2])).collect()
+        };
+        self.set_segments(segments, sustain_segment);
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        let mut parameters = vec![self.core.sustain_segment.map_or(-1.0, |segment| segment as f32)];
+
+        for segment in &self.core.segments {
+            parameters.extend_from_slice(&[segment.level, segment.time, segment.curve]);
+        }
+
+        parameters
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &self.parameter_names
+    }
+
+    fn set_gate(&mut self, open: bool) {
+        self.gate = open;
+    }
+
+    fn reset(&mut self) {
+        self.core.reset();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        self.core.process(buffer, self.buffer_size, self.gate);
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioToolbox::AudioGraph;
+    use crate::ModelNodes::TestGenNode;
+
+    fn run(node: &mut dyn AudioNode, num_samples: usize) -> Vec<f32> {
+        node.init(&AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: num_samples });
+        node.process_block(&mut vec![0.0; num_samples]).to_vec()
+    }
+
+    #[test]
+    fn run_adsr_envelope() {
+        //  10 ms attack, 20 ms decay to 0.5 and 10 ms release at 1 kHz
+        let mut adsr = AdsrNode::new(0.01, 0.02, 0.5, 0.01);
+        assert_eq!(run(&mut adsr, 4), vec![0.0; 4]);
+
+        adsr.set_gate(true);
+        let output = run(&mut adsr, 50);
+        assert!((output[4] - 0.5).abs() < 1e-6);
+        assert_eq!(output[9], 1.0);
+        assert!(output[10] < 1.0 && output[10] > 0.8);
+        assert_eq!(output[29..], [0.5; 21]);
+
+        //  Closing the gate releases from the sustain level
+        adsr.set_gate(false);
+        let output = run(&mut adsr, 20);
+        assert!(output[0] < 0.5 && output[0] > 0.2);
+        assert_eq!(output[9..], [0.0; 11]);
+
+        //  Retriggering during the release starts the attack from the current level instead of jumping to zero
+        adsr.set_gate(true);
+        run(&mut adsr, 5);
+        adsr.set_gate(false);
+        let released = run(&mut adsr, 2)[1];
+        adsr.set_gate(true);
+        let retriggered = run(&mut adsr, 1)[0];
+        assert!(retriggered > released && retriggered < released + 0.2);
+
+        adsr.reset();
+        assert_eq!(adsr.get_level(), 0.0);
+        adsr.set_gate(false);
+        assert_eq!(run(&mut adsr, 4), vec![0.0; 4]);
+
+        //  In a graph, opening the gate leaves the parameters and the edit history alone
+        let mut graph = AudioGraph::new();
+        let adsr_id = graph.add_new_node(Box::new(adsr)).ok().unwrap();
+        if graph.connect_node_to_output(adsr_id).is_err() { panic!(); }
+        if graph.prepare(AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: 4 }).is_err() { panic!(); }
+        graph.enable_history(8);
+
+        if graph.set_node_gate(adsr_id, true).is_err() { panic!(); }
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert!(buffer[3] > 0.0); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+        assert_eq!(graph.get_node(adsr_id).unwrap().get_parameters(), vec![0.01, 0.02, 0.5, 0.01, 0.0]);
+        assert!(!graph.can_undo());
+        assert!(graph.set_node_gate(42, true).is_err());
+    }
+
+    #[test]
+    fn run_multi_segment_envelope() {
+        //  Up to 1 in 4 ms along a slow-starting curve, down to 0.25 in 4 ms in a straight line, then back to 0 in 2 ms.  No sustain
+        let segments = vec![EnvelopeSegment::new(1.0, 0.004, 3.0), EnvelopeSegment::new(0.25, 0.004, 0.0), EnvelopeSegment::new(0.0, 0.002, 0.0)];
+        let mut envelope = EnvelopeNode::new(segments, None);
+
+        envelope.set_gate(true);
+        let output = run(&mut envelope, 12);
+        assert!(output[0] < 0.25);
+        assert_eq!(output[3], 1.0);
+        assert_eq!(output[4..8], [0.8125, 0.625, 0.4375, 0.25]);
+        assert_eq!(output[8..], [0.125, 0.0, 0.0, 0.0]);
+
+        //  Parameters describe the segments and can replace them
+        assert_eq!(envelope.get_parameters(), vec![-1.0, 1.0, 0.004, 3.0, 0.25, 0.004, 0.0, 0.0, 0.002, 0.0]);
+        envelope.set_gate(false);
+        envelope.change_parameters(&[0.0, 0.5, 0.002, 0.0, 0.0, 0.002, 0.0]);
+        assert_eq!(envelope.get_segments().len(), 2);
+        assert_eq!(envelope.get_parameter_names().len(), 7);
+        envelope.reset();
+
+        //  Driven by a gate signal in a graph: [gen] -> [envelope] -> [Output].  The gate stays open, so the envelope sustains
+        let mut graph = AudioGraph::new();
+        let gen_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let envelope_id = graph.add_new_node(Box::new(envelope)).ok().unwrap();
+        if graph.connect_node(gen_id, envelope_id, 0).is_err() { panic!(); }
+        if graph.connect_node_to_output(envelope_id).is_err() { panic!(); }
+        if graph.prepare(AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: 6 }).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 6];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [0.25, 0.5, 0.5, 0.5, 0.5, 0.5]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+}
@@ -0,0 +1,871 @@
+//! # Filters
+//! Filter nodes built on the topology-preserving transform (TPT) state variable filter described by Andrew Simper.
+//!
+//! The SVF produces the same magnitude responses as the RBJ "Audio EQ Cookbook" biquads, but its state stays meaningful when the coefficients change,
+//! so cutoff and gain can be swept quickly, even every sample, without the zipper noise or blow-ups of a direct form biquad.
+//!
+//! `BiquadNode` is a single filter; `ParametricEqNode` chains any number of bells, shelves and steep pass filters and can report its
+//! combined response for drawing EQ curves:
+//!
+//! ```
+//! use audio_graph::AudioToolbox::{AudioNode, AudioRuntimeParameters};
+//! use audio_graph::Filters::{EqBand, EqBandType, ParametricEqNode};
+//!
+//! let mut eq = ParametricEqNode::new(vec![
+//!     EqBand::new(EqBandType::HighPass, 80.0, 0.0, 0.707, 24),
+//!     EqBand::new(EqBandType::Bell, 2_500.0, 4.0, 1.5, 12),
+//!     EqBand::new(EqBandType::HighShelf, 10_000.0, -3.0, 0.707, 12)
+//! ]);
+//! eq.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 });
+//!
+//! let curve = eq.get_response(&[20.0, 100.0, 1_000.0, 2_500.0, 15_000.0]);
+//! let (magnitude, _phase) = curve[3];
+//! assert!((20.0 * magnitude.log10() - 4.0).abs() < 0.1);
+//! ```
+
+use std::f64::consts::PI;
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters};
+
+/// Time constant with which frequency, Q and gain follow parameter changes
+const PARAMETER_SMOOTHING_TIME: f32 = 0.02;
+
+const BIQUAD_PARAMETER_NAMES: [&str; 4] = ["type", "frequency", "q", "gain_db"];
+
+const EQ_BAND_PARAMETER_NAMES: [&str; 6] = ["type", "frequency", "gain_db", "q", "slope", "enabled"];
+
+/// Steepest slope of the pass filters of `ParametricEqNode`, in dB per octave.  Every 12 dB takes one second order section
+const MAX_EQ_SLOPE: u32 = 48;
+const MAX_EQ_SECTIONS: usize = (MAX_EQ_SLOPE / 12) as usize;
+
+
+/// Responses from the RBJ cookbook.  Stored in the `type` parameter of filter nodes as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    /// Band-pass with a peak gain of 0 dB
+    BandPass,
+    Notch,
+    AllPass,
+    /// Bell boosting or cutting `gain_db` around the frequency
+    Peak,
+    LowShelf,
+    HighShelf
+}
+
+impl FilterType {
+    pub(crate) fn from_parameter(value: f32) -> FilterType {
+        match value.round() as i32 {
+            1 => FilterType::HighPass,
+            2 => FilterType::BandPass,
+            3 => FilterType::Notch,
+            4 => FilterType::AllPass,
+            5 => FilterType::Peak,
+            6 => FilterType::LowShelf,
+            7 => FilterType::HighShelf,
+            _ => FilterType::LowPass
+        }
+    }
+
+    pub(crate) fn to_parameter(self) -> f32 {
+        match self {
+            FilterType::LowPass => 0.0,
+            FilterType::HighPass => 1.0,
+            FilterType::BandPass => 2.0,
+            FilterType::Notch => 3.0,
+            FilterType::AllPass => 4.0,
+            FilterType::Peak => 5.0,
+            FilterType::LowShelf => 6.0,
+            FilterType::HighShelf => 7.0
+        }
+    }
+}
+
+
+/// Coefficients of one SVF section.  `a1` to `a3` run the filter, `m0` to `m2` mix its high-pass, band-pass and low-pass outputs into the response
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct SvfCoefficients {
+    g: f64,
+    k: f64,
+    a1: f64,
+    a2: f64,
+    a3: f64,
+    m0: f64,
+    m1: f64,
+    m2: f64
+}
+
+impl SvfCoefficients {
+    /// Design a section.  The frequency is clamped to just below Nyquist and Q to a small positive value
+    pub(crate) fn design(filter_type: FilterType, frequency: f32, q: f32, gain_db: f32, sampling_freq: f32) -> SvfCoefficients {
+        let frequency = (frequency as f64).clamp(1.0, 0.49 * sampling_freq as f64);
+        let q = (q as f64).max(0.01);
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+
+        let mut g = (PI * frequency / sampling_freq as f64).tan();
+        let mut k = 1.0 / q;
+
+        let (m0, m1, m2) = match filter_type {
+            FilterType::LowPass => (0.0, 0.0, 1.0),
+            FilterType::HighPass => (1.0, -k, -1.0),
+            FilterType::BandPass => (0.0, k, 0.0),
+            FilterType::Notch => (1.0, -k, 0.0),
+            FilterType::AllPass => (1.0, -2.0 * k, 0.0),
+            FilterType::Peak => {
+                k = 1.0 / (q * a);
+                (1.0, k * (a * a - 1.0), 0.0)
+            },
+            FilterType::LowShelf => {
+                g /= a.sqrt();
+                (1.0, k * (a - 1.0), a * a - 1.0)
+            },
+            FilterType::HighShelf => {
+                g *= a.sqrt();
+                (a * a, k * (1.0 - a) * a, 1.0 - a * a)
+            }
+        };
+
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        SvfCoefficients { g, k, a1, a2, a3, m0, m1, m2 }
+    }
+
+    /// Coefficients that pass the signal through unchanged
+    pub(crate) fn identity() -> SvfCoefficients {
+        SvfCoefficients { g: 0.0, k: 1.0, a1: 1.0, a2: 0.0, a3: 0.0, m0: 1.0, m1: 0.0, m2: 0.0 }
+    }
+
+    /// Get the complex frequency response at `frequency` as (real, imaginary).
+    /// The TPT filter equals its analogue prototype under the bilinear transform with prewarping, which gives `s = j tan(w / 2) / g`
+    pub(crate) fn response(&self, frequency: f32, sampling_freq: f32) -> (f64, f64) {
+        let w = 2.0 * PI * (frequency as f64) / sampling_freq as f64;
+        let s = (w / 2.0).tan() / self.g.max(1e-12);
+
+        //  H(s) = (m0 (s^2 + k s + 1) + m1 s + m2) / (s^2 + k s + 1), with s = j * s_imag
+        let denominator = (1.0 - s * s, self.k * s);
+        let numerator = (self.m0 * (1.0 - s * s) + self.m2, self.m0 * self.k * s + self.m1 * s);
+        let norm = denominator.0 * denominator.0 + denominator.1 * denominator.1;
+
+        ((numerator.0 * denominator.0 + numerator.1 * denominator.1) / norm,
+         (numerator.1 * denominator.0 - numerator.0 * denominator.1) / norm)
+    }
+}
+
+/// Integrator state of one SVF section for one channel
+#[derive(Clone, Copy, Default)]
+pub(crate) struct SvfState {
+    ic1eq: f64,
+    ic2eq: f64
+}
+
+impl SvfState {
+    pub(crate) fn tick(&mut self, coefficients: &SvfCoefficients, input: f64) -> f64 {
+        let v3 = input - self.ic2eq;
+        let v1 = coefficients.a1 * self.ic1eq + coefficients.a2 * v3;
+        let v2 = self.ic2eq + coefficients.a2 * self.ic1eq + coefficients.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        coefficients.m0 * input + coefficients.m1 * v1 + coefficients.m2 * v2
+    }
+}
+
+/// One-pole smoother for a filter parameter
+#[derive(Clone, Copy)]
+struct SmoothedValue {
+    current: f32,
+    target: f32
+}
+
+impl SmoothedValue {
+    fn new(value: f32) -> SmoothedValue {
+        SmoothedValue { current: value, target: value }
+    }
+
+    fn is_settled(&self) -> bool {
+        self.current == self.target
+    }
+
+    fn next(&mut self, coefficient: f32) -> f32 {
+        self.current = self.target + (self.current - self.target) * coefficient;
+        if (self.current - self.target).abs() < 1e-4 {
+            self.current = self.target;
+        }
+
+        self.current
+    }
+}
+
+
+/// Filter node with the RBJ cookbook responses (see `FilterType`), processing any number of channels with the same settings.
+/// Coefficients are computed in `init()` and whenever the parameters change.  Frequency (on a logarithmic scale), Q and gain glide to new values
+/// over about 20 ms, with the coefficients recomputed every sample while they do.
+///
+/// Parameters, in order: `type` (index of `FilterType`), `frequency` in Hz, `q`, `gain_db` (peak and shelf types only)
+pub struct BiquadNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    filter_type: FilterType,
+    frequency: f32,
+    log_frequency: SmoothedValue,
+    q: SmoothedValue,
+    gain_db: SmoothedValue,
+    sampling_freq: f32,
+    buffer_size: usize,
+    smoothing_coefficient: f32,
+    coefficients: SvfCoefficients,
+    states: Vec<SvfState>
+}
+
+impl BiquadNode {
+    pub fn new(filter_type: FilterType, frequency: f32, q: f32, gain_db: f32) -> BiquadNode {
+        let mut biquad_node = BiquadNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_channels: 1,
+            filter_type,
+            frequency,
+            log_frequency: SmoothedValue::new(frequency.max(1.0).log2()),
+            q: SmoothedValue::new(q),
+            gain_db: SmoothedValue::new(gain_db),
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            smoothing_coefficient: 0.0,
+            coefficients: SvfCoefficients::identity(),
+            states: vec![SvfState::default()]
+        };
+        biquad_node.update_coefficients();
+
+        biquad_node
+    }
+
+    /// Change the number of channels filtered.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+        self.states = vec![SvfState::default(); self.num_channels];
+    }
+
+    /// Get the magnitude (linear) and phase (radians) of the filter's response at `frequency`, for the target parameters
+    pub fn get_response(&self, frequency: f32) -> (f32, f32) {
+        let coefficients = SvfCoefficients::design(self.filter_type, self.frequency, self.q.target, self.gain_db.target, self.sampling_freq);
+        let (real, imaginary) = coefficients.response(frequency, self.sampling_freq);
+
+        (real.hypot(imaginary) as f32, imaginary.atan2(real) as f32)
+    }
+
+    fn update_coefficients(&mut self) {
+        self.coefficients = SvfCoefficients::design(self.filter_type, self.log_frequency.current.exp2(), self.q.current, self.gain_db.current, self.sampling_freq);
+    }
+
+    fn snap_to_targets(&mut self) {
+        self.log_frequency.current = self.log_frequency.target;
+        self.q.current = self.q.target;
+        self.gain_db.current = self.gain_db.target;
+        self.update_coefficients();
+    }
+}
+
+impl AudioNode for BiquadNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.smoothing_coefficient = (-1.0 / (PARAMETER_SMOOTHING_TIME * self.sampling_freq)).exp();
+        self.snap_to_targets();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("biquad")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(filter_type) = parameters.first() {
+            self.filter_type = FilterType::from_parameter(*filter_type);
+        }
+        if let Some(frequency) = parameters.get(1) {
+            self.frequency = frequency.max(1.0);
+            self.log_frequency.target = self.frequency.log2();
+        }
+        if let Some(q) = parameters.get(2) { self.q.target = q.max(0.01); }
+        if let Some(gain_db) = parameters.get(3) { self.gain_db.target = *gain_db; }
+
+        //  Before the first block there is nothing to glide from
+        if self.buffer_size == 0 {
+            self.snap_to_targets();
+        } else {
+            self.update_coefficients();
+        }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.filter_type.to_parameter(), self.frequency, self.q.target, self.gain_db.target]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &BIQUAD_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        for state in &mut self.states {
+            *state = SvfState::default();
+        }
+        self.snap_to_targets();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+
+        for index in 0..buffer_size {
+            if !(self.log_frequency.is_settled() && self.q.is_settled() && self.gain_db.is_settled()) {
+                self.log_frequency.next(self.smoothing_coefficient);
+                self.q.next(self.smoothing_coefficient);
+                self.gain_db.next(self.smoothing_coefficient);
+                self.update_coefficients();
+            }
+
+            for (channel, state) in self.states.iter_mut().enumerate() {
+                let sample = &mut buffer[channel * buffer_size + index];
+                *sample = state.tick(&self.coefficients, *sample as f64) as f32;
+            }
+        }
+
+        buffer
+    }
+}
+
+
+/// Kinds of band of `ParametricEqNode`.  Stored in the band's `type` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EqBandType {
+    Bell,
+    LowShelf,
+    HighShelf,
+    HighPass,
+    LowPass
+}
+
+impl EqBandType {
+    fn from_parameter(value: f32) -> EqBandType {
+        match value.round() as i32 {
+            1 => EqBandType::LowShelf,
+            2 => EqBandType::HighShelf,
+            3 => EqBandType::HighPass,
+            4 => EqBandType::LowPass,
+            _ => EqBandType::Bell
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            EqBandType::Bell => 0.0,
+            EqBandType::LowShelf => 1.0,
+            EqBandType::HighShelf => 2.0,
+            EqBandType::HighPass => 3.0,
+            EqBandType::LowPass => 4.0
+        }
+    }
+}
+
+/// Settings of one band of `ParametricEqNode`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EqBand {
+    pub band_type: EqBandType,
+    pub frequency: f32,
+    /// Boost or cut of bells and shelves
+    pub gain_db: f32,
+    pub q: f32,
+    /// Slope of high and low-pass bands in dB per octave: 12, 24, 36 or 48
+    pub slope: u32,
+    pub enabled: bool
+}
+
+impl EqBand {
+    pub fn new(band_type: EqBandType, frequency: f32, gain_db: f32, q: f32, slope: u32) -> EqBand {
+        EqBand {
+            band_type,
+            frequency,
+            gain_db,
+            q,
+            slope: EqBand::clamp_slope(slope),
+            enabled: true
+        }
+    }
+
+    /// Change one setting, indexed as in the band's parameters
+    fn set_parameter(&mut self, index: usize, value: f32) {
+        match index {
+            0 => self.band_type = EqBandType::from_parameter(value),
+            1 => self.frequency = value,
+            2 => self.gain_db = value,
+            3 => self.q = value,
+            4 => self.slope = value.max(0.0).round() as u32,
+            _ => self.enabled = value >= 0.5
+        }
+    }
+
+    fn clamp_slope(slope: u32) -> u32 {
+        (slope.clamp(12, MAX_EQ_SLOPE) + 6) / 12 * 12
+    }
+
+    /// Design the second order sections that make up the band.  Returns the number of sections used.
+    /// A 12 dB/oct pass filter uses the band's Q; steeper ones are Butterworth cascades, whose sections need Qs of their own
+    fn design(&self, frequency: f32, gain_db: f32, q: f32, sampling_freq: f32, sections: &mut [SvfCoefficients; MAX_EQ_SECTIONS]) -> usize {
+        let filter_type = match self.band_type {
+            EqBandType::Bell => FilterType::Peak,
+            EqBandType::LowShelf => FilterType::LowShelf,
+            EqBandType::HighShelf => FilterType::HighShelf,
+            EqBandType::HighPass => FilterType::HighPass,
+            EqBandType::LowPass => FilterType::LowPass
+        };
+
+        if !matches!(self.band_type, EqBandType::HighPass | EqBandType::LowPass) || self.slope == 12 {
+            sections[0] = SvfCoefficients::design(filter_type, frequency, q, gain_db, sampling_freq);
+            return 1;
+        }
+
+        let num_sections = (self.slope / 12) as usize;
+        for (index, section) in sections.iter_mut().enumerate().take(num_sections) {
+            let angle = PI * (2 * index + 1) as f64 / (4 * num_sections) as f64;
+            let butterworth_q = 1.0 / (2.0 * angle.cos());
+            *section = SvfCoefficients::design(filter_type, frequency, butterworth_q as f32, 0.0, sampling_freq);
+        }
+
+        num_sections
+    }
+}
+
+/// A band with its smoothed settings, its filter sections and their state for every channel
+struct EqBandState {
+    band: EqBand,
+    log_frequency: SmoothedValue,
+    gain_db: SmoothedValue,
+    q: SmoothedValue,
+    sections: [SvfCoefficients; MAX_EQ_SECTIONS],
+    num_sections: usize,
+    channel_states: Vec<[SvfState; MAX_EQ_SECTIONS]>
+}
+
+impl EqBandState {
+    fn new(band: EqBand, num_channels: usize) -> EqBandState {
+        EqBandState {
+            band,
+            log_frequency: SmoothedValue::new(band.frequency.max(1.0).log2()),
+            gain_db: SmoothedValue::new(band.gain_db),
+            q: SmoothedValue::new(band.q.max(0.01)),
+            sections: [SvfCoefficients::identity(); MAX_EQ_SECTIONS],
+            num_sections: 0,
+            channel_states: vec![[SvfState::default(); MAX_EQ_SECTIONS]; num_channels]
+        }
+    }
+
+    fn set_band(&mut self, band: EqBand) {
+        //  A different kind of filter has nothing in common with the previous one to glide from
+        if band.band_type != self.band.band_type || band.slope != self.band.slope {
+            self.channel_states.iter_mut().for_each(|states| *states = [SvfState::default(); MAX_EQ_SECTIONS]);
+        }
+
+        self.band = band;
+        self.log_frequency.target = band.frequency.max(1.0).log2();
+        self.gain_db.target = band.gain_db;
+        self.q.target = band.q.max(0.01);
+    }
+
+    fn snap_to_targets(&mut self) {
+        self.log_frequency.current = self.log_frequency.target;
+        self.gain_db.current = self.gain_db.target;
+        self.q.current = self.q.target;
+    }
+
+    fn is_settled(&self) -> bool {
+        self.log_frequency.is_settled() && self.gain_db.is_settled() && self.q.is_settled()
+    }
+
+    fn update_sections(&mut self, sampling_freq: f32) {
+        let band = self.band;
+        self.num_sections = band.design(self.log_frequency.current.exp2(), self.gain_db.current, self.q.current, sampling_freq, &mut self.sections);
+    }
+}
+
+
+/// Parametric equaliser with any number of bands, each a bell, a shelf or a high or low-pass filter of 12 to 48 dB/oct (see `EqBand`).
+/// Every band is built from TPT state variable sections and glides to new settings like `BiquadNode`.  Disabled bands are skipped.
+///
+/// Parameters: `num_bands`, then `type` (index of `EqBandType`), `frequency`, `gain_db`, `q`, `slope` and `enabled` for every band in turn.
+/// Bands are only added or removed by changing `num_bands`; added bands start as disabled flat bells.  Settings left out keep their values,
+/// and settings past the last band are ignored
+pub struct ParametricEqNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    sampling_freq: f32,
+    buffer_size: usize,
+    smoothing_coefficient: f32,
+    bands: Vec<EqBandState>,
+    parameter_names: Vec<&'static str>
+}
+
+impl ParametricEqNode {
+    pub fn new(bands: Vec<EqBand>) -> ParametricEqNode {
+        let mut eq_node = ParametricEqNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_channels: 1,
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            smoothing_coefficient: 0.0,
+            bands: vec![],
+            parameter_names: vec![]
+        };
+        eq_node.set_bands(&bands);
+
+        eq_node
+    }
+
+    /// Change the number of channels equalised.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+        for band in &mut self.bands {
+            band.channel_states = vec![[SvfState::default(); MAX_EQ_SECTIONS]; self.num_channels];
+        }
+    }
+
+    pub fn get_bands(&self) -> Vec<EqBand> {
+        self.bands.iter().map(|band| band.band).collect()
+    }
+
+    /// Replace the settings of all bands.  Bands that already existed glide to their new settings
+    pub fn set_bands(&mut self, bands: &[EqBand]) {
+        self.bands.truncate(bands.len());
+
+        for (index, band) in bands.iter().enumerate() {
+            let band = EqBand { slope: EqBand::clamp_slope(band.slope), ..*band };
+
+            match self.bands.get_mut(index) {
+                Some(band_state) => band_state.set_band(band),
+                None => {
+                    let mut band_state = EqBandState::new(band, self.num_channels);
+                    band_state.update_sections(self.sampling_freq);
+                    self.bands.push(band_state);
+                }
+            }
+        }
+
+        //  Before the first block there is nothing to glide from
+        for band_state in &mut self.bands {
+            if self.buffer_size == 0 {
+                band_state.snap_to_targets();
+            }
+            band_state.update_sections(self.sampling_freq);
+        }
+
+        self.parameter_names = vec!["num_bands"];
+        for _ in 0..self.bands.len() {
+            self.parameter_names.extend_from_slice(&EQ_BAND_PARAMETER_NAMES);
+        }
+    }
+
+    /// Get the combined magnitude (linear) and phase (radians, wrapped to [-pi, pi]) of all enabled bands at each of `frequencies`, for the target settings.
+    /// Meant for drawing EQ curves; it does not touch the audio processing state
+    pub fn get_response(&self, frequencies: &[f32]) -> Vec<(f32, f32)> {
+        let mut sections = [SvfCoefficients::identity(); MAX_EQ_SECTIONS];
+
+        let mut responses = vec![(1.0f64, 0.0f64); frequencies.len()];
+        for band_state in self.bands.iter().filter(|band_state| band_state.band.enabled) {
+            let band = band_state.band;
+            let num_sections = band.design(band.frequency, band.gain_db, band.q.max(0.01), self.sampling_freq, &mut sections);
+
+            for (response, frequency) in responses.iter_mut().zip(frequencies) {
+                for section in &sections[..num_sections] {
+                    let (real, imaginary) = section.response(*frequency, self.sampling_freq);
+                    *response = (response.0 * real - response.1 * imaginary, response.0 * imaginary + response.1 * real);
+                }
+            }
+        }
+
+        responses.into_iter().map(|(real, imaginary)| (real.hypot(imaginary) as f32, imaginary.atan2(real) as f32)).collect()
+    }
+}
+
+impl AudioNode for ParametricEqNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.smoothing_coefficient = (-1.0 / (PARAMETER_SMOOTHING_TIME * self.sampling_freq)).exp();
+
+        for band_state in &mut self.bands {
+            band_state.snap_to_targets();
+            band_state.update_sections(self.sampling_freq);
+        }
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("parametric_eq")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        let num_bands = match parameters.first() {
+            Some(num_bands) => num_bands.max(0.0).round() as usize,
+            None => return
+        };
+
+        let mut bands = self.get_bands();
+        bands.resize(num_bands, EqBand { enabled: false, ..EqBand::new(EqBandType::Bell, 1_000.0, 0.0, 0.707, 12) });
+
+        for (index, value) in parameters[1..].iter().enumerate().take(num_bands * EQ_BAND_PARAMETER_NAMES.len()) {
+            bands[index / EQ_BAND_PARAMETER_NAMES.len()].set_parameter(index % EQ_BAND_PARAMETER_NAMES.len(), *value);
+        }
+
+        self.set_bands(&bands);
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        let mut parameters = vec![self.bands.len() as f32];
+        for band_state in &self.bands {
+            let band = band_state.band;
+            parameters.extend_from_slice(&[band.band_type.to_parameter(), band.frequency, band.gain_db, band.q, band.slope as f32, if band.enabled { 1.0 } else { 0.0 }]);
+        }
+
+        parameters
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &self.parameter_names
+    }
+
+    fn reset(&mut self) {
+        for band_state in &mut self.bands {
+            band_state.channel_states.iter_mut().for_each(|states| *states = [SvfState::default(); MAX_EQ_SECTIONS]);
+            band_state.snap_to_targets();
+            band_state.update_sections(self.sampling_freq);
+        }
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+
+        for band_state in self.bands.iter_mut().filter(|band_state| band_state.band.enabled) {
+            for index in 0..buffer_size {
+                if !band_state.is_settled() {
+                    band_state.log_frequency.next(self.smoothing_coefficient);
+                    band_state.gain_db.next(self.smoothing_coefficient);
+                    band_state.q.next(self.smoothing_coefficient);
+                    band_state.update_sections(self.sampling_freq);
+                }
+
+                let num_sections = band_state.num_sections;
+                for (channel, states) in band_state.channel_states.iter_mut().enumerate() {
+                    let sample = &mut buffer[channel * buffer_size + index];
+
+                    let mut value = *sample as f64;
+                    for (state, section) in states.iter_mut().zip(band_state.sections.iter()).take(num_sections) {
+                        value = state.tick(section, value);
+                    }
+                    *sample = value as f32;
+                }
+            }
+        }
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_sine(filter: &mut BiquadNode, frequency: f32, num_samples: usize) -> f32 {
+        filter.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: num_samples });
+        let mut buffer: Vec<f32> = (0..num_samples).map(|index| (2.0 * std::f32::consts::PI * frequency * index as f32 / 48_000.0).sin()).collect();
+        filter.process_block(&mut buffer);
+
+        //  Peak of the second half, once the filter has settled
+        buffer[num_samples / 2..].iter().fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn filter_cookbook_responses() {
+        let cases = [
+            (FilterType::LowPass, 100.0, 1.0),
+            (FilterType::LowPass, 10_000.0, 0.0),
+            (FilterType::HighPass, 100.0, 0.0),
+            (FilterType::HighPass, 10_000.0, 1.0),
+            (FilterType::BandPass, 1_000.0, 1.0),
+            (FilterType::BandPass, 10_000.0, 0.12),
+            (FilterType::Notch, 1_000.0, 0.0),
+            (FilterType::AllPass, 300.0, 1.0),
+            (FilterType::Peak, 1_000.0, 2.0),
+            (FilterType::LowShelf, 50.0, 2.0),
+            (FilterType::HighShelf, 15_000.0, 2.0)
+        ];
+
+        for (filter_type, frequency, expected) in cases {
+            //  1 kHz with a Q of 0.707 and 6 dB of gain for the types that use it
+            let mut filter = BiquadNode::new(filter_type, 1_000.0, std::f32::consts::FRAC_1_SQRT_2, 6.0206);
+            let peak = filter_sine(&mut filter, frequency, 9_600);
+            let (magnitude, _) = filter.get_response(frequency);
+
+            assert!((peak - expected).abs() < 0.05, "{:?} at {} Hz: {}", filter_type, frequency, peak);
+            assert!((magnitude - expected).abs() < 0.05, "{:?} at {} Hz: response {}", filter_type, frequency, magnitude);
+        }
+
+        //  A second order low-pass is 3 dB down with a quarter turn of phase at its cutoff
+        let filter = BiquadNode::new(FilterType::LowPass, 1_000.0, std::f32::consts::FRAC_1_SQRT_2, 0.0);
+        let (magnitude, phase) = filter.get_response(1_000.0);
+        assert!((magnitude - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+        assert!((phase + std::f32::consts::FRAC_PI_2).abs() < 0.01);
+    }
+
+    #[test]
+    fn modulate_cutoff_quickly() {
+        let mut filter = BiquadNode::new(FilterType::LowPass, 1_000.0, 10.0, 0.0);
+        filter.set_number_of_channels(2);
+        filter.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 16 });
+
+        //  Sweep a resonant filter between the extremes every 16 samples
+        let mut peak: f32 = 0.0;
+        for block in 0..1_000 {
+            let frequency = if block % 2 == 0 { 20.0 } else { 20_000.0 };
+            filter.change_parameters(&[0.0, frequency, 10.0]);
+
+            let mut buffer: Vec<f32> = (0..32).map(|index| if index % 3 == 0 { 1.0 } else { -0.5 }).collect();
+            filter.process_block(&mut buffer);
+            peak = buffer.iter().fold(peak, |peak, sample| sample.abs().max(peak));
+        }
+
+        assert!(peak.is_finite() && peak < 20.0);
+        assert_eq!(filter.get_parameters(), vec![0.0, 20_000.0, 10.0, 0.0]);
+    }
+
+    #[test]
+    fn equalise_with_multiple_bands() {
+        let mut eq = ParametricEqNode::new(vec![
+            EqBand::new(EqBandType::HighPass, 100.0, 0.0, 0.707, 48),
+            EqBand::new(EqBandType::Bell, 1_000.0, 6.0, 2.0, 12),
+            EqBand::new(EqBandType::LowPass, 8_000.0, 0.0, 0.707, 13)
+        ]);
+        eq.set_number_of_channels(2);
+        eq.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 9_600 });
+
+        //  The slope is rounded to whole sections of 12 dB/oct
+        assert_eq!(eq.get_bands()[2].slope, 12);
+
+        let to_db = |magnitude: f32| 20.0 * magnitude.log10();
+        let response = eq.get_response(&[25.0, 50.0, 1_000.0, 16_000.0]);
+        assert!((to_db(response[0].0) - to_db(response[1].0) + 48.0).abs() < 2.0);
+        assert!((to_db(response[2].0) - 6.0).abs() < 0.1);
+        assert!(to_db(response[3].0) < -10.0);
+
+        //  The processed signal follows the response, on both channels
+        let frequency = 1_000.0;
+        let mut buffer: Vec<f32> = (0..2 * 9_600).map(|index| (2.0 * std::f32::consts::PI * frequency * (index % 9_600) as f32 / 48_000.0).sin()).collect();
+        eq.process_block(&mut buffer);
+        for channel in 0..2 {
+            let peak = buffer[channel * 9_600 + 4_800..(channel + 1) * 9_600].iter().fold(0.0, |peak: f32, sample| sample.abs().max(peak));
+            assert!((to_db(peak) - 6.0).abs() < 0.2);
+        }
+
+        //  Disabled bands drop out of the response.  Leaving out the settings of later bands leaves them as they are
+        let mut parameters = eq.get_parameters();
+        assert_eq!(parameters.len(), 19);
+        parameters[12] = 0.0;
+        eq.change_parameters(&parameters[..13]);
+        assert_eq!(eq.get_bands().len(), 3);
+        assert!(!eq.get_bands()[1].enabled);
+        assert!(to_db(eq.get_response(&[1_000.0])[0].0).abs() < 0.1);
+
+        //  A partial band still updates the settings it covers
+        eq.change_parameters(&[3.0, 3.0, 100.0, 0.0, 0.707, 48.0, 1.0, 0.0, 2_000.0]);
+        assert_eq!(eq.get_bands()[1].frequency, 2_000.0);
+        assert_eq!(eq.get_bands()[1].gain_db, 6.0);
+
+        //  Only `num_bands` adds or removes bands
+        eq.change_parameters(&[]);
+        assert_eq!(eq.get_bands().len(), 3);
+        eq.change_parameters(&[2.0]);
+        assert_eq!(eq.get_bands().len(), 2);
+        assert_eq!(eq.get_parameter_names().len(), 13);
+        eq.change_parameters(&[4.0]);
+        assert_eq!(eq.get_bands().len(), 4);
+        assert!(!eq.get_bands()[3].enabled);
+    }
+}
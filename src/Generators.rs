@@ -0,0 +1,1089 @@
+//! # Generators
+//! Nodes that produce audio of their own rather than processing an input.
+//!
+//! `OscillatorNode` generates band-limited sine, saw, pulse and triangle waves.  Its optional inputs modulate frequency, phase and amplitude,
+//! hard-sync it to another oscillator or frequency-modulate it at audio rate.
+//!
+//! `NoiseNode` generates white, pink or brown noise from a seeded generator, so the same seed always produces the same samples.
+//!
+//! `FilePlayerNode` plays a WAV or AIFF file.  The file is never loaded whole: a background thread streams it from disk into a ring buffer
+//! a little ahead of playback, so files of any length can be played without allocating or touching the disk on the audio thread.
+//!
+//! ```no_run
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::Generators::FilePlayerNode;
+//!
+//! let mut player = FilePlayerNode::open("drums.wav")?;
+//! player.set_number_of_output_channels(1);
+//!
+//! let mut graph = AudioGraph::new();
+//! let player_id = graph.add_new_node(Box::new(player))?;
+//! graph.connect_node_to_output(player_id)?;
+//!
+//! //  Loop bars two to four of a 120 BPM file at half speed
+//! graph.set_node_parameters(player_id, &[1.0, 2.0, 8.0, 0.0, 0.5])?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 })?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters, Error, ErrorCodes};
+use super::AudioFile::{AudioFileInfo, AudioFileReader};
+
+/// Number of frames the disk thread keeps buffered ahead of playback
+const STREAM_BUFFER_FRAMES: usize = 1 << 16;
+
+/// Number of frames the disk thread reads from the file at a time
+const STREAM_CHUNK_FRAMES: usize = 4096;
+
+/// How long the disk thread sleeps when the buffer is full, unless playback wakes it up earlier
+const STREAM_IDLE_TIME: Duration = Duration::from_millis(5);
+
+/// Number of frames the interpolator reads ahead of the playback position
+const INTERPOLATION_LOOKAHEAD: usize = 3;
+
+const OSCILLATOR_PARAMETER_NAMES: [&str; 6] = ["waveform", "frequency", "amplitude", "phase", "pulse_width", "fm_depth"];
+
+const NOISE_PARAMETER_NAMES: [&str; 3] = ["color", "amplitude", "seed"];
+/// Largest noise seed.  Parameters are `f32`, which hold every whole number up to 2^24 exactly
+const MAX_NOISE_SEED: u32 = 1 << 24;
+
+const FILE_PLAYER_PARAMETER_NAMES: [&str; 5] = ["looping", "loop_start", "loop_end", "start_offset", "rate"];
+
+
+/// State shared between a `FilePlayerNode` and its disk thread.
+/// The ring buffer is single-producer single-consumer: only the disk thread advances `write_frame` and only the audio thread advances `read_frame`.
+/// Both count frames since the last seek and only ever grow, so the number of buffered frames is simply their difference
+struct StreamState {
+    samples: Vec<AtomicU32>,
+    num_channels: usize,
+    read_frame: AtomicUsize,
+    write_frame: AtomicUsize,
+    end_of_stream: AtomicBool,
+
+    //  A seek is requested by storing the target frame and incrementing `seek_request`.
+    //  The audio thread stops reading until the disk thread has emptied the buffer and echoed the request in `seek_done`
+    seek_frame: AtomicUsize,
+    seek_request: AtomicUsize,
+    seek_done: AtomicUsize,
+
+    looping: AtomicBool,
+    loop_start: AtomicUsize,
+    loop_end: AtomicUsize,
+    shutdown: AtomicBool
+}
+
+/// Body of the disk thread: keep the ring buffer filled from the file, wrapping around the loop points while looping
+fn stream_from_disk(mut reader: AudioFileReader, stream: Arc<StreamState>) {
+    let num_channels = stream.num_channels;
+    let num_file_frames = reader.get_info().num_frames;
+    let mut chunk = vec![0.0; STREAM_CHUNK_FRAMES * num_channels];
+    let mut handled_request = 0;
+
+    while !stream.shutdown.load(Ordering::Acquire) {
+        let request = stream.seek_request.load(Ordering::Acquire);
+        if request != handled_request {
+            //  The audio thread is not reading while a seek is pending, so the buffer can be emptied from this side
+            stream.write_frame.store(stream.read_frame.load(Ordering::Acquire), Ordering::Release);
+            let seek_failed = reader.seek(stream.seek_frame.load(Ordering::Acquire)).is_err();
+            stream.end_of_stream.store(seek_failed, Ordering::Release);
+
+            handled_request = request;
+            stream.seek_done.store(request, Ordering::Release);
+        }
+
+        if stream.end_of_stream.load(Ordering::Acquire) {
+            thread::park_timeout(STREAM_IDLE_TIME);
+            continue;
+        }
+
+        let write_frame = stream.write_frame.load(Ordering::Relaxed);
+        let num_free_frames = STREAM_BUFFER_FRAMES - (write_frame - stream.read_frame.load(Ordering::Acquire));
+        if num_free_frames < STREAM_CHUNK_FRAMES.min(STREAM_BUFFER_FRAMES) {
+            thread::park_timeout(STREAM_IDLE_TIME);
+            continue;
+        }
+
+        let loop_start = stream.loop_start.load(Ordering::Relaxed);
+        let loop_end = stream.loop_end.load(Ordering::Relaxed).min(num_file_frames);
+        let looping = stream.looping.load(Ordering::Relaxed) && loop_start < loop_end;
+
+        //  Playback that starts past the loop end plays to the end of the file before it wraps to the loop start
+        let position = reader.get_position();
+        let region_end = if looping && position < loop_end { loop_end } else { num_file_frames };
+
+        if position >= region_end || (looping && position == loop_end) {
+            if !looping || reader.seek(loop_start).is_err() {
+                stream.end_of_stream.store(true, Ordering::Release);
+            }
+            continue;
+        }
+
+        let num_frames = STREAM_CHUNK_FRAMES.min(region_end - position);
+        let num_frames_read = reader.read_interleaved(&mut chunk[..num_frames * num_channels]).unwrap_or_default();
+
+        if num_frames_read == 0 {
+            stream.end_of_stream.store(true, Ordering::Release);
+            continue;
+        }
+
+        for frame in 0..num_frames_read {
+            let ring_offset = ((write_frame + frame) % STREAM_BUFFER_FRAMES) * num_channels;
+            for channel in 0..num_channels {
+                stream.samples[ring_offset + channel].store(chunk[frame * num_channels + channel].to_bits(), Ordering::Relaxed);
+            }
+        }
+
+        stream.write_frame.store(write_frame + num_frames_read, Ordering::Release);
+    }
+}
+
+/// 4-point, 3rd-order Hermite interpolation between `x[1]` and `x[2]`.  Returns `x[1]` exactly when `t` is 0
+pub(crate) fn interpolate_hermite(x: &[f32; 4], t: f32) -> f32 {
+    let c1 = 0.5 * (x[2] - x[0]);
+    let c2 = x[0] - 2.5 * x[1] + 2.0 * x[2] - 0.5 * x[3];
+    let c3 = 0.5 * (x[3] - x[0]) + 1.5 * (x[1] - x[2]);
+
+    ((c3 * t + c2) * t + c1) * t + x[1]
+}
+
+
+/// Plays a WAV or AIFF file, streamed from disk on a background thread.
+/// Closing the gate with `set_gate()` stops playback, opening it (or opening it again once the file has ended) starts playback from `start_offset`.
+///
+/// Parameters, in order:
+/// - `looping`: 1 to repeat the section between `loop_start` and `loop_end`
+/// - `loop_start`, `loop_end`: loop points in seconds of file time.  A `loop_end` of 0 means the end of the file
+/// - `start_offset`: where playback starts, in seconds of file time
+/// - `rate`: playback speed, 1 being the original speed and pitch
+///
+/// Files whose sampling frequency differs from the graph's are resampled with Hermite interpolation, as are rates other than 1.
+/// Loop point changes take effect once the frames already buffered ahead have been played.
+/// The node has as many output channels as the file unless changed with `set_number_of_output_channels()`.
+/// It has no registry type name, since patches cannot hold the path of the file
+pub struct FilePlayerNode {
+    node_type: AudioNodeType,
+    info: AudioFileInfo,
+    num_output_channels: usize,
+    stream: Arc<StreamState>,
+    disk_thread: Option<JoinHandle<()>>,
+    wait_for_disk: bool,
+    seek_request: usize,
+
+    playing: bool,
+    looping: bool,
+    loop_start: f32,
+    loop_end: f32,
+    start_offset: f32,
+    rate: f32,
+
+    buffer_size: usize,
+    sampling_freq: f32,
+    step: f64,
+    fraction: f64,
+    num_frames_needed: usize,
+    num_tail_frames: usize,
+    finished: bool,
+    history: Vec<[f32; 4]>,
+    frame_values: Vec<f32>
+}
+
+impl FilePlayerNode {
+    /// Open a file and start streaming it from the beginning.  Playback starts as soon as the node is processed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FilePlayerNode, Error> {
+        let reader = AudioFileReader::open(path)?;
+        let info = reader.get_info();
+
+        let stream = Arc::new(StreamState {
+            samples: (0..STREAM_BUFFER_FRAMES * info.num_channels).map(|_| AtomicU32::new(0)).collect(),
+            num_channels: info.num_channels,
+            read_frame: AtomicUsize::new(0),
+            write_frame: AtomicUsize::new(0),
+            end_of_stream: AtomicBool::new(false),
+            seek_frame: AtomicUsize::new(0),
+            seek_request: AtomicUsize::new(0),
+            seek_done: AtomicUsize::new(0),
+            looping: AtomicBool::new(false),
+            loop_start: AtomicUsize::new(0),
+            loop_end: AtomicUsize::new(info.num_frames),
+            shutdown: AtomicBool::new(false)
+        });
+
+        let thread_stream = Arc::clone(&stream);
+        let disk_thread = match thread::Builder::new().name(String::from("file-player-disk")).spawn(move || stream_from_disk(reader, thread_stream)) {
+            Ok(disk_thread) => disk_thread,
+            Err(e) => return Err(Error {
+                code: ErrorCodes::FileAccessFailed,
+                message: format!("Could not start the disk thread of a file player: {}", e)
+            })
+        };
+
+        Ok(FilePlayerNode {
+            node_type: AudioNodeType::Generator,
+            info,
+            num_output_channels: info.num_channels,
+            stream,
+            disk_thread: Some(disk_thread),
+            wait_for_disk: false,
+            seek_request: 0,
+            playing: true,
+            looping: false,
+            loop_start: 0.0,
+            loop_end: 0.0,
+            start_offset: 0.0,
+            rate: 1.0,
+            buffer_size: 0,
+            sampling_freq: info.sampling_freq,
+            step: 1.0,
+            fraction: 0.0,
+            num_frames_needed: INTERPOLATION_LOOKAHEAD,
+            num_tail_frames: 0,
+            finished: false,
+            history: vec![[0.0; 4]; info.num_channels],
+            frame_values: vec![0.0; info.num_channels]
+        })
+    }
+
+    pub fn get_file_info(&self) -> AudioFileInfo {
+        self.info
+    }
+
+    /// Change the number of output channels.  A mono output mixes all file channels down; otherwise output channel `c` plays file channel `c` modulo the number of file channels.
+    /// Must be called before the node is added to a graph
+    pub fn set_number_of_output_channels(&mut self, num_channels: usize) {
+        self.num_output_channels = num_channels.max(1);
+    }
+
+    /// Make the audio thread wait for the disk thread instead of playing silence when the buffer runs dry.
+    /// Meant for offline rendering, which runs faster than the disk thread can keep up with; never enable it for real-time playback
+    pub fn set_wait_for_disk(&mut self, wait_for_disk: bool) {
+        self.wait_for_disk = wait_for_disk;
+    }
+
+    /// Check whether the file has played to its end (it never does while looping)
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn seconds_to_file_frames(&self, seconds: f32) -> usize {
+        ((seconds.max(0.0) * self.info.sampling_freq).round() as usize).min(self.info.num_frames)
+    }
+
+    fn update_step(&mut self) {
+        self.step = (self.info.sampling_freq as f64 / self.sampling_freq as f64) * self.rate as f64;
+    }
+
+    fn update_loop_points(&self) {
+        let loop_end = if self.loop_end > 0.0 { self.seconds_to_file_frames(self.loop_end) } else { self.info.num_frames };
+
+        self.stream.loop_start.store(self.seconds_to_file_frames(self.loop_start), Ordering::Relaxed);
+        self.stream.loop_end.store(loop_end, Ordering::Relaxed);
+        self.stream.looping.store(self.looping, Ordering::Relaxed);
+    }
+
+    /// Restart playback from `start_offset`.  Until the disk thread has refilled the buffer, the node plays silence (or waits, see `set_wait_for_disk()`)
+    fn restart(&mut self) {
+        self.stream.seek_frame.store(self.seconds_to_file_frames(self.start_offset), Ordering::Release);
+        self.seek_request += 1;
+        self.stream.seek_request.store(self.seek_request, Ordering::Release);
+        self.wake_disk_thread();
+
+        for frame in &mut self.history {
+            *frame = [0.0; 4];
+        }
+        self.fraction = 0.0;
+        self.num_frames_needed = INTERPOLATION_LOOKAHEAD;
+        self.num_tail_frames = 0;
+        self.finished = false;
+    }
+
+    fn wake_disk_thread(&self) {
+        if let Some(disk_thread) = &self.disk_thread {
+            disk_thread.thread().unpark();
+        }
+    }
+
+    /// Move the next file frame into the interpolation history.  Past the end of the file, silence is moved in.
+    /// Returns false if no frame is available yet
+    fn pull_frame(&mut self) -> bool {
+        let num_channels = self.info.num_channels;
+
+        loop {
+            if self.stream.seek_done.load(Ordering::Acquire) == self.seek_request {
+                //  The end of the stream is flagged after the last frame was written, so it has to be checked first
+                let end_of_stream = self.stream.end_of_stream.load(Ordering::Acquire);
+                let read_frame = self.stream.read_frame.load(Ordering::Relaxed);
+
+                if read_frame < self.stream.write_frame.load(Ordering::Acquire) {
+                    let ring_offset = (read_frame % STREAM_BUFFER_FRAMES) * num_channels;
+                    for (channel, history) in self.history.iter_mut().enumerate() {
+                        let sample = f32::from_bits(self.stream.samples[ring_offset + channel].load(Ordering::Relaxed));
+                        *history = [history[1], history[2], history[3], sample];
+                    }
+
+                    self.stream.read_frame.store(read_frame + 1, Ordering::Release);
+                    return true;
+                }
+
+                if end_of_stream {
+                    for history in &mut self.history {
+                        *history = [history[1], history[2], history[3], 0.0];
+                    }
+
+                    self.num_tail_frames += 1;
+                    self.finished = self.num_tail_frames > INTERPOLATION_LOOKAHEAD;
+                    return true;
+                }
+            }
+
+            if !self.wait_for_disk {
+                return false;
+            }
+
+            self.wake_disk_thread();
+            thread::yield_now();
+        }
+    }
+}
+
+impl AudioNode for FilePlayerNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.update_step();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        0
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        None
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_output_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(looping) = parameters.first() { self.looping = *looping >= 0.5; }
+        if let Some(loop_start) = parameters.get(1) { self.loop_start = loop_start.max(0.0); }
+        if let Some(loop_end) = parameters.get(2) { self.loop_end = loop_end.max(0.0); }
+        if let Some(start_offset) = parameters.get(3) { self.start_offset = start_offset.max(0.0); }
+        if let Some(rate) = parameters.get(4) { self.rate = rate.max(0.0); }
+
+        self.update_loop_points();
+        self.update_step();
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![
+            if self.looping { 1.0 } else { 0.0 },
+            self.loop_start,
+            self.loop_end,
+            self.start_offset,
+            self.rate
+        ]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &FILE_PLAYER_PARAMETER_NAMES
+    }
+
+    fn set_gate(&mut self, open: bool) {
+        let was_stopped = !self.playing || self.finished;
+        self.playing = open;
+
+        if self.playing && was_stopped {
+            self.restart();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.restart();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let num_file_channels = self.info.num_channels;
+        buffer[..buffer_size * self.num_output_channels].fill(0.0);
+
+        if !self.playing {
+            return buffer;
+        }
+
+        for frame in 0..buffer_size {
+            if self.finished {
+                break;
+            }
+
+            //  Underruns play silence without moving the playback position
+            while self.num_frames_needed > 0 {
+                if !self.pull_frame() {
+                    break;
+                }
+                self.num_frames_needed -= 1;
+            }
+
+            if self.num_frames_needed > 0 {
+                continue;
+            }
+
+            let t = self.fraction as f32;
+            for (value, history) in self.frame_values.iter_mut().zip(self.history.iter()) {
+                *value = interpolate_hermite(history, t);
+            }
+
+            if self.num_output_channels == 1 {
+                buffer[frame] = self.frame_values.iter().sum::<f32>() / num_file_channels as f32;
+            } else {
+                for channel in 0..self.num_output_channels {
+                    buffer[channel * buffer_size + frame] = self.frame_values[channel % num_file_channels];
+                }
+            }
+
+            self.fraction += self.step;
+            let num_whole_frames = self.fraction.floor();
+            self.fraction -= num_whole_frames;
+            self.num_frames_needed = num_whole_frames as usize;
+        }
+
+        self.wake_disk_thread();
+        buffer
+    }
+}
+
+impl Drop for FilePlayerNode {
+    fn drop(&mut self) {
+        self.stream.shutdown.store(true, Ordering::Release);
+
+        if let Some(disk_thread) = self.disk_thread.take() {
+            disk_thread.thread().unpark();
+            let _ = disk_thread.join();
+        }
+    }
+}
+
+
+
+/// Waveforms of `OscillatorNode`.  Stored in its `waveform` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveform {
+    Sine,
+    Saw,
+    /// Pulse wave; a pulse width of 0.5 gives a square wave
+    Pulse,
+    Triangle
+}
+
+impl Waveform {
+    fn from_parameter(value: f32) -> Waveform {
+        match value.round() as i32 {
+            1 => Waveform::Saw,
+            2 => Waveform::Pulse,
+            3 => Waveform::Triangle,
+            _ => Waveform::Sine
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            Waveform::Sine => 0.0,
+            Waveform::Saw => 1.0,
+            Waveform::Pulse => 2.0,
+            Waveform::Triangle => 3.0
+        }
+    }
+}
+
+/// Input ports of `OscillatorNode`.  Each port is mono and has a channel of its own
+pub const OSCILLATOR_FREQUENCY_INPUT: usize = 0;
+pub const OSCILLATOR_PHASE_INPUT: usize = 1;
+pub const OSCILLATOR_AMPLITUDE_INPUT: usize = 2;
+pub const OSCILLATOR_SYNC_INPUT: usize = 3;
+pub const OSCILLATOR_FM_INPUT: usize = 4;
+
+/// Polynomial approximation of the residual between a band-limited and a naive unit step (PolyBLEP), for a step at phase 0.
+/// `t` is the phase in [0, 1) and `dt` the phase increment per sample
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Integrated PolyBLEP (PolyBLAMP): the residual of a band-limited change of slope of one per sample, at phase 0
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = 1.0 - t / dt;
+        x * x * x / 6.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt + 1.0;
+        x * x * x / 6.0
+    } else {
+        0.0
+    }
+}
+
+/// Band-limited oscillator.  Saw and pulse edges are smoothed with PolyBLEP and the corners of the triangle with PolyBLAMP,
+/// which keeps aliasing low without oversampling.  Phase resets by the sync input are not band-limited.
+///
+/// Parameters, in order:
+/// - `waveform`: 0 sine, 1 saw, 2 pulse, 3 triangle (see `Waveform`)
+/// - `frequency`: in Hz
+/// - `amplitude`: peak level
+/// - `phase`: phase offset in cycles
+/// - `pulse_width`: fraction of the cycle the pulse wave is high, between 0.01 and 0.99
+/// - `fm_depth`: frequency deviation per unit of the FM input, as a fraction of `frequency`
+///
+/// The signals connected to the inputs are added to the parameters: the frequency input in Hz, the phase input in cycles and
+/// the amplitude input to the amplitude (so an envelope into the amplitude input of an oscillator with an amplitude of 0 acts as a VCA).
+/// A rising edge through zero on the sync input restarts the cycle.  The FM input modulates the frequency linearly and may push it through zero
+pub struct OscillatorNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    waveform: Waveform,
+    frequency: f32,
+    amplitude: f32,
+    phase_offset: f32,
+    pulse_width: f32,
+    fm_depth: f32,
+    sampling_freq: f32,
+    buffer_size: usize,
+    phase: f64,
+    previous_sync: f32
+}
+
+impl OscillatorNode {
+    pub fn new(waveform: Waveform, frequency: f32) -> OscillatorNode {
+        OscillatorNode {
+            node_type: AudioNodeType::Generator,
+            num_inputs: 5,
+            next_available_input: 0,
+            waveform,
+            frequency,
+            amplitude: 1.0,
+            phase_offset: 0.0,
+            pulse_width: 0.5,
+            fm_depth: 0.0,
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            phase: 0.0,
+            previous_sync: 0.0
+        }
+    }
+
+    /// Compute one sample of the waveform at phase `t` with a phase increment of `dt` per sample
+    fn generate(&self, t: f64, dt: f64) -> f64 {
+        match self.waveform {
+            Waveform::Sine => (std::f64::consts::TAU * t).sin(),
+
+            Waveform::Saw => 2.0 * t - 1.0 - poly_blep(t, dt),
+
+            Waveform::Pulse => {
+                let pulse_width = self.pulse_width.clamp(0.01, 0.99) as f64;
+                let naive = if t < pulse_width { 1.0 } else { -1.0 };
+                naive + poly_blep(t, dt) - poly_blep((t + 1.0 - pulse_width).fract(), dt)
+            },
+
+            //  The slope changes by 8 per cycle at both corners, rising at phase 0 and falling at phase 0.5
+            Waveform::Triangle => {
+                let naive = if t < 0.5 { 4.0 * t - 1.0 } else { 3.0 - 4.0 * t };
+                naive + 8.0 * dt * (poly_blamp(t, dt) - poly_blamp((t + 0.5).fract(), dt))
+            }
+        }
+    }
+}
+
+impl AudioNode for OscillatorNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("oscillator")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_input_port_channel(&self, port: usize) -> usize {
+        port
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(waveform) = parameters.first() { self.waveform = Waveform::from_parameter(*waveform); }
+        if let Some(frequency) = parameters.get(1) { self.frequency = *frequency; }
+        if let Some(amplitude) = parameters.get(2) { self.amplitude = *amplitude; }
+        if let Some(phase_offset) = parameters.get(3) { self.phase_offset = *phase_offset; }
+        if let Some(pulse_width) = parameters.get(4) { self.pulse_width = pulse_width.clamp(0.01, 0.99); }
+        if let Some(fm_depth) = parameters.get(5) { self.fm_depth = *fm_depth; }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.waveform.to_parameter(), self.frequency, self.amplitude, self.phase_offset, self.pulse_width, self.fm_depth]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &OSCILLATOR_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+        self.previous_sync = 0.0;
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let input = |port: usize, index: usize| port * buffer_size + index;
+
+        for index in 0..buffer_size {
+            let sync = buffer[input(OSCILLATOR_SYNC_INPUT, index)];
+            if sync > 0.0 && self.previous_sync <= 0.0 {
+                self.phase = 0.0;
+            }
+            self.previous_sync = sync;
+
+            let frequency = self.frequency + buffer[input(OSCILLATOR_FREQUENCY_INPUT, index)]
+                + buffer[input(OSCILLATOR_FM_INPUT, index)] * self.fm_depth * self.frequency;
+            let increment = (frequency / self.sampling_freq) as f64;
+            let dt = increment.abs().min(0.5);
+
+            let t = (self.phase + (self.phase_offset + buffer[input(OSCILLATOR_PHASE_INPUT, index)]) as f64).rem_euclid(1.0);
+            let amplitude = self.amplitude + buffer[input(OSCILLATOR_AMPLITUDE_INPUT, index)];
+
+            //  Channel 0 doubles as the frequency input, which has already been read for this sample
+            buffer[index] = (self.generate(t, dt) * amplitude as f64) as f32;
+            self.phase = (self.phase + increment).rem_euclid(1.0);
+        }
+
+        buffer
+    }
+}
+
+
+
+/// Spectra of `NoiseNode`.  Stored in its `color` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseColor {
+    /// Equal power per Hz
+    White,
+    /// Equal power per octave (-3 dB per octave)
+    Pink,
+    /// Integrated white noise (-6 dB per octave)
+    Brown
+}
+
+impl NoiseColor {
+    fn from_parameter(value: f32) -> NoiseColor {
+        match value.round() as i32 {
+            1 => NoiseColor::Pink,
+            2 => NoiseColor::Brown,
+            _ => NoiseColor::White
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            NoiseColor::White => 0.0,
+            NoiseColor::Pink => 1.0,
+            NoiseColor::Brown => 2.0
+        }
+    }
+}
+
+/// Deterministic noise generator.  Samples come from a xorshift generator seeded by the `seed` parameter, so renders are bit-identical across runs and platforms.
+/// Pink noise is white noise through Paul Kellet's pinking filter, brown noise is white noise through a leaky integrator.
+///
+/// Parameters, in order:
+/// - `color`: 0 white, 1 pink, 2 brown (see `NoiseColor`)
+/// - `amplitude`: the level, with white noise peaking at `amplitude`
+/// - `seed`: a whole number up to 2^24.  Changing it, or resetting the node, restarts the sequence
+pub struct NoiseNode {
+    node_type: AudioNodeType,
+    color: NoiseColor,
+    amplitude: f32,
+    seed: u32,
+    buffer_size: usize,
+    state: u32,
+    pink_state: [f32; 7],
+    brown_state: f32
+}
+
+impl NoiseNode {
+    /// Create a noise generator.  Seeds above 2^24 are clamped so that they survive being saved as a parameter
+    pub fn new(color: NoiseColor, seed: u32) -> NoiseNode {
+        let mut noise_node = NoiseNode {
+            node_type: AudioNodeType::Generator,
+            color,
+            amplitude: 1.0,
+            seed: seed.min(MAX_NOISE_SEED),
+            buffer_size: 0,
+            state: 0,
+            pink_state: [0.0; 7],
+            brown_state: 0.0
+        };
+        noise_node.reseed();
+
+        noise_node
+    }
+
+    fn reseed(&mut self) {
+        //  Scramble the seed (SplitMix32 finaliser) so that neighbouring seeds give unrelated sequences.  Xorshift must never be in state 0
+        let mut state = self.seed.wrapping_add(0x9E37_79B9);
+        state = (state ^ (state >> 16)).wrapping_mul(0x85EB_CA6B);
+        state = (state ^ (state >> 13)).wrapping_mul(0xC2B2_AE35);
+        state ^= state >> 16;
+
+        self.state = if state == 0 { 0x6D2B_79F5 } else { state };
+        self.pink_state = [0.0; 7];
+        self.brown_state = 0.0;
+    }
+
+    /// Next white noise sample, uniformly distributed in [-1, 1)
+    fn next_white(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        (self.state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let white = self.next_white();
+
+        match self.color {
+            NoiseColor::White => white,
+
+            NoiseColor::Pink => {
+                let b = &mut self.pink_state;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+
+                pink * 0.11
+            },
+
+            NoiseColor::Brown => {
+                self.brown_state = (self.brown_state + 0.02 * white) / 1.02;
+                self.brown_state * 3.5
+            }
+        }
+    }
+}
+
+impl AudioNode for NoiseNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("noise")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        0
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        None
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(color) = parameters.first() { self.color = NoiseColor::from_parameter(*color); }
+        if let Some(amplitude) = parameters.get(1) { self.amplitude = *amplitude; }
+
+        if let Some(seed) = parameters.get(2) {
+            let seed = seed.clamp(0.0, MAX_NOISE_SEED as f32).round() as u32;
+            if seed != self.seed {
+                self.seed = seed;
+                self.reseed();
+            }
+        }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.color.to_parameter(), self.amplitude, self.seed as f32]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &NOISE_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.reseed();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        for sample in buffer[..self.buffer_size].iter_mut() {
+            *sample = self.next_sample() * self.amplitude;
+        }
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioFile::{SampleFormat, WavWriter};
+    use crate::AudioToolbox::AudioGraph;
+    use crate::ModelNodes::TestGenNode;
+
+    fn write_ramp(name: &str, num_frames: usize, sampling_freq: f32) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let samples: Vec<f32> = (0..num_frames).map(|frame| (frame % 1000) as f32 / 1000.0).collect();
+
+        let mut writer = WavWriter::create(&path, 1, sampling_freq, SampleFormat::Float32).ok().unwrap();
+        if writer.write_interleaved(&samples).is_err() { panic!(); }
+        if writer.finalize().is_err() { panic!(); }
+
+        path
+    }
+
+    fn play(player: &mut FilePlayerNode, num_frames: usize) -> Vec<f32> {
+        let mut output = vec![];
+        let mut buffer = vec![0.0; 64];
+
+        while output.len() < num_frames {
+            output.extend_from_slice(player.process_block(&mut buffer));
+        }
+        output.truncate(num_frames);
+
+        output
+    }
+
+    #[test]
+    fn stream_audio_file() {
+        //  Longer than the stream buffer, so the disk thread has to keep refilling it
+        let num_frames = STREAM_BUFFER_FRAMES * 2 + 123;
+        let path = write_ramp("audio_graph_stream_audio_file.wav", num_frames, 1_000.0);
+
+        let mut player = match FilePlayerNode::open(&path) {
+            Ok(player) => player,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        player.set_wait_for_disk(true);
+        player.init(&AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: 64 });
+
+        let output = play(&mut player, num_frames + 64);
+        for (frame, sample) in output.iter().enumerate().take(num_frames) {
+            assert_eq!(*sample, (frame % 1000) as f32 / 1000.0);
+        }
+        assert_eq!(output[num_frames..], [0.0; 64]);
+        assert!(player.is_finished());
+
+        //  Opening the gate again restarts from the start offset
+        player.change_parameters(&[0.0, 0.0, 0.0, 0.5, 1.0]);
+        player.set_gate(true);
+        assert_eq!(play(&mut player, 3), [0.5, 0.501, 0.502]);
+
+        drop(player);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn loop_and_resample_audio_file() {
+        let path = write_ramp("audio_graph_loop_and_resample_audio_file.wav", 100, 1_000.0);
+        let mut player = FilePlayerNode::open(&path).ok().unwrap();
+        player.set_wait_for_disk(true);
+        player.init(&AudioRuntimeParameters { sampling_freq: 1_000.0, buffer_size: 64 });
+
+        //  Loop from frame 20 to 50
+        player.change_parameters(&[1.0, 0.02, 0.05, 0.0, 1.0]);
+        player.reset();
+        let output = play(&mut player, 200);
+        assert_eq!(output[49], 0.049);
+        assert_eq!(output[50], 0.02);
+        assert_eq!(output[80], 0.02);
+        assert!(!player.is_finished());
+
+        //  Twice the speed skips every other frame
+        player.change_parameters(&[0.0, 0.0, 0.0, 0.0, 2.0]);
+        player.reset();
+        let output = play(&mut player, 60);
+        assert_eq!(output[10], 0.02);
+        assert_eq!(output[45], 0.09);
+        assert_eq!(output[50], 0.0);
+
+        //  Playing a 1 kHz file at 2 kHz interpolates between frames, which is exact for a ramp
+        player.init(&AudioRuntimeParameters { sampling_freq: 2_000.0, buffer_size: 64 });
+        player.change_parameters(&[0.0, 0.0, 0.0, 0.0, 1.0]);
+        player.reset();
+        let output = play(&mut player, 40);
+        assert_eq!(output[20], 0.01);
+        assert!((output[21] - 0.0105).abs() < 1e-6);
+
+        //  Closing the gate stops playback
+        player.set_gate(false);
+        assert_eq!(play(&mut player, 4), [0.0; 4]);
+
+        drop(player);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn generate(oscillator: &mut OscillatorNode, num_samples: usize) -> Vec<f32> {
+        oscillator.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: num_samples });
+        let mut buffer = vec![0.0; num_samples * 5];
+        oscillator.process_block(&mut buffer)[..num_samples].to_vec()
+    }
+
+    #[test]
+    fn generate_band_limited_waveforms() {
+        //  A quarter of the sampling frequency hits the peaks and zero crossings exactly
+        let output = generate(&mut OscillatorNode::new(Waveform::Sine, 12_000.0), 8);
+        for (sample, expected) in output.iter().zip([0.0, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0, -1.0]) {
+            assert!((sample - expected).abs() < 1e-6);
+        }
+
+        //  Band-limited edges are spread over two samples instead of jumping the full height at once
+        for waveform in [Waveform::Saw, Waveform::Pulse, Waveform::Triangle] {
+            let output = generate(&mut OscillatorNode::new(waveform, 1_000.0), 4_800);
+            let largest_step = output.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max);
+            let mean = output.iter().sum::<f32>() / output.len() as f32;
+
+            assert!(largest_step < 1.5, "{:?} jumps by {}", waveform, largest_step);
+            assert!(mean.abs() < 1e-3, "{:?} has an offset of {}", waveform, mean);
+            assert!(output.iter().all(|sample| sample.abs() <= 1.05));
+        }
+
+        //  A narrow pulse spends most of the cycle low
+        let mut oscillator = OscillatorNode::new(Waveform::Pulse, 1_000.0);
+        oscillator.change_parameters(&[2.0, 1_000.0, 1.0, 0.0, 0.25, 0.0]);
+        let output = generate(&mut oscillator, 4_800);
+        let mean = output.iter().sum::<f32>() / output.len() as f32;
+        assert!((mean + 0.5).abs() < 1e-3);
+        assert_eq!(oscillator.get_parameters(), vec![2.0, 1_000.0, 1.0, 0.0, 0.25, 0.0]);
+    }
+
+    fn process_modulated(port: usize, parameters: &[f32]) -> Vec<f32> {
+        //  [gen] -> input `port` of [osc] -> [Output]
+        let mut graph = AudioGraph::new();
+        let gen_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let osc_id = graph.add_new_node(Box::new(OscillatorNode::new(Waveform::Sine, 12_000.0))).ok().unwrap();
+        if graph.set_node_parameters(osc_id, parameters).is_err() { panic!(); }
+        if graph.connect_node(gen_id, osc_id, port).is_err() { panic!(); }
+        if graph.connect_node_to_output(osc_id).is_err() { panic!(); }
+        if graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 4 }).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => buffer.to_vec(),
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    #[test]
+    fn modulate_oscillator_through_inputs() {
+        //  The generator's constant 1.0 into the amplitude input of a silent oscillator brings it up to full level
+        let output = process_modulated(OSCILLATOR_AMPLITUDE_INPUT, &[0.0, 12_000.0, 0.0]);
+        for (sample, expected) in output.iter().zip([0.0, 1.0, 0.0, -1.0]) {
+            assert!((sample - expected).abs() < 1e-6);
+        }
+
+        //  Into the phase input it shifts the cycle by a whole period, which leaves the quarter cycle phase offset audible
+        let output = process_modulated(OSCILLATOR_PHASE_INPUT, &[0.0, 12_000.0, 1.0, 0.25]);
+        for (sample, expected) in output.iter().zip([1.0, 0.0, -1.0, 0.0]) {
+            assert!((sample - expected).abs() < 1e-6);
+        }
+
+        //  Into the frequency input it raises 12 kHz by 1 Hz, which is barely a change over four samples
+        let output = process_modulated(OSCILLATOR_FREQUENCY_INPUT, &[0.0, 12_000.0]);
+        assert!((output[1] - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn generate_seeded_noise() {
+        let noise = |color: NoiseColor, seed: u32| {
+            let mut noise_node = NoiseNode::new(color, seed);
+            noise_node.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 48_000 });
+            noise_node.process_block(&mut vec![0.0; 48_000]).to_vec()
+        };
+
+        //  The same seed gives the same samples, a different seed different ones
+        let white = noise(NoiseColor::White, 7);
+        assert_eq!(white, noise(NoiseColor::White, 7));
+        assert_ne!(white, noise(NoiseColor::White, 8));
+        assert!(white.iter().all(|sample| (-1.0..1.0).contains(sample)));
+
+        //  The redder the noise, the more each sample resembles the previous one
+        let correlation = |samples: &[f32]| {
+            let energy: f32 = samples.iter().map(|sample| sample * sample).sum();
+            samples.windows(2).map(|pair| pair[0] * pair[1]).sum::<f32>() / energy
+        };
+        let pink = noise(NoiseColor::Pink, 7);
+        let brown = noise(NoiseColor::Brown, 7);
+        assert!(correlation(&white).abs() < 0.05);
+        assert!(correlation(&pink) > 0.3 && correlation(&pink) < 0.9);
+        assert!(correlation(&brown) > 0.95);
+
+        //  Resetting, or setting the seed again, restarts the sequence
+        let mut noise_node = NoiseNode::new(NoiseColor::Pink, 0);
+        noise_node.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 16 });
+        noise_node.change_parameters(&[1.0, 1.0, 7.0]);
+        let first = noise_node.process_block(&mut [0.0; 16]).to_vec();
+        noise_node.reset();
+        assert_eq!(noise_node.process_block(&mut [0.0; 16]).to_vec(), first);
+        assert_eq!(first[..], pink[..16]);
+
+        //  Seeds are clamped to 2^24 so they round-trip through the parameters
+        let noise_node = NoiseNode::new(NoiseColor::White, u32::MAX);
+        let parameters = noise_node.get_parameters();
+        assert_eq!(parameters[2], 16_777_216.0);
+        let mut reloaded = NoiseNode::new(NoiseColor::White, 0);
+        reloaded.change_parameters(&parameters);
+        assert_eq!(reloaded.get_parameters(), parameters);
+        assert_eq!(noise(NoiseColor::White, u32::MAX), noise(NoiseColor::White, 1 << 24));
+    }
+}
@@ -0,0 +1,443 @@
+//! Minimal JSON reader and writer used for patch files and graph snapshots.
+//! Only what the crate needs is supported: objects keep their keys in insertion order so that written files diff cleanly
+
+use std::fmt::Write;
+
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>)
+}
+
+/// Error produced when a JSON document cannot be parsed.  `line` and `column` start at 1
+pub struct JsonError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize
+}
+
+impl JsonValue {
+    /// Build a number from an `f32` so that it is written with the shortest representation that reads back to the same `f32`
+    pub fn from_f32(value: f32) -> JsonValue {
+        JsonValue::Number(value.to_string().parse::<f64>().unwrap_or(value as f64))
+    }
+
+    /// Look up a key of an object.  Returns None for missing keys and for values that are not objects
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(number) => Some(*number),
+            _ => None
+        }
+    }
+
+    /// Get a number that is a non-negative integer, e.g. a node id or port
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            JsonValue::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as usize),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(value) => Some(*value),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(value) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(values) => Some(values),
+            _ => None
+        }
+    }
+
+    /// Write the value as indented JSON.
+    /// Arrays and objects that only hold scalars are kept on a single line, which keeps parameter lists and connections readable
+    pub fn to_pretty_string(&self) -> String {
+        let mut output = String::new();
+        self.write_pretty(&mut output, 0);
+        output.push('\n');
+        output
+    }
+
+    fn is_scalar(&self) -> bool {
+        !matches!(self, JsonValue::Array(_) | JsonValue::Object(_))
+    }
+
+    fn write_pretty(&self, output: &mut String, indent: usize) {
+        match self {
+            JsonValue::Null => output.push_str("null"),
+            JsonValue::Bool(value) => output.push_str(if *value { "true" } else { "false" }),
+            JsonValue::Number(number) => write_number(output, *number),
+            JsonValue::String(value) => write_string(output, value),
+
+            JsonValue::Array(values) => {
+                if values.iter().all(JsonValue::is_scalar) {
+                    output.push('[');
+                    for (i, value) in values.iter().enumerate() {
+                        if i > 0 {
+                            output.push_str(", ");
+                        }
+                        value.write_pretty(output, indent);
+                    }
+                    output.push(']');
+                    return;
+                }
+
+                output.push_str("[\n");
+                for (i, value) in values.iter().enumerate() {
+                    push_indent(output, indent + 1);
+                    value.write_pretty(output, indent + 1);
+                    output.push_str(if i + 1 < values.len() { ",\n" } else { "\n" });
+                }
+                push_indent(output, indent);
+                output.push(']');
+            },
+
+            JsonValue::Object(members) => {
+                if members.is_empty() {
+                    output.push_str("{}");
+                    return;
+                }
+
+                if members.iter().all(|(_, value)| value.is_scalar()) {
+                    output.push_str("{ ");
+                    for (i, (name, value)) in members.iter().enumerate() {
+                        if i > 0 {
+                            output.push_str(", ");
+                        }
+                        write_string(output, name);
+                        output.push_str(": ");
+                        value.write_pretty(output, indent);
+                    }
+                    output.push_str(" }");
+                    return;
+                }
+
+                output.push_str("{\n");
+                for (i, (name, value)) in members.iter().enumerate() {
+                    push_indent(output, indent + 1);
+                    write_string(output, name);
+                    output.push_str(": ");
+                    value.write_pretty(output, indent + 1);
+                    output.push_str(if i + 1 < members.len() { ",\n" } else { "\n" });
+                }
+                push_indent(output, indent);
+                output.push('}');
+            }
+        }
+    }
+}
+
+fn push_indent(output: &mut String, indent: usize) {
+    for _ in 0..indent {
+        output.push_str("  ");
+    }
+}
+
+/// JSON has no representation for NaN or infinity, so those are written as null
+fn write_number(output: &mut String, number: f64) {
+    if number.is_finite() {
+        let _ = write!(output, "{}", number);
+    } else {
+        output.push_str("null");
+    }
+}
+
+fn write_string(output: &mut String, value: &str) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(output, "\\u{:04x}", c as u32); },
+            c => output.push(c)
+        }
+    }
+    output.push('"');
+}
+
+
+/// Arrays and objects nested deeper than this are rejected so that a hostile document cannot overflow the stack
+const MAX_NESTING_DEPTH: usize = 128;
+
+/// Parse a JSON document
+pub fn parse(text: &str) -> Result<JsonValue, JsonError> {
+    let mut parser = Parser { chars: text.chars().collect(), position: 0, depth: 0 };
+
+    parser.skip_whitespace();
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+
+    if parser.position < parser.chars.len() {
+        return Err(parser.error("Unexpected characters after the end of the document"));
+    }
+
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    depth: usize
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> JsonError {
+        let consumed = &self.chars[..self.position.min(self.chars.len())];
+        let line = consumed.iter().filter(|c| **c == '\n').count() + 1;
+        let column = consumed.iter().rev().take_while(|c| **c != '\n').count() + 1;
+
+        JsonError {
+            message: String::from(message),
+            line,
+            column
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("Expected '{}'", expected)));
+        }
+
+        self.position += 1;
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, JsonError> {
+        match self.peek() {
+            Some('{') => self.parse_nested(Parser::parse_object),
+            Some('[') => self.parse_nested(Parser::parse_array),
+            Some('"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some('t') => self.parse_literal("true", JsonValue::Bool(true)),
+            Some('f') => self.parse_literal("false", JsonValue::Bool(false)),
+            Some('n') => self.parse_literal("null", JsonValue::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of document"))
+        }
+    }
+
+    fn parse_nested(&mut self, parse: fn(&mut Parser) -> Result<JsonValue, JsonError>) -> Result<JsonValue, JsonError> {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err(self.error("Document is nested too deeply"));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        for expected in literal.chars() {
+            if self.peek() != Some(expected) {
+                return Err(self.error(&format!("Expected '{}'", literal)));
+            }
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') {
+                break;
+            }
+            self.position += 1;
+        }
+
+        let text: String = self.chars[start..self.position].iter().collect();
+        match text.parse::<f64>() {
+            Ok(number) => Ok(JsonValue::Number(number)),
+            Err(_) => {
+                self.position = start;
+                Err(self.error(&format!("Invalid number '{}'", text)))
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(value);
+                },
+                Some('\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let digits: String = self.chars.iter().skip(self.position + 1).take(4).collect();
+                            let code = u32::from_str_radix(&digits, 16).map_err(|_| self.error("Invalid unicode escape"))?;
+                            self.position += 4;
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+                        _ => return Err(self.error("Invalid escape sequence"))
+                    };
+                    value.push(escaped);
+                    self.position += 1;
+                },
+                Some(c) => {
+                    value.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('[')?;
+        self.skip_whitespace();
+
+        let mut values = vec![];
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(values));
+        }
+
+        loop {
+            self.skip_whitespace();
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => { self.position += 1; },
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(values));
+                },
+                _ => return Err(self.error("Expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('{')?;
+        self.skip_whitespace();
+
+        let mut members = vec![];
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let name = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            members.push((name, self.parse_value()?));
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => { self.position += 1; },
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(members));
+                },
+                _ => return Err(self.error("Expected ',' or '}'"))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_parse_json() {
+        let value = JsonValue::Object(vec![
+            (String::from("name"), JsonValue::String(String::from("a \"quoted\"\nname"))),
+            (String::from("values"), JsonValue::Array(vec![JsonValue::from_f32(0.1), JsonValue::Number(-2.5e3), JsonValue::Null])),
+            (String::from("nested"), JsonValue::Array(vec![
+                JsonValue::Object(vec![(String::from("on"), JsonValue::Bool(true))])
+            ]))
+        ]);
+
+        let text = value.to_pretty_string();
+        assert_eq!(text, "{\n  \"name\": \"a \\\"quoted\\\"\\nname\",\n  \"values\": [0.1, -2500, null],\n  \"nested\": [\n    { \"on\": true }\n  ]\n}\n");
+
+        let parsed = match parse(&text) {
+            Ok(parsed) => parsed,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        assert_eq!(parsed.get("name").and_then(JsonValue::as_str), Some("a \"quoted\"\nname"));
+        assert_eq!(parsed.get("values").and_then(JsonValue::as_array).map(|values| values.len()), Some(3));
+        assert_eq!(parsed.get("values").unwrap().as_array().unwrap()[0].as_f64().map(|v| v as f32), Some(0.1));
+        assert_eq!(parsed.get("nested").unwrap().as_array().unwrap()[0].get("on").and_then(JsonValue::as_bool), Some(true));
+    }
+
+    #[test]
+    fn report_json_errors() {
+        match parse("{\n  \"a\": [1, 2,\n  }") {
+            Ok(_) => { panic!(); },
+            Err(e) => {
+                assert_eq!(e.line, 3);
+                assert_eq!(e.column, 3);
+            }
+        }
+
+        if parse("[1, 2] x").is_ok() { panic!(); }
+        if parse("\"unterminated").is_ok() { panic!(); }
+
+        let nested = format!("{}{}", "[".repeat(MAX_NESTING_DEPTH), "]".repeat(MAX_NESTING_DEPTH));
+        if parse(&nested).is_err() { panic!(); }
+        match parse(&"[".repeat(100000)) {
+            Ok(_) => { panic!(); },
+            Err(e) => assert_eq!(e.column, MAX_NESTING_DEPTH + 1)
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! # Patch
+//! Saving audio graphs to, and rebuilding them from, a human-readable JSON patch format.
+//!
+//! A patch lists every node by its registered type name together with its name (if any), parameters and mixing state, followed by every connection:
+//!
+//! ```json
+//! {
+//!   "format": "audio_graph_patch",
+//!   "version": 1,
+//!   "nodes": [
+//!     {
+//!       "id": 1,
+//!       "type": "test_gen",
+//!       "parameters": []
+//!     },
+//!     {
+//!       "id": 2,
+//!       "type": "test_fx",
+//!       "name": "fuzz",
+//!       "parameters": [],
+//!       "bypassed": true
+//!     }
+//!   ],
+//!   "connections": [
+//!     { "from": 2, "to": 0, "port": 0 },
+//!     { "from": 1, "to": 2, "port": 0 }
+//!   ]
+//! }
+//! ```
+//!
+//! A top-level `"output_channels"` field is written for graphs with more than one output channel.
+//! Node 0 is always the output node.  Node ids are only used to resolve connections within the file, so a loaded graph may assign different ids
+
+use std::path::Path;
+
+use super::AudioToolbox::{AudioGraph, AudioNode, AudioNodeType, Error, ErrorCodes};
+use super::Json::{self, JsonValue};
+use super::ModelNodes::*;
+use super::Generators::*;
+use super::Envelopes::*;
+use super::Filters::*;
+use super::Delays::*;
+use super::Reverbs::*;
+use super::Dynamics::*;
+use super::Utilities::*;
+
+/// Value of the "format" field that identifies a patch file
+pub const PATCH_FORMAT: &str = "audio_graph_patch";
+
+/// Version of the patch format written by `save_patch()`.  Patches with a newer version are rejected when loading
+pub const PATCH_VERSION: usize = 1;
+
+
+/// Maps node type names to factories that create a node of that type with default parameters.
+/// `load_patch()` uses a registry to recreate the nodes listed in a patch, and graphs can be built from configuration by type name with `create_node()`
+pub struct NodeRegistry {
+    entries: Vec<RegistryEntry>
+}
+
+type NodeFactory = Box<dyn Fn() -> Box<dyn AudioNode>>;
+
+struct RegistryEntry {
+    type_name: String,
+    description: String,
+    factory: NodeFactory
+}
+
+/// Information about a registered node type, as returned by `NodeRegistry::get_type_info()`.
+/// Everything except the description is read from a node created with default parameters
+pub struct NodeTypeInfo {
+    pub type_name: String,
+    pub description: String,
+    pub node_type: AudioNodeType,
+    pub num_inputs: usize,
+    pub parameter_names: Vec<String>,
+    pub default_parameters: Vec<f32>
+}
+
+impl Default for NodeRegistry {
+    fn default() -> NodeRegistry {
+        NodeRegistry::new()
+    }
+}
+
+impl NodeRegistry {
+    /// Create an empty registry
+    pub fn new() -> NodeRegistry {
+        NodeRegistry {
+            entries: vec![]
+        }
+    }
+
+    /// Create a registry that already holds every node type that ships with this crate
+    pub fn with_default_nodes() -> NodeRegistry {
+        let mut registry = NodeRegistry::new();
+
+        registry.register("test", "Model node that passes its input through", || Box::new(TestNode::new()));
+        registry.register("test_gen", "Model generator that outputs a constant 1.0", || Box::new(TestGenNode::new()));
+        registry.register("test_fx", "Model effect that halves its input", || Box::new(TestFXNode::new()));
+        registry.register("test_mixer", "Model mixer with a configurable number of inputs", || Box::new(TestMixerNode::new(2)));
+        registry.register("test_stereo", "Model effect that turns a mono input into two channels", || Box::new(TestStereoNode::new()));
+        registry.register("test_latency", "Model effect that delays its input and reports the delay as latency", || Box::new(TestLatencyNode::new(0)));
+        registry.register("oscillator", "Band-limited sine, saw, pulse and triangle oscillator", || Box::new(OscillatorNode::new(Waveform::Sine, 440.0)));
+        registry.register("noise", "Seeded white, pink or brown noise", || Box::new(NoiseNode::new(NoiseColor::White, 0)));
+        registry.register("adsr", "Attack-decay-sustain-release envelope with a gate input", || Box::new(AdsrNode::new(0.01, 0.1, 0.7, 0.3)));
+        registry.register("envelope", "Envelope with any number of curved segments", || {
+            Box::new(EnvelopeNode::new(vec![EnvelopeSegment::new(1.0, 0.01, 0.0), EnvelopeSegment::new(0.0, 0.3, -5.0)], Some(0)))
+        });
+        registry.register("biquad", "Low-pass, high-pass, band-pass, notch, all-pass, peak or shelving filter", || Box::new(BiquadNode::new(FilterType::LowPass, 1_000.0, 0.707, 0.0)));
+        registry.register("parametric_eq", "Equaliser with any number of bell, shelf and pass bands", || {
+            Box::new(ParametricEqNode::new(vec![EqBand::new(EqBandType::LowShelf, 100.0, 0.0, 0.707, 12), EqBand::new(EqBandType::Bell, 1_000.0, 0.0, 1.0, 12), EqBand::new(EqBandType::HighShelf, 8_000.0, 0.0, 0.707, 12)]))
+        });
+        registry.register("delay", "Delay with feedback, tempo sync, modulation and ping-pong", || Box::new(DelayNode::new(DelayTime::Milliseconds(250.0), 0.3, 0.5)));
+        registry.register("reverb", "Freeverb-style stereo reverb with pre-delay and width", || Box::new(ReverbNode::new(0.5, 0.5, 0.3)));
+        registry.register("dynamics", "Compressor, limiter, expander or gate with sidechain input and lookahead", || Box::new(DynamicsNode::new(DynamicsMode::Compressor, -20.0, 4.0)));
+        registry.register("true_peak_limiter", "Brickwall limiter holding the true peak level below a ceiling", || Box::new(TruePeakLimiterNode::new(-1.0, 50.0)));
+        registry.register("gain", "Gain in dB with polarity inversion", || Box::new(GainNode::new(0.0)));
+        registry.register("pan", "Mono to stereo panner with a choice of pan law", || Box::new(PanNode::new(0.0, PanLaw::ConstantPower)));
+        registry.register("stereo_width", "Balance and width of a stereo signal", || Box::new(StereoWidthNode::new(0.0, 1.0)));
+        registry.register("crossfader", "Equal-power crossfade between two inputs", || Box::new(CrossfaderNode::new(0.0)));
+
+        registry
+    }
+
+    /// Register a node type under a name.  Registering a name a second time replaces the previous factory.
+    /// The name should match what the created nodes return from `AudioNode::get_type_name()`, otherwise saved patches cannot be loaded back
+    pub fn register<F>(&mut self, type_name: &str, description: &str, factory: F) where F: Fn() -> Box<dyn AudioNode> + 'static {
+        let entry = RegistryEntry {
+            type_name: String::from(type_name),
+            description: String::from(description),
+            factory: Box::new(factory)
+        };
+
+        match self.entries.iter_mut().find(|existing| existing.type_name == type_name) {
+            Some(existing) => { *existing = entry; },
+            None => { self.entries.push(entry); }
+        }
+    }
+
+    /// Check whether a node type is registered
+    pub fn contains(&self, type_name: &str) -> bool {
+        self.find_entry(type_name).is_some()
+    }
+
+    /// Get the names of all registered node types, in the order they were registered
+    pub fn get_type_names(&self) -> Vec<&str> {
+        self.entries.iter().map(|entry| entry.type_name.as_str()).collect()
+    }
+
+    /// Get the description, inputs and default parameters of a registered node type.  Returns None if the type is not registered
+    pub fn get_type_info(&self, type_name: &str) -> Option<NodeTypeInfo> {
+        let entry = self.find_entry(type_name)?;
+        let node = (entry.factory)();
+
+        Some(NodeTypeInfo {
+            type_name: entry.type_name.clone(),
+            description: entry.description.clone(),
+            node_type: *node.get_node_type(),
+            num_inputs: node.get_number_of_inputs(),
+            parameter_names: node.get_parameter_names().iter().map(|name| String::from(*name)).collect(),
+            default_parameters: node.get_parameters()
+        })
+    }
+
+    /// Create a node of a registered type with default parameters.  Returns None if the type is not registered
+    pub fn create_node(&self, type_name: &str) -> Option<Box<dyn AudioNode>> {
+        self.find_entry(type_name).map(|entry| (entry.factory)())
+    }
+
+    fn find_entry(&self, type_name: &str) -> Option<&RegistryEntry> {
+        self.entries.iter().find(|entry| entry.type_name == type_name)
+    }
+}
+
+
+/// Save the nodes, parameters, mixing state and connections of a graph as a JSON patch.
+/// Fails if any node does not report a type name
+pub fn save_patch(graph: &AudioGraph) -> Result<String, Error> {
+    let mut nodes = vec![];
+
+    for node_id in graph.get_node_ids().into_iter().skip(1) {
+        let node = match graph.get_node(node_id) {
+            Some(node) => node,
+            None => continue
+        };
+
+        let type_name = match node.get_type_name() {
+            Some(type_name) => type_name,
+            None => return Err(Error {
+                code: ErrorCodes::NodeNotSerializable,
+                message: format!("Node {} has no registered type name and cannot be saved", node_id)
+            })
+        };
+
+        let mut members = vec![
+            (String::from("id"), JsonValue::Number(node_id as f64)),
+            (String::from("type"), JsonValue::String(String::from(type_name)))
+        ];
+        if let Some(name) = graph.get_node_name(node_id) {
+            members.push((String::from("name"), JsonValue::String(String::from(name))));
+        }
+        members.push((String::from("parameters"), JsonValue::Array(node.get_parameters().into_iter().map(JsonValue::from_f32).collect())));
+
+        //  Mixing state is only written when set, which keeps patches short
+        for (key, is_set) in [("bypassed", graph.is_node_bypassed(node_id)), ("muted", graph.is_node_muted(node_id)), ("soloed", graph.is_node_soloed(node_id))] {
+            if is_set {
+                members.push((String::from(key), JsonValue::Bool(true)));
+            }
+        }
+
+        nodes.push(JsonValue::Object(members));
+    }
+
+    let connections = graph.get_connections().into_iter().map(|connection| {
+        JsonValue::Object(vec![
+            (String::from("from"), JsonValue::Number(connection.node_out_id as f64)),
+            (String::from("to"), JsonValue::Number(connection.node_in_id as f64)),
+            (String::from("port"), JsonValue::Number(connection.node_in_input_port as f64))
+        ])
+    }).collect();
+
+    let mut members = vec![
+        (String::from("format"), JsonValue::String(String::from(PATCH_FORMAT))),
+        (String::from("version"), JsonValue::Number(PATCH_VERSION as f64))
+    ];
+
+    //  Mono output is the default and is left out
+    if graph.get_number_of_output_channels() != 1 {
+        members.push((String::from("output_channels"), JsonValue::Number(graph.get_number_of_output_channels() as f64)));
+    }
+
+    members.push((String::from("nodes"), JsonValue::Array(nodes)));
+    members.push((String::from("connections"), JsonValue::Array(connections)));
+
+    let patch = JsonValue::Object(members);
+
+    Ok(patch.to_pretty_string())
+}
+
+/// Rebuild a graph from a JSON patch, creating its nodes through `registry`.
+/// The returned graph has not been prepared yet
+pub fn load_patch(text: &str, registry: &NodeRegistry) -> Result<AudioGraph, Error> {
+    let patch = match Json::parse(text) {
+        Ok(patch) => patch,
+        Err(e) => return Err(invalid_patch(format!("Patch is not valid JSON (line {}, column {}): {}", e.line, e.column, e.message)))
+    };
+
+    if patch.get("format").and_then(JsonValue::as_str) != Some(PATCH_FORMAT) {
+        return Err(invalid_patch(format!("Missing \"format\": \"{}\" field, this is not an audio graph patch", PATCH_FORMAT)));
+    }
+
+    match patch.get("version").and_then(JsonValue::as_usize) {
+        Some(version) if version >= 1 && version <= PATCH_VERSION => {},
+        Some(version) => return Err(Error {
+            code: ErrorCodes::PatchVersionUnsupported,
+            message: format!("Patch version {} is not supported (this library reads versions 1 to {})", version, PATCH_VERSION)
+        }),
+        None => return Err(invalid_patch(String::from("Missing or invalid \"version\" field")))
+    }
+
+    let mut graph = AudioGraph::new();
+
+    if let Some(value) = patch.get("output_channels") {
+        match value.as_usize() {
+            Some(num_channels) => graph.set_number_of_output_channels(num_channels)?,
+            None => return Err(invalid_patch(String::from("\"output_channels\" must be a positive whole number")))
+        }
+    }
+
+    //  Maps ids used in the file to ids in the new graph.  The output node is always 0
+    let mut node_ids: Vec<(usize, usize)> = vec![(0, 0)];
+
+    for node_entry in get_array(&patch, "nodes")? {
+        let file_id = match node_entry.get("id").and_then(JsonValue::as_usize) {
+            Some(id) => id,
+            None => return Err(invalid_patch(String::from("Node entry is missing a valid \"id\"")))
+        };
+
+        if node_ids.iter().any(|(id, _)| *id == file_id) {
+            return Err(invalid_patch(format!("Node id {} is used more than once", file_id)));
+        }
+
+        let type_name = match node_entry.get("type").and_then(JsonValue::as_str) {
+            Some(type_name) => type_name,
+            None => return Err(invalid_patch(format!("Node {} is missing a \"type\"", file_id)))
+        };
+
+        let mut node = match registry.create_node(type_name) {
+            Some(node) => node,
+            None => return Err(Error {
+                code: ErrorCodes::NodeTypeUnknown,
+                message: format!("Node {} has unknown type \"{}\"", file_id, type_name)
+            })
+        };
+
+        if let Some(parameters) = node_entry.get("parameters") {
+            let parameters: Option<Vec<f32>> = match parameters.as_array() {
+                Some(values) => values.iter().map(|value| value.as_f64().map(|v| v as f32)).collect(),
+                None => None
+            };
+
+            match parameters {
+                Some(parameters) => node.change_parameters(&parameters),
+                None => return Err(invalid_patch(format!("Parameters of node {} must be an array of numbers", file_id)))
+            }
+        }
+
+        let graph_id = match node_entry.get("name") {
+            Some(JsonValue::String(name)) => graph.add_new_named_node(name, node)?,
+            Some(_) => return Err(invalid_patch(format!("Name of node {} must be a string", file_id))),
+            None => graph.add_new_node(node)?
+        };
+        node_ids.push((file_id, graph_id));
+
+        let flag = |key: &str| node_entry.get(key).and_then(JsonValue::as_bool).unwrap_or(false);
+        graph.set_node_bypass(graph_id, flag("bypassed"))?;
+        graph.set_node_mute(graph_id, flag("muted"))?;
+        graph.set_node_solo(graph_id, flag("soloed"))?;
+    }
+
+    let lookup = |file_id: usize| -> Result<usize, Error> {
+        match node_ids.iter().find(|(id, _)| *id == file_id) {
+            Some((_, graph_id)) => Ok(*graph_id),
+            None => Err(invalid_patch(format!("Connection refers to node {}, which is not listed in \"nodes\"", file_id)))
+        }
+    };
+
+    for connection in get_array(&patch, "connections")? {
+        let from = connection.get("from").and_then(JsonValue::as_usize);
+        let to = connection.get("to").and_then(JsonValue::as_usize);
+        let port = connection.get("port").and_then(JsonValue::as_usize).unwrap_or(0);
+
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (lookup(from)?, lookup(to)?),
+            _ => return Err(invalid_patch(String::from("Connection entry needs numeric \"from\" and \"to\" fields")))
+        };
+
+        let result = match to {
+            0 => graph.connect_node_to_output(from),
+            _ => graph.connect_node(from, to, port)
+        };
+
+        if let Err(e) = result {
+            return Err(Error {
+                code: e.code,
+                message: format!("Cannot connect node {} to node {}: {}", from, to, e.message)
+            });
+        }
+    }
+
+    Ok(graph)
+}
+
+/// Save a graph to a patch file
+pub fn save_patch_to_file<P: AsRef<Path>>(graph: &AudioGraph, path: P) -> Result<(), Error> {
+    let text = save_patch(graph)?;
+
+    match std::fs::write(path.as_ref(), text) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Cannot write {}: {}", path.as_ref().display(), e)
+        })
+    }
+}
+
+/// Load a graph from a patch file
+pub fn load_patch_from_file<P: AsRef<Path>>(path: P, registry: &NodeRegistry) -> Result<AudioGraph, Error> {
+    match std::fs::read_to_string(path.as_ref()) {
+        Ok(text) => load_patch(&text, registry),
+        Err(e) => Err(Error {
+            code: ErrorCodes::FileAccessFailed,
+            message: format!("Cannot read {}: {}", path.as_ref().display(), e)
+        })
+    }
+}
+
+fn get_array<'a>(patch: &'a JsonValue, key: &str) -> Result<&'a Vec<JsonValue>, Error> {
+    match patch.get(key).and_then(JsonValue::as_array) {
+        Some(values) => Ok(values),
+        None => Err(invalid_patch(format!("Missing \"{}\" array", key)))
+    }
+}
+
+fn invalid_patch(message: String) -> Error {
+    Error {
+        code: ErrorCodes::PatchInvalid,
+        message
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioToolbox::AudioRuntimeParameters;
+
+    #[test]
+    fn save_and_load_patch() {
+        //  [g1] -> [fx] -> [mixer] -> [Output]
+        //  [g2] ---------> [     ]
+        let mut graph = AudioGraph::new();
+        let mixer_id = graph.add_new_node(Box::new(TestMixerNode::new(2))).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let fx_id = graph.add_new_node(Box::new(TestFXNode::new())).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+
+        if graph.connect_node(g1_id, fx_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx_id, mixer_id, 0).is_err() { panic!(); }
+        if graph.connect_node(g2_id, mixer_id, 1).is_err() { panic!(); }
+        if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+        if graph.set_node_mute(g2_id, true).is_err() { panic!(); }
+        if graph.set_node_name(fx_id, "fuzz").is_err() { panic!(); }
+
+        let text = match save_patch(&graph) {
+            Ok(text) => text,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        assert!(text.contains("      \"type\": \"test_mixer\",\n      \"parameters\": [2]\n"));
+        assert!(text.contains("      \"muted\": true\n"));
+        assert!(text.contains("      \"type\": \"test_fx\",\n      \"name\": \"fuzz\",\n"));
+        assert!(text.contains("    { \"from\": 4, \"to\": 1, \"port\": 1 },\n"));
+
+        let mut loaded = match load_patch(&text, &NodeRegistry::with_default_nodes()) {
+            Ok(loaded) => loaded,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        //  Saving the loaded graph gives back the same patch
+        match save_patch(&loaded) {
+            Ok(reloaded_text) => { assert_eq!(reloaded_text, text); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+
+        let runtime_params = AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+
+        if loaded.prepare(runtime_params).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 4];
+        match loaded.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [0.5; 4]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    #[test]
+    fn reject_invalid_patches() {
+        let registry = NodeRegistry::with_default_nodes();
+
+        let unknown_type = "{ \"format\": \"audio_graph_patch\", \"version\": 1, \"nodes\": [{ \"id\": 1, \"type\": \"flanger\" }], \"connections\": [] }";
+        match load_patch(unknown_type, &registry) {
+            Err(Error { code: ErrorCodes::NodeTypeUnknown, message }) => { assert!(message.contains("flanger")); },
+            _ => { panic!(); }
+        }
+
+        let future_version = "{ \"format\": \"audio_graph_patch\", \"version\": 99, \"nodes\": [], \"connections\": [] }";
+        if !matches!(load_patch(future_version, &registry), Err(Error { code: ErrorCodes::PatchVersionUnsupported, .. })) { panic!(); }
+
+        let dangling_connection = "{ \"format\": \"audio_graph_patch\", \"version\": 1, \"nodes\": [], \"connections\": [{ \"from\": 3, \"to\": 0 }] }";
+        if !matches!(load_patch(dangling_connection, &registry), Err(Error { code: ErrorCodes::PatchInvalid, .. })) { panic!(); }
+
+        if !matches!(load_patch("{ \"nodes\": [", &registry), Err(Error { code: ErrorCodes::PatchInvalid, .. })) { panic!(); }
+
+        //  Nodes without a type name cannot be saved
+        let mut graph = AudioGraph::new();
+        if graph.add_new_node(Box::new(TestLatencyNode::new(1))).is_err() { panic!(); }
+        if graph.add_new_node(Box::new(UnnamedNode)).is_err() { panic!(); }
+        if !matches!(save_patch(&graph), Err(Error { code: ErrorCodes::NodeNotSerializable, .. })) { panic!(); }
+    }
+
+    #[test]
+    fn register_node_types() {
+        let mut registry = NodeRegistry::with_default_nodes();
+        assert!(registry.contains("test_gen"));
+        assert!(registry.contains("test_fx"));
+        assert!(!registry.contains("unnamed"));
+
+        let info = match registry.get_type_info("test_mixer") {
+            Some(info) => info,
+            None => { panic!(); }
+        };
+
+        assert_eq!(info.node_type, AudioNodeType::Mixer);
+        assert_eq!(info.num_inputs, 2);
+        assert_eq!(info.parameter_names, vec![String::from("num_inputs")]);
+        assert_eq!(info.default_parameters, vec![2.0]);
+
+        //  User types can be registered next to the defaults and show up in the list of available types
+        registry.register("unnamed", "A node without a type name", || Box::new(UnnamedNode));
+        assert_eq!(registry.get_type_names().last(), Some(&"unnamed"));
+        assert_eq!(registry.get_type_info("unnamed").map(|info| info.description), Some(String::from("A node without a type name")));
+
+        match registry.create_node("test_fx") {
+            Some(node) => { assert_eq!(node.get_type_name(), Some("test_fx")); },
+            None => { panic!(); }
+        }
+
+        assert!(registry.create_node("flanger").is_none());
+        assert!(registry.get_type_info("flanger").is_none());
+    }
+
+    /// A node that does not report a type name
+    struct UnnamedNode;
+
+    impl AudioNode for UnnamedNode {
+        fn get_node_type(&self) -> &AudioNodeType {
+            &AudioNodeType::Effect
+        }
+
+        fn get_number_of_inputs(&self) -> usize {
+            1
+        }
+
+        fn get_next_available_input(&self) -> Option<usize> {
+            Some(0)
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! # Render
+//! Offline rendering: runs a prepared `AudioGraph` as fast as it can and collects or writes out the result.
+//!
+//! ```no_run
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::AudioFile::SampleFormat;
+//! use audio_graph::Render::{render_to_wav, RenderLength};
+//! use audio_graph::ModelNodes::*;
+//!
+//! let mut graph = AudioGraph::new();
+//! let gen_id = graph.add_new_node(Box::new(TestGenNode::new()))?;
+//! graph.connect_node_to_output(gen_id)?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 })?;
+//!
+//! render_to_wav(&mut graph, "bounce.wav", RenderLength::Duration(10.0), SampleFormat::Int24)?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use std::path::Path;
+
+use super::AudioToolbox::{AudioGraph, Error, ErrorCodes};
+use super::AudioFile::{SampleFormat, WavWriter};
+
+/// How long an offline render runs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderLength {
+    /// Render exactly this many seconds
+    Duration(f32),
+
+    /// Render `duration` seconds, then keep going until the output has stayed below `threshold_db` (dBFS, on every channel) for `hold` seconds.
+    /// This captures reverb and delay tails.  The tail is cut off after `max_tail` seconds in case the output never goes quiet
+    UntilSilence {
+        duration: f32,
+        threshold_db: f32,
+        hold: f32,
+        max_tail: f32
+    }
+}
+
+/// What an offline render produced
+pub struct RenderSummary {
+    /// Number of frames (samples per channel) rendered
+    pub num_frames: usize,
+    pub num_channels: usize,
+    pub sampling_freq: f32,
+    /// Largest absolute sample value of any channel
+    pub peak: f32,
+    /// Whether a `RenderLength::UntilSilence` render ended because the output went quiet rather than by reaching `max_tail`
+    pub ended_on_silence: bool
+}
+
+impl RenderSummary {
+    pub fn get_duration(&self) -> f32 {
+        self.num_frames as f32 / self.sampling_freq
+    }
+}
+
+/// Render a graph into memory.  Returns one vector of samples per output channel along with a summary.
+/// The graph must have been prepared, and rendering continues from wherever the graph currently is
+pub fn render(graph: &mut AudioGraph, length: RenderLength) -> Result<(Vec<Vec<f32>>, RenderSummary), Error> {
+    let mut channels: Vec<Vec<f32>> = (0..graph.get_number_of_output_channels()).map(|_| vec![]).collect();
+
+    let summary = render_blocks(graph, length, |block, buffer_size, num_frames| {
+        for (channel, samples) in channels.iter_mut().enumerate() {
+            samples.extend_from_slice(&block[channel * buffer_size..channel * buffer_size + num_frames]);
+        }
+
+        Ok(())
+    })?;
+
+    Ok((channels, summary))
+}
+
+/// Render a graph straight into a WAV file at the graph's sampling frequency, with one file channel per output channel of the graph.
+/// The graph must have been prepared, and rendering continues from wherever the graph currently is
+pub fn render_to_wav<P: AsRef<Path>>(graph: &mut AudioGraph, path: P, length: RenderLength, sample_format: SampleFormat) -> Result<RenderSummary, Error> {
+    let sampling_freq = get_prepared_parameters(graph)?.0;
+    let mut writer = WavWriter::create(path, graph.get_number_of_output_channels(), sampling_freq, sample_format)?;
+
+    let summary = render_blocks(graph, length, |block, buffer_size, num_frames| {
+        writer.write_planar(block, buffer_size, num_frames)
+    })?;
+
+    writer.finalize()?;
+
+    Ok(summary)
+}
+
+/// Run the graph block by block and hand every block to `consume` along with the buffer size (the channel stride) and the number of frames to keep
+fn render_blocks<F>(graph: &mut AudioGraph, length: RenderLength, mut consume: F) -> Result<RenderSummary, Error> where F: FnMut(&[f32], usize, usize) -> Result<(), Error> {
+    let (sampling_freq, buffer_size) = get_prepared_parameters(graph)?;
+    let num_channels = graph.get_number_of_output_channels();
+
+    let to_frames = |seconds: f32| (seconds.max(0.0) * sampling_freq).round() as usize;
+    let (num_duration_frames, silence) = match length {
+        RenderLength::Duration(duration) => (to_frames(duration), None),
+        RenderLength::UntilSilence { duration, threshold_db, hold, max_tail } => {
+            (to_frames(duration), Some((10.0f32.powf(threshold_db / 20.0), to_frames(hold), to_frames(max_tail))))
+        }
+    };
+
+    let mut block = vec![0.0; buffer_size * num_channels];
+    let mut summary = RenderSummary {
+        num_frames: 0,
+        num_channels,
+        sampling_freq,
+        peak: 0.0,
+        ended_on_silence: false
+    };
+    let mut num_quiet_frames = 0;
+
+    loop {
+        let frames_left = match silence {
+            None => num_duration_frames - summary.num_frames,
+            Some((_, _, max_tail)) => num_duration_frames + max_tail - summary.num_frames
+        };
+
+        if frames_left == 0 {
+            break;
+        }
+
+        graph.process_block(&mut block)?;
+
+        //  During the tail, only render up to the frame where the output has been quiet for long enough
+        let num_block_frames = buffer_size.min(frames_left);
+        let mut num_frames = num_block_frames;
+        for frame in 0..num_block_frames {
+            let frame_peak = (0..num_channels).map(|channel| block[channel * buffer_size + frame].abs()).fold(0.0, f32::max);
+            summary.peak = summary.peak.max(frame_peak);
+
+            if let Some((threshold, hold, _)) = silence {
+                num_quiet_frames = if frame_peak < threshold { num_quiet_frames + 1 } else { 0 };
+
+                if summary.num_frames + frame + 1 >= num_duration_frames && num_quiet_frames >= hold {
+                    num_frames = frame + 1;
+                    summary.ended_on_silence = true;
+                    break;
+                }
+            }
+        }
+
+        consume(&block, buffer_size, num_frames)?;
+        summary.num_frames += num_frames;
+
+        if summary.ended_on_silence {
+            break;
+        }
+    }
+
+    Ok(summary)
+}
+
+fn get_prepared_parameters(graph: &AudioGraph) -> Result<(f32, usize), Error> {
+    match graph.get_audio_runtime_parameters() {
+        Some(parameters) => Ok((parameters.sampling_freq, parameters.buffer_size)),
+        None => Err(Error {
+            code: ErrorCodes::AudioGraphNotPrepared,
+            message: String::from("The graph must be prepared before it can be rendered")
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioToolbox::AudioRuntimeParameters;
+    use crate::ModelNodes::*;
+
+    fn build_stereo_graph() -> AudioGraph {
+        //  [gen] -> [fx] -> [stereo] -> [Output]
+        let mut graph = AudioGraph::new();
+        let gen_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let fx_id = graph.add_new_node(Box::new(TestFXNode::new())).ok().unwrap();
+        let stereo_id = graph.add_new_node(Box::new(TestStereoNode::new())).ok().unwrap();
+
+        if graph.set_number_of_output_channels(2).is_err() { panic!(); }
+        if graph.connect_node(gen_id, fx_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx_id, stereo_id, 0).is_err() { panic!(); }
+        if graph.connect_node_to_output(stereo_id).is_err() { panic!(); }
+
+        let runtime_params = AudioRuntimeParameters {
+            sampling_freq: 1_000.0,
+            buffer_size: 64
+        };
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        graph
+    }
+
+    #[test]
+    fn render_audio_graph_offline() {
+        let mut graph = build_stereo_graph();
+
+        //  Durations need not be a multiple of the buffer size
+        let (channels, summary) = match render(&mut graph, RenderLength::Duration(0.1)) {
+            Ok(result) => result,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        assert_eq!(summary.num_frames, 100);
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0], vec![0.5; 100]);
+        assert_eq!(channels[1], vec![0.25; 100]);
+        assert_eq!(summary.peak, 0.5);
+
+        let path = std::env::temp_dir().join("audio_graph_render_audio_graph_offline.wav");
+        let summary = match render_to_wav(&mut graph, &path, RenderLength::Duration(0.05), SampleFormat::Float32) {
+            Ok(summary) => summary,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        //  44 byte header plus the fact chunk, then 50 frames of two 4 byte samples
+        assert_eq!(summary.num_frames, 50);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 44 + 12 + 50 * 2 * 4);
+        let _ = std::fs::remove_file(&path);
+
+        //  Unprepared graphs cannot be rendered
+        match render(&mut AudioGraph::new(), RenderLength::Duration(1.0)) {
+            Err(e) => { assert_eq!(e.code, ErrorCodes::AudioGraphNotPrepared); },
+            Ok(_) => panic!()
+        }
+    }
+
+    #[test]
+    fn process_block_checks_buffer_size() {
+        let mut graph = build_stereo_graph();
+
+        //  The buffer must hold every output channel, no more and no less
+        for length in [64, 3 * 64] {
+            let mut buffer = vec![0.0; length];
+            match graph.process_block(&mut buffer) {
+                Err(e) => { assert_eq!(e.code, ErrorCodes::InvalidBufferSize); },
+                Ok(_) => panic!()
+            }
+        }
+
+        let mut buffer = vec![0.0; 2 * 64];
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        assert_eq!(buffer[..64], [0.5; 64]);
+        assert_eq!(buffer[64..], [0.25; 64]);
+    }
+
+    #[test]
+    fn render_until_silence() {
+        let mut graph = build_stereo_graph();
+
+        //  The generator never stops, so the tail runs to its limit
+        let length = RenderLength::UntilSilence { duration: 0.1, threshold_db: -60.0, hold: 0.02, max_tail: 0.5 };
+        let (_, summary) = render(&mut graph, length).ok().unwrap();
+        assert_eq!(summary.num_frames, 600);
+        assert!(!summary.ended_on_silence);
+
+        //  Once muted, the render stops as soon as the output has been quiet for the hold time after the duration
+        if graph.set_node_mute(3, true).is_err() { panic!(); }
+        let (channels, summary) = render(&mut graph, length).ok().unwrap();
+        assert!(summary.ended_on_silence);
+        assert_eq!(summary.num_frames, 100);
+        assert_eq!(channels[0][99], 0.0);
+    }
+}
@@ -0,0 +1,377 @@
+//! # Reverbs
+//! Algorithmic reverbs.
+//!
+//! `ReverbNode` is a Freeverb-style reverb: per channel, eight lowpass-feedback comb filters in parallel followed by four all-pass diffusers in series,
+//! with the right channel's delays slightly longer than the left's to decorrelate them.  The original tunings are for 44.1 kHz; every delay is scaled
+//! to the sampling frequency the graph is prepared with, and the damping filter is adjusted to keep its cutoff, so the reverb sounds the same at any rate.
+//!
+//! ```
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::Generators::{NoiseColor, NoiseNode};
+//! use audio_graph::Reverbs::ReverbNode;
+//!
+//! //  [noise] -> [reverb] -> [Output], mono in, stereo out
+//! let mut graph = AudioGraph::new();
+//! graph.set_number_of_output_channels(2)?;
+//! let noise_id = graph.add_new_node(Box::new(NoiseNode::new(NoiseColor::Pink, 1)))?;
+//! let reverb_id = graph.add_new_node(Box::new(ReverbNode::new(0.8, 0.5, 0.3)))?;
+//! graph.connect_node(noise_id, reverb_id, 0)?;
+//! graph.connect_node_to_output(reverb_id)?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 96_000.0, buffer_size: 256 })?;
+//!
+//! let mut buffer = vec![0.0; 2 * 256];
+//! graph.process_block(&mut buffer)?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters};
+
+/// Sampling frequency the Freeverb tunings were made for
+const TUNING_SAMPLING_FREQ: f32 = 44_100.0;
+
+/// Comb filter delays in samples at `TUNING_SAMPLING_FREQ`
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+
+/// All-pass delays in samples at `TUNING_SAMPLING_FREQ`
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+
+/// Extra delay of the right channel's combs and all-passes, in samples at `TUNING_SAMPLING_FREQ`
+const STEREO_SPREAD: usize = 23;
+
+/// Gain into the combs.  Eight combs with feedback close to 1 are loud; this and `WET_GAIN` bring the tail back to about the level of the input
+const INPUT_GAIN: f32 = 0.015;
+const WET_GAIN: f32 = 3.0;
+
+const ROOM_SIZE_SCALE: f32 = 0.28;
+const ROOM_SIZE_OFFSET: f32 = 0.7;
+const DAMPING_SCALE: f32 = 0.4;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+/// Longest pre-delay, in milliseconds.  The pre-delay line is allocated for it in `init()`
+const MAX_PRE_DELAY: f32 = 500.0;
+
+const REVERB_PARAMETER_NAMES: [&str; 5] = ["room_size", "damping", "pre_delay", "width", "mix"];
+
+
+/// Comb filter with a one-pole lowpass in its feedback path, which makes high frequencies die away faster like in a real room
+struct DampedComb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_state: f32
+}
+
+impl DampedComb {
+    fn new(length: usize) -> DampedComb {
+        DampedComb { buffer: vec![0.0; length.max(1)], index: 0, filter_state: 0.0 }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+
+        self.filter_state = output + (self.filter_state - output) * damping;
+        //  Flush the decaying state to zero before it turns into slow denormals
+        if self.filter_state.abs() < 1e-20 {
+            self.filter_state = 0.0;
+        }
+
+        self.buffer[self.index] = input + self.filter_state * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+
+        output
+    }
+
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+        self.filter_state = 0.0;
+    }
+}
+
+/// Schroeder all-pass, as used by Freeverb (which is only an all-pass for a feedback of 0.618, but sounds better at 0.5)
+struct Diffuser {
+    buffer: Vec<f32>,
+    index: usize
+}
+
+impl Diffuser {
+    fn new(length: usize) -> Diffuser {
+        Diffuser { buffer: vec![0.0; length.max(1)], index: 0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+
+        self.buffer[self.index] = input + delayed * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+
+        delayed - input
+    }
+
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+    }
+}
+
+/// The combs and diffusers of one output channel
+struct ReverbTank {
+    combs: Vec<DampedComb>,
+    diffusers: Vec<Diffuser>
+}
+
+impl ReverbTank {
+    fn new(spread: usize, scale: f32) -> ReverbTank {
+        let scaled = |length: usize| ((length + spread) as f32 * scale).round() as usize;
+
+        ReverbTank {
+            combs: COMB_TUNINGS.iter().map(|length| DampedComb::new(scaled(*length))).collect(),
+            diffusers: ALLPASS_TUNINGS.iter().map(|length| Diffuser::new(scaled(*length))).collect()
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let mut output = 0.0;
+        for comb in &mut self.combs {
+            output += comb.process(input, feedback, damping);
+        }
+
+        for diffuser in &mut self.diffusers {
+            output = diffuser.process(output);
+        }
+
+        output
+    }
+
+    fn clear(&mut self) {
+        self.combs.iter_mut().for_each(DampedComb::clear);
+        self.diffusers.iter_mut().for_each(Diffuser::clear);
+    }
+}
+
+
+/// Freeverb-style stereo reverb.  Takes a mono input by default, or a stereo one after `set_number_of_input_channels(2)`, and always outputs two channels.
+/// Both input channels are summed into the reverb; with a stereo input the dry signal keeps its stereo image.
+///
+/// Parameters, in order: `room_size` (0 to 1, the length of the tail), `damping` (0 to 1, how quickly the highs die away), `pre_delay` in ms (up to 500),
+/// `width` (0 mono to 1 full stereo tail) and `mix` (0 dry to 1 wet)
+pub struct ReverbNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_input_channels: usize,
+    sampling_freq: f32,
+    buffer_size: usize,
+    room_size: f32,
+    damping: f32,
+    pre_delay: f32,
+    width: f32,
+    mix: f32,
+    tanks: Vec<ReverbTank>,
+    pre_delay_line: Vec<f32>,
+    pre_delay_position: usize
+}
+
+impl ReverbNode {
+    pub fn new(room_size: f32, damping: f32, mix: f32) -> ReverbNode {
+        ReverbNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_input_channels: 1,
+            sampling_freq: 44_100.0,
+            buffer_size: 0,
+            room_size: room_size.clamp(0.0, 1.0),
+            damping: damping.clamp(0.0, 1.0),
+            pre_delay: 0.0,
+            width: 1.0,
+            mix: mix.clamp(0.0, 1.0),
+            tanks: vec![],
+            pre_delay_line: vec![],
+            pre_delay_position: 0
+        }
+    }
+
+    /// Take a mono (1) or stereo (2) input.  Must be called before the node is added to a graph
+    pub fn set_number_of_input_channels(&mut self, num_channels: usize) {
+        self.num_input_channels = num_channels.clamp(1, 2);
+    }
+
+    /// Feedback of the combs.  Each comb's loop is as long in seconds at any sampling frequency, so the same feedback gives the same decay
+    fn get_feedback(&self) -> f32 {
+        self.room_size * ROOM_SIZE_SCALE + ROOM_SIZE_OFFSET
+    }
+
+    /// Coefficient of the combs' one-pole lowpass, adjusted from the 44.1 kHz tuning to keep the same cutoff
+    fn get_damping_coefficient(&self) -> f32 {
+        (self.damping * DAMPING_SCALE).powf(TUNING_SAMPLING_FREQ / self.sampling_freq)
+    }
+}
+
+impl AudioNode for ReverbNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.sampling_freq = audio_runtime_params.sampling_freq;
+        self.buffer_size = audio_runtime_params.buffer_size;
+
+        let scale = self.sampling_freq / TUNING_SAMPLING_FREQ;
+        self.tanks = vec![ReverbTank::new(0, scale), ReverbTank::new(STEREO_SPREAD, scale)];
+        self.pre_delay_line = vec![0.0; (MAX_PRE_DELAY * 0.001 * self.sampling_freq).ceil() as usize + 1];
+        self.pre_delay_position = 0;
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("reverb")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_input_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        2
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(room_size) = parameters.first() { self.room_size = room_size.clamp(0.0, 1.0); }
+        if let Some(damping) = parameters.get(1) { self.damping = damping.clamp(0.0, 1.0); }
+        if let Some(pre_delay) = parameters.get(2) { self.pre_delay = pre_delay.clamp(0.0, MAX_PRE_DELAY); }
+        if let Some(width) = parameters.get(3) { self.width = width.clamp(0.0, 1.0); }
+        if let Some(mix) = parameters.get(4) { self.mix = mix.clamp(0.0, 1.0); }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.room_size, self.damping, self.pre_delay, self.width, self.mix]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &REVERB_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.tanks.iter_mut().for_each(ReverbTank::clear);
+        self.pre_delay_line.iter_mut().for_each(|sample| *sample = 0.0);
+        self.pre_delay_position = 0;
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        if self.tanks.is_empty() {
+            return buffer;
+        }
+
+        let buffer_size = self.buffer_size;
+        let feedback = self.get_feedback();
+        let damping = self.get_damping_coefficient();
+        let pre_delay = ((self.pre_delay * 0.001 * self.sampling_freq).round() as usize).min(self.pre_delay_line.len() - 1);
+
+        //  The wet signal of each channel with some of the other's mixed in: all of it at zero width, none at full width
+        let wet = self.mix * WET_GAIN;
+        let wet_direct = wet * (0.5 + 0.5 * self.width);
+        let wet_crossed = wet * (0.5 - 0.5 * self.width);
+
+        for index in 0..buffer_size {
+            let dry_left = buffer[index];
+            let dry_right = if self.num_input_channels == 2 { buffer[buffer_size + index] } else { dry_left };
+
+            let length = self.pre_delay_line.len();
+            self.pre_delay_line[self.pre_delay_position] = (dry_left + dry_right) * 0.5 * INPUT_GAIN;
+            let input = self.pre_delay_line[(self.pre_delay_position + length - pre_delay) % length];
+            self.pre_delay_position = (self.pre_delay_position + 1) % length;
+
+            let left = self.tanks[0].process(input, feedback, damping);
+            let right = self.tanks[1].process(input, feedback, damping);
+
+            buffer[index] = (1.0 - self.mix) * dry_left + wet_direct * left + wet_crossed * right;
+            buffer[buffer_size + index] = (1.0 - self.mix) * dry_right + wet_direct * right + wet_crossed * left;
+        }
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `input`, followed by silence, through a reverb for `seconds` and return the left and right channels
+    fn run(reverb: &mut ReverbNode, sampling_freq: f32, seconds: f32, input: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        let num_samples = (seconds * sampling_freq) as usize;
+        reverb.init(&AudioRuntimeParameters { sampling_freq, buffer_size: num_samples });
+
+        let mut buffer = vec![0.0; 2 * num_samples];
+        buffer[..input.len()].copy_from_slice(input);
+        reverb.process_block(&mut buffer);
+
+        let right = buffer.split_off(num_samples);
+        (buffer, right)
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|sample| sample * sample).sum()
+    }
+
+    #[test]
+    fn decay_independent_of_sampling_freq() {
+        //  How far the tail of a 5 ms burst of 1 kHz has fallen between its first and fourth tenths of a second, in dB
+        let decay = |room_size: f32, sampling_freq: f32| {
+            let burst: Vec<f32> = (0..(0.005 * sampling_freq) as usize).map(|index| (2.0 * std::f32::consts::PI * 1_000.0 * index as f32 / sampling_freq).sin()).collect();
+            let mut reverb = ReverbNode::new(room_size, 0.5, 1.0);
+            let (left, _) = run(&mut reverb, sampling_freq, 0.4, &burst);
+            let tenth = (0.1 * sampling_freq) as usize;
+            10.0 * (energy(&left[..tenth]) / energy(&left[3 * tenth..])).log10()
+        };
+
+        let decay_44k = decay(0.5, 44_100.0);
+        let decay_96k = decay(0.5, 96_000.0);
+        assert!(decay_44k > 6.0);
+        assert!((decay_44k - decay_96k).abs() < 1.5);
+
+        //  A bigger room rings longer
+        assert!(decay(0.9, 48_000.0) < decay(0.2, 48_000.0));
+    }
+
+    #[test]
+    fn pre_delay_and_width() {
+        let mut reverb = ReverbNode::new(0.5, 0.5, 1.0);
+        reverb.change_parameters(&[0.5, 0.5, 20.0, 1.0, 1.0]);
+        let (left, right) = run(&mut reverb, 48_000.0, 0.2, &[1.0]);
+
+        //  Nothing comes out before the pre-delay and the shortest comb have passed, and the channels differ
+        let silence = (0.02 * 48_000.0 + 1116.0 * 48_000.0 / 44_100.0) as usize;
+        assert!(left[..silence - 1].iter().all(|sample| *sample == 0.0));
+        assert!(left[silence..].iter().any(|sample| *sample != 0.0));
+        assert!(left.iter().zip(right.iter()).any(|(left, right)| left != right));
+
+        //  At zero width both channels get the same tail
+        reverb.change_parameters(&[0.5, 0.5, 20.0, 0.0, 1.0]);
+        reverb.reset();
+        let (left, right) = run(&mut reverb, 48_000.0, 0.2, &[1.0]);
+        assert_eq!(left, right);
+        assert_eq!(reverb.get_parameters(), vec![0.5, 0.5, 20.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,664 @@
+//! # Utilities
+//! Plumbing nodes for level, panning and stereo image: `GainNode`, `PanNode`, `StereoWidthNode` and `CrossfaderNode`.
+//!
+//! Their gains ramp linearly across a block whenever a parameter changes, so they can be automated from the control thread without clicks.
+//!
+//! ```
+//! use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+//! use audio_graph::Generators::{NoiseColor, NoiseNode, OscillatorNode, Waveform};
+//! use audio_graph::Utilities::{CrossfaderNode, PanLaw, PanNode};
+//!
+//! //  [osc] and [noise] -> [crossfader] -> [pan] -> [Output]
+//! let mut graph = AudioGraph::new();
+//! graph.set_number_of_output_channels(2)?;
+//! let osc_id = graph.add_new_node(Box::new(OscillatorNode::new(Waveform::Saw, 220.0)))?;
+//! let noise_id = graph.add_new_node(Box::new(NoiseNode::new(NoiseColor::Pink, 1)))?;
+//! let crossfader_id = graph.add_new_node(Box::new(CrossfaderNode::new(0.25)))?;
+//! let pan_id = graph.add_new_node(Box::new(PanNode::new(-0.5, PanLaw::ConstantPower)))?;
+//! graph.connect_node(osc_id, crossfader_id, 0)?;
+//! graph.connect_node(noise_id, crossfader_id, 1)?;
+//! graph.connect_node(crossfader_id, pan_id, 0)?;
+//! graph.connect_node_to_output(pan_id)?;
+//! graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 256 })?;
+//! # Ok::<(), audio_graph::AudioToolbox::Error>(())
+//! ```
+
+use std::f32::consts::FRAC_PI_2;
+
+use super::AudioToolbox::{AudioNode, AudioNodeType, AudioRuntimeParameters};
+use super::Dynamics::{db_to_linear, linear_to_db, SILENCE_DB};
+
+const GAIN_PARAMETER_NAMES: [&str; 2] = ["gain_db", "invert"];
+
+const PAN_PARAMETER_NAMES: [&str; 2] = ["pan", "law"];
+
+const STEREO_WIDTH_PARAMETER_NAMES: [&str; 2] = ["balance", "width"];
+
+const CROSSFADER_PARAMETER_NAMES: [&str; 1] = ["position"];
+
+
+/// Gain `index` samples into a block of `buffer_size` samples that ramps from `start` to `end`
+fn ramp(start: f32, end: f32, index: usize, buffer_size: usize) -> f32 {
+    start + (end - start) * (index + 1) as f32 / buffer_size as f32
+}
+
+
+/// Multiplies any number of channels by a gain, optionally inverting their polarity.
+///
+/// Parameters, in order: `gain_db` (-144 or below is silence) and `invert` (1 to flip the polarity)
+pub struct GainNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    buffer_size: usize,
+    gain_db: f32,
+    invert: bool,
+    current_gain: f32
+}
+
+impl GainNode {
+    pub fn new(gain_db: f32) -> GainNode {
+        let mut gain_node = GainNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            num_channels: 1,
+            buffer_size: 0,
+            gain_db: gain_db.max(SILENCE_DB),
+            invert: false,
+            current_gain: 1.0
+        };
+        gain_node.current_gain = gain_node.get_linear_gain();
+
+        gain_node
+    }
+
+    /// Create a gain node from a linear gain.  A negative gain inverts the polarity
+    pub fn from_linear(gain: f32) -> GainNode {
+        let mut gain_node = GainNode::new(0.0);
+        gain_node.set_linear_gain(gain);
+        gain_node.current_gain = gain_node.get_linear_gain();
+
+        gain_node
+    }
+
+    /// Change the number of channels.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+    }
+
+    /// Set the gain as a linear factor.  A negative gain inverts the polarity
+    pub fn set_linear_gain(&mut self, gain: f32) {
+        self.gain_db = linear_to_db(gain.abs());
+        self.invert = gain < 0.0;
+    }
+
+    /// Get the gain as a linear factor, negative when the polarity is inverted
+    pub fn get_linear_gain(&self) -> f32 {
+        let gain = db_to_linear(self.gain_db);
+        if self.invert { -gain } else { gain }
+    }
+}
+
+impl AudioNode for GainNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.current_gain = self.get_linear_gain();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("gain")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(gain_db) = parameters.first() { self.gain_db = gain_db.max(SILENCE_DB); }
+        if let Some(invert) = parameters.get(1) { self.invert = *invert >= 0.5; }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.gain_db, if self.invert { 1.0 } else { 0.0 }]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &GAIN_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.current_gain = self.get_linear_gain();
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let target_gain = self.get_linear_gain();
+
+        for channel in 0..self.num_channels {
+            for (index, sample) in buffer[channel * buffer_size..(channel + 1) * buffer_size].iter_mut().enumerate() {
+                *sample *= ramp(self.current_gain, target_gain, index, buffer_size);
+            }
+        }
+        self.current_gain = target_gain;
+
+        buffer
+    }
+}
+
+
+/// How much a centred signal is turned down by `PanNode`.  Stored in the `law` parameter as the index given here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PanLaw {
+    /// -3 dB in the centre, so the power stays the same across the stereo field.  The usual choice
+    ConstantPower,
+    /// -6 dB in the centre, so the channels still add up to the input.  Suits material that will be summed to mono
+    Linear,
+    /// -4.5 dB in the centre, halfway between the other two
+    Compromise,
+    /// 0 dB in the centre; each channel only drops as the signal moves towards the other side
+    Balance
+}
+
+impl PanLaw {
+    fn from_parameter(value: f32) -> PanLaw {
+        match value.round() as i32 {
+            1 => PanLaw::Linear,
+            2 => PanLaw::Compromise,
+            3 => PanLaw::Balance,
+            _ => PanLaw::ConstantPower
+        }
+    }
+
+    fn to_parameter(self) -> f32 {
+        match self {
+            PanLaw::ConstantPower => 0.0,
+            PanLaw::Linear => 1.0,
+            PanLaw::Compromise => 2.0,
+            PanLaw::Balance => 3.0
+        }
+    }
+
+    /// Left and right gains for `pan` from -1 (left) to 1 (right)
+    pub fn get_gains(self, pan: f32) -> (f32, f32) {
+        let position = 0.5 * (pan.clamp(-1.0, 1.0) + 1.0);
+        let linear = (1.0 - position, position);
+        let constant_power = ((position * FRAC_PI_2).cos(), (position * FRAC_PI_2).sin());
+
+        match self {
+            PanLaw::ConstantPower => constant_power,
+            PanLaw::Linear => linear,
+            PanLaw::Compromise => ((linear.0 * constant_power.0).sqrt(), (linear.1 * constant_power.1).sqrt()),
+            PanLaw::Balance => ((2.0 * linear.0).min(1.0), (2.0 * linear.1).min(1.0))
+        }
+    }
+}
+
+/// Places a mono input in a stereo field.
+///
+/// Parameters, in order: `pan` (-1 left to 1 right) and `law` (index of `PanLaw`)
+pub struct PanNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    buffer_size: usize,
+    pan: f32,
+    law: PanLaw,
+    current_gains: (f32, f32)
+}
+
+impl PanNode {
+    pub fn new(pan: f32, law: PanLaw) -> PanNode {
+        let pan = pan.clamp(-1.0, 1.0);
+
+        PanNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            buffer_size: 0,
+            pan,
+            law,
+            current_gains: law.get_gains(pan)
+        }
+    }
+}
+
+impl AudioNode for PanNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.current_gains = self.law.get_gains(self.pan);
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("pan")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        2
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(pan) = parameters.first() { self.pan = pan.clamp(-1.0, 1.0); }
+        if let Some(law) = parameters.get(1) { self.law = PanLaw::from_parameter(*law); }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.pan, self.law.to_parameter()]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &PAN_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.current_gains = self.law.get_gains(self.pan);
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let (start_left, start_right) = self.current_gains;
+        let (end_left, end_right) = self.law.get_gains(self.pan);
+
+        //  The input is in the left channel, so the right channel has to be written first
+        for index in 0..buffer_size {
+            let input = buffer[index];
+            buffer[buffer_size + index] = input * ramp(start_right, end_right, index, buffer_size);
+            buffer[index] = input * ramp(start_left, end_left, index, buffer_size);
+        }
+        self.current_gains = (end_left, end_right);
+
+        buffer
+    }
+}
+
+
+/// Adjusts the balance and width of a stereo signal.  Width scales the side (difference) signal: 0 folds the input down to mono,
+/// 1 leaves it unchanged and 2 doubles the side signal.  Balance turns one channel down as it moves towards the other, keeping the centre at 0 dB.
+///
+/// Parameters, in order: `balance` (-1 left to 1 right) and `width` (0 to 2)
+pub struct StereoWidthNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    buffer_size: usize,
+    balance: f32,
+    width: f32,
+    current_balance: (f32, f32),
+    current_width: f32
+}
+
+impl StereoWidthNode {
+    pub fn new(balance: f32, width: f32) -> StereoWidthNode {
+        let balance = balance.clamp(-1.0, 1.0);
+        let width = width.clamp(0.0, 2.0);
+
+        StereoWidthNode {
+            node_type: AudioNodeType::Effect,
+            num_inputs: 1,
+            next_available_input: 0,
+            buffer_size: 0,
+            balance,
+            width,
+            current_balance: PanLaw::Balance.get_gains(balance),
+            current_width: width
+        }
+    }
+}
+
+impl AudioNode for StereoWidthNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.reset();
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("stereo_width")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        2
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        2
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(balance) = parameters.first() { self.balance = balance.clamp(-1.0, 1.0); }
+        if let Some(width) = parameters.get(1) { self.width = width.clamp(0.0, 2.0); }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.balance, self.width]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &STEREO_WIDTH_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.current_balance = PanLaw::Balance.get_gains(self.balance);
+        self.current_width = self.width;
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let (start_left, start_right) = self.current_balance;
+        let (end_left, end_right) = PanLaw::Balance.get_gains(self.balance);
+
+        for index in 0..buffer_size {
+            let (left, right) = (buffer[index], buffer[buffer_size + index]);
+            let mid = 0.5 * (left + right);
+            let side = 0.5 * (left - right) * ramp(self.current_width, self.width, index, buffer_size);
+
+            buffer[index] = (mid + side) * ramp(start_left, end_left, index, buffer_size);
+            buffer[buffer_size + index] = (mid - side) * ramp(start_right, end_right, index, buffer_size);
+        }
+        self.current_balance = (end_left, end_right);
+        self.current_width = self.width;
+
+        buffer
+    }
+}
+
+
+/// Crossfades between two inputs of any number of channels with an equal-power curve, so the loudness of uncorrelated signals stays the same
+/// through the fade.  Input port 0 is heard at position 0 and port 1 at position 1; each port has channels of its own.
+///
+/// Parameters: `position` (0 to 1)
+pub struct CrossfaderNode {
+    node_type: AudioNodeType,
+    num_inputs: usize,
+    next_available_input: usize,
+    num_channels: usize,
+    buffer_size: usize,
+    position: f32,
+    current_gains: (f32, f32)
+}
+
+impl CrossfaderNode {
+    pub fn new(position: f32) -> CrossfaderNode {
+        let position = position.clamp(0.0, 1.0);
+
+        CrossfaderNode {
+            node_type: AudioNodeType::Mixer,
+            num_inputs: 2,
+            next_available_input: 0,
+            num_channels: 1,
+            buffer_size: 0,
+            position,
+            current_gains: CrossfaderNode::get_gains(position)
+        }
+    }
+
+    /// Change the number of channels of each input and of the output.  Must be called before the node is added to a graph
+    pub fn set_number_of_channels(&mut self, num_channels: usize) {
+        self.num_channels = num_channels.max(1);
+    }
+
+    fn get_gains(position: f32) -> (f32, f32) {
+        ((position * FRAC_PI_2).cos(), (position * FRAC_PI_2).sin())
+    }
+}
+
+impl AudioNode for CrossfaderNode {
+    fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+        self.buffer_size = audio_runtime_params.buffer_size;
+        self.current_gains = CrossfaderNode::get_gains(self.position);
+    }
+
+    fn get_node_type(&self) -> &AudioNodeType {
+        &self.node_type
+    }
+
+    fn get_type_name(&self) -> Option<&str> {
+        Some("crossfader")
+    }
+
+    fn get_number_of_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    fn get_next_available_input(&self) -> Option<usize> {
+        if self.next_available_input >= self.num_inputs {
+            return None;
+        }
+
+        Some(self.next_available_input)
+    }
+
+    fn connect_input(&mut self) {
+        if self.next_available_input < self.num_inputs {
+            self.next_available_input += 1;
+        }
+    }
+
+    fn disconnect_input(&mut self) {
+        if self.next_available_input > 0 {
+            self.next_available_input -= 1;
+        }
+    }
+
+    fn get_number_of_input_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn get_input_port_channel(&self, port: usize) -> usize {
+        port * self.num_channels
+    }
+
+    fn get_number_of_output_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn change_parameters(&mut self, parameters: &[f32]) {
+        if let Some(position) = parameters.first() { self.position = position.clamp(0.0, 1.0); }
+    }
+
+    fn get_parameters(&self) -> Vec<f32> {
+        vec![self.position]
+    }
+
+    fn get_parameter_names(&self) -> &[&str] {
+        &CROSSFADER_PARAMETER_NAMES
+    }
+
+    fn reset(&mut self) {
+        self.current_gains = CrossfaderNode::get_gains(self.position);
+    }
+
+    fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+        let buffer_size = self.buffer_size;
+        let (start_first, start_second) = self.current_gains;
+        let (end_first, end_second) = CrossfaderNode::get_gains(self.position);
+        let second_offset = self.num_channels * buffer_size;
+
+        for channel in 0..self.num_channels {
+            for index in 0..buffer_size {
+                let first = buffer[channel * buffer_size + index];
+                let second = buffer[second_offset + channel * buffer_size + index];
+                buffer[channel * buffer_size + index] = first * ramp(start_first, end_first, index, buffer_size) + second * ramp(start_second, end_second, index, buffer_size);
+            }
+        }
+        self.current_gains = (end_first, end_second);
+
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioToolbox::AudioGraph;
+    use crate::ModelNodes::{TestFXNode, TestGenNode};
+
+    fn assert_close(samples: &[f32], expected: &[f32]) {
+        assert_eq!(samples.len(), expected.len());
+        for (sample, expected) in samples.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn apply_gain_and_pan() {
+        let mut gain = GainNode::new(-6.0206);
+        gain.set_number_of_channels(2);
+        gain.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 2 });
+        let mut buffer = [1.0, -1.0, 2.0, 4.0];
+        assert_close(gain.process_block(&mut buffer), &[0.5, -0.5, 1.0, 2.0]);
+
+        //  Inverting the polarity ramps through zero over the next block instead of jumping
+        gain.change_parameters(&[-6.0206, 1.0]);
+        assert_eq!(gain.get_parameters(), vec![-6.0206, 1.0]);
+        let mut buffer = [1.0, 1.0, 1.0, 1.0];
+        assert_close(gain.process_block(&mut buffer), &[0.0, -0.5, 0.0, -0.5]);
+
+        let gain = GainNode::from_linear(-0.25);
+        assert!((gain.get_parameters()[0] + 12.0412).abs() < 1e-3);
+        assert!((gain.get_linear_gain() + 0.25).abs() < 1e-6);
+        assert_eq!(GainNode::from_linear(0.0).get_linear_gain(), 0.0);
+
+        //  Every law leaves a hard-panned signal at full level, and differs in the centre
+        let half_power = std::f32::consts::FRAC_1_SQRT_2;
+        for (law, centre) in [(PanLaw::ConstantPower, half_power), (PanLaw::Linear, 0.5), (PanLaw::Compromise, (0.5 * half_power).sqrt()), (PanLaw::Balance, 1.0)] {
+            let (left, right) = law.get_gains(-1.0);
+            assert!((left - 1.0).abs() < 1e-6 && right.abs() < 1e-6);
+            let (left, right) = law.get_gains(0.0);
+            assert!((left - centre).abs() < 1e-6 && (right - centre).abs() < 1e-6);
+        }
+
+        let mut pan = PanNode::new(1.0, PanLaw::ConstantPower);
+        pan.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 2 });
+        let mut buffer = [1.0, 2.0, 0.0, 0.0];
+        assert_close(pan.process_block(&mut buffer), &[0.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn adjust_stereo_image_and_crossfade() {
+        let mut stereo_width = StereoWidthNode::new(0.0, 0.0);
+        stereo_width.init(&AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 2 });
+        let mut buffer = [1.0, 0.5, 0.0, 0.5];
+        assert_close(stereo_width.process_block(&mut buffer), &[0.5, 0.5, 0.5, 0.5]);
+
+        //  Double width and balance halfway to the right
+        stereo_width.change_parameters(&[0.5, 2.0]);
+        stereo_width.reset();
+        let mut buffer = [1.0, 0.5, 0.0, 0.5];
+        assert_close(stereo_width.process_block(&mut buffer), &[0.75, 0.25, -0.5, 0.5]);
+
+        //  [gen] -> port 0 of [crossfader], [gen] -> [fx] -> port 1, [crossfader] -> [Output]
+        let mut graph = AudioGraph::new();
+        let gen1_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let gen2_id = graph.add_new_node(Box::new(TestGenNode::new())).ok().unwrap();
+        let fx_id = graph.add_new_node(Box::new(TestFXNode::new())).ok().unwrap();
+        let crossfader_id = graph.add_new_node(Box::new(CrossfaderNode::new(0.5))).ok().unwrap();
+        if graph.connect_node(gen1_id, crossfader_id, 0).is_err() { panic!(); }
+        if graph.connect_node(gen2_id, fx_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx_id, crossfader_id, 1).is_err() { panic!(); }
+        if graph.connect_node_to_output(crossfader_id).is_err() { panic!(); }
+        if graph.prepare(AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 4 }).is_err() { panic!(); }
+
+        //  Equal power in the middle, then a ramp over to the second input
+        let half_power = std::f32::consts::FRAC_1_SQRT_2;
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => assert_close(buffer, &[1.5 * half_power; 4]),
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+
+        if graph.set_node_parameters(crossfader_id, &[1.0]).is_err() { panic!(); }
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        assert_close(&buffer, &[0.5; 4]);
+    }
+}
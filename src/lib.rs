@@ -2,11 +2,22 @@ pub mod AudioToolbox {
 //! # Audio Toolbox
 //! Module containing structures for audio such as audio graphs and the nodes that are inside them
 
+    use std::collections::VecDeque;
+    use std::fmt::Write;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    use super::Json::JsonValue;
+
+    #[derive(Debug)]
     pub struct Error {
         pub code: ErrorCodes,
         pub message: String
     }
 
+    #[derive(Clone, Copy, PartialEq, Debug)]
     pub enum ErrorCodes {
         NodeIDNonExistent,
         NodeInputPortInvalid,
@@ -14,14 +25,38 @@ pub mod AudioToolbox {
         NodeParentAlreadyExists,
         NodeConnectingToItself,
         CannotAddOutputTypeNode,
+        CannotConnectOutputNode,
         ConnectionAlreadyExists,
         InvalidBufferSize,
         InvalidSamplingFrequency,
         AudioGraphNotPrepared,
-        AudioGraphRunning
+        AudioGraphRunning,
+        PatchInvalid,
+        PatchVersionUnsupported,
+        NodeTypeUnknown,
+        NodeNotSerializable,
+        FileAccessFailed,
+        NodeNameInvalid,
+        NodeNameAlreadyExists,
+        NodeNameUnknown,
+        TransactionOpen,
+        TransactionNotOpen,
+        NodeInputPortInUse,
+        ConnectionCreatesCycle,
+        ConnectionNonExistent,
+        ChannelCountMismatch,
+        InvalidChannelCount,
+        AudioFileInvalid,
+        AudioFileUnsupported,
+        NodeUnconnected,
+        NodeUnreachable,
+        NodeInputUnfilled,
+        OutputUnconnected,
+        GraphInvalid
     }
 
-    pub trait AudioNode {
+    /// Nodes must be `Send` since the audio graph may process independent branches on worker threads (see `AudioGraph::enable_parallel_processing()`)
+    pub trait AudioNode: Send {
         /// Do any initializations that need to be done for a given node (allocating space, sampling freq, coefficients etc).  
         /// Called by the audio graph instance when calling `AudioGraph::prepare()`
         fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {}
@@ -46,13 +81,54 @@ pub mod AudioToolbox {
         /// Parameters are packed into an array.  Each element maps to some parameter defined by the trait implementor
         fn change_parameters<'a>(&mut self, parameters: &'a [f32]) {}
 
+        /// Get the current parameters of a node, packed the same way as for `change_parameters()`.
+        /// Used when saving a graph to a patch
+        fn get_parameters(&self) -> Vec<f32> { vec![] }
+
+        /// Get the name the node type is registered under in a `Patch::NodeRegistry` (e.g. "biquad").
+        /// Nodes that return None cannot be saved to a patch
+        fn get_type_name(&self) -> Option<&str> { None }
+
+        /// Get a name for each element of the array passed to `change_parameters()`, in the same order
+        fn get_parameter_names(&self) -> &[&str] { &[] }
+
+        /// Open or close the node's gate, e.g. to start and release an envelope or to start and stop a file player.
+        /// Gates are performance state rather than settings, so unlike parameters they are neither recorded in the edit history nor saved to patches
+        fn set_gate(&mut self, _open: bool) {}
+
         /// Reset state of node
         fn reset(&mut self) {}
 
+        /// Check whether an input port has to be connected for the node to do anything useful.  `AudioGraph::validate()` reports processed nodes with unfilled required inputs.
+        /// By default the first input of an effect is required
+        fn is_input_required(&self, port: usize) -> bool {
+            *self.get_node_type() == AudioNodeType::Effect && port == 0
+        }
+
+        /// Get the number of audio channels every node connected to this node's inputs has to produce
+        fn get_number_of_input_channels(&self) -> usize { 1 }
+
+        /// Get the channel of the node's buffer at which the signal connected to an input port starts.
+        /// By default the signals of all ports are summed into the same channels.  Nodes with modulation or sidechain inputs give each port channels of its own,
+        /// e.g. a stereo compressor puts its sidechain (port 1) at channel 2, after the two channels of its main input
+        fn get_input_port_channel(&self, _port: usize) -> usize { 0 }
+
+        /// Get the number of audio channels the node produces.
+        /// Multichannel audio is passed to `process_block()` planar: channel `c` occupies samples `c * buffer_size` to `(c + 1) * buffer_size`.
+        /// The buffer holds as many channels as the larger of the input and output channel counts.  The inputs arrive in the first input channels
+        /// and the node writes its output to the first output channels
+        fn get_number_of_output_channels(&self) -> usize { 1 }
+
+        /// Get the number of samples by which the node delays its input (lookahead, linear-phase filters, FFT blocks etc).
+        /// The audio graph delays the other branches meeting this node's branch by the same amount so that they stay time-aligned.
+        /// Queried when the graph is prepared, after `init()` has been called
+        fn latency_samples(&self) -> usize { 0 }
+
         /// Get samples from a given node
         fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] { buffer }
     }
 
+    #[derive(Clone, Copy, PartialEq, Debug)]
     pub enum AudioNodeType {
         Test,
         Generator,
@@ -67,7 +143,8 @@ pub mod AudioToolbox {
     struct OutputNode {
         node_type: AudioNodeType,
         num_inputs: usize,
-        next_available_input: usize
+        next_available_input: usize,
+        num_channels: usize
     }
 
     impl AudioNode for OutputNode {
@@ -98,14 +175,50 @@ pub mod AudioToolbox {
                 self.next_available_input -= 1;
             }
         }
+
+        fn get_number_of_input_channels(&self) -> usize {
+            self.num_channels
+        }
+
+        fn get_number_of_output_channels(&self) -> usize {
+            self.num_channels
+        }
     }
 
     impl OutputNode {
-        fn new() -> OutputNode {
+        fn new(num_channels: usize) -> OutputNode {
             OutputNode {
                 node_type: AudioNodeType::Output,
                 num_inputs: 1,
-                next_available_input: 0
+                next_available_input: 0,
+                num_channels
+            }
+        }
+    }
+
+    /// Takes the place of a removed node so that the IDs of the remaining nodes do not change
+    struct VacantNode {
+        node_type: AudioNodeType
+    }
+
+    impl AudioNode for VacantNode {
+        fn get_node_type(&self) -> &AudioNodeType {
+            &self.node_type
+        }
+
+        fn get_number_of_inputs(&self) -> usize {
+            0
+        }
+
+        fn get_next_available_input(&self) -> Option<usize> {
+            None
+        }
+    }
+
+    impl VacantNode {
+        fn new() -> VacantNode {
+            VacantNode {
+                node_type: AudioNodeType::Unknown
             }
         }
     }
@@ -113,12 +226,41 @@ pub mod AudioToolbox {
     
     /// This struct carries information about audio playback settings such as sampling frequency and buffer size.  
     /// An instance of this struct is passed to AudioGraph::prepare() before the audio graph is run.
+    #[derive(Clone, Copy, Debug)]
     pub struct AudioRuntimeParameters {
         pub sampling_freq: f32,
         pub buffer_size: usize,
     }
 
 
+    /// Escape text such as a node name for use inside a double-quoted Graphviz string.  Backslashes and quotes are escaped,
+    /// and line breaks in the text become Graphviz line breaks
+    fn escape_dot(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for character in text.chars() {
+            match character {
+                '\\' => escaped.push_str("\\\\"),
+                '"' => escaped.push_str("\\\""),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                _ => escaped.push(character)
+            }
+        }
+
+        escaped
+    }
+
+
+    /// A connection between two nodes of an audio graph, as returned by `AudioGraph::get_connections()`.
+    /// The output of `node_out_id` feeds input port `node_in_input_port` of `node_in_id`.  A `node_in_id` of 0 is the output node
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct Connection {
+        pub node_out_id: usize,
+        pub node_in_id: usize,
+        pub node_in_input_port: usize
+    }
+
+
     /// GraphTree is a map that keeps track of the connections between different nodes
     /// The AudioNode instances themselves do not track the relationships between other nodes.  That is the job of the GraphTree
     /// Each node that is added to the graph is assigned an identification, which is also the index of the nodes vector in this struct
@@ -129,407 +271,2445 @@ pub mod AudioToolbox {
 
     /// MapNode is a "leaf" structure that belongs to a NodeTree instance
     /// When an AudioNode is added to the AudioGraph, a corresponding MapNode is created and added to the node tree
+    /// It also carries the mixing state of the node (bypass, mute and solo), which the audio graph applies to the node's output
     struct MapNode {
+        name: Option<String>,
         parent: Option<usize>,
+        input_port: usize,
         children: Vec<usize>,
+        bypassed: bool,
+        muted: bool,
+        soloed: bool,
+        silenced_by_solo: bool,
+        vacant: bool
     }
 
     impl MapNode {
         fn new() -> MapNode {
             MapNode {
+                name: None,
                 parent: None,
+                input_port: 0,
                 children: vec![],
+                bypassed: false,
+                muted: false,
+                soloed: false,
+                silenced_by_solo: false,
+                vacant: false
             }
         }
     }
 
 
+    /// A reversible edit of the graph.  Edits that move a node out of the graph hold on to it so that it can be put back
+    enum GraphEdit {
+        AddNode { node_id: usize, removed: Option<RemovedNode> },
+        RemoveNode { node_id: usize, removed: Option<RemovedNode> },
+        Connect { connection: Connection, index: usize },
+        Disconnect { connection: Connection, index: usize },
+        SetParameters { node_id: usize, old_parameters: Vec<f32>, new_parameters: Vec<f32> }
+    }
 
-    /// A structure that holds audio nodes and obtains audio samples from them via calls to `process_block()`
-    /// Nodes are created by the user and registered into the graph using `add_new_node()`.  
-    /// 
-    /// ## Example Routine
-    /// 
-    /// ```
-    /// use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
-    /// use audio_graph::ModelNodes::*;
-    /// 
-    /// let mut graph = AudioGraph::new();
-    /// 
-    /// //  Create a generator node and test node.  Connections will look like this:
-    /// //  [n1] -> [n2] -> [Output]
-    /// let n1 = Box::new(TestGenNode::new());
-    /// let n2 = Box::new(TestFXNode::new());
-    /// 
-    /// //  Add nodes to graph and get their ids
-    /// let mut n1_id: usize = 0;
-    /// let mut n2_id: usize = 0;
-    /// 
-    /// match graph.add_new_node(n1) {
-    ///     Ok(i) => {n1_id = i;},
-    ///     Err(e) => {}
-    /// }
-    /// 
-    /// match graph.add_new_node(n2) {
-    ///     Ok(i) => {n2_id = i;},
-    ///     Err(e) => {}
-    /// }
-    /// 
-    /// //  Establish connections between nodes
-    /// graph.connect_node(n1_id, n2_id, 0);
-    /// graph.connect_node_to_output(n2_id);
-    /// 
-    /// //  Create AudioRuntimeParameters
-    /// let runtime_params = AudioRuntimeParameters {
-    ///     sampling_freq: 44_100.0,
-    ///     buffer_size: 512
-    /// };
-    /// 
-    /// //  Prepare graph for operation
-    /// match graph.prepare(runtime_params) {
-    ///     Ok(()) => {
-    ///         //  Get samples from graph
-    ///         let mut buffer = [0.0; 512];
-    ///         
-    ///         //  process_block() would be called in a callback, loop etc
-    ///         let result = graph.process_block(&mut buffer);
-    ///         match result {
-    ///             Ok(buf) => {},
-    ///             Err(e) => {}
-    ///         }
-    ///     },
-    ///     Err(e) => {}
-    /// }
-    /// 
-    /// ```
-    pub struct AudioGraph {
-        nodes: Vec<Box<dyn AudioNode + 'static>>,
-        graph_map: NodeTree,
-        iter_stack: Vec<(usize, usize, usize)>,
-        iter_stack_size: usize,
-        audio_runtime_params: AudioRuntimeParameters,
-        graph_running: bool
+    impl GraphEdit {
+        /// Parameter changes are the only edits that can be undone or redone while the graph is running
+        fn changes_topology(&self) -> bool {
+            !matches!(self, GraphEdit::SetParameters { .. })
+        }
     }
 
+    /// A node taken out of the graph together with everything needed to put it back exactly where it was.
+    /// Connections are stored with their position in the receiving node's list of children, since that is the order inputs are summed in
+    struct RemovedNode {
+        node: Box<dyn AudioNode>,
+        name: Option<String>,
+        bypassed: bool,
+        muted: bool,
+        soloed: bool,
+        parent: Option<(Connection, usize)>,
+        children: Vec<(Connection, usize)>
+    }
 
-    impl AudioGraph {
-        /// Create a new audio graph instance
-        pub fn new() -> AudioGraph {
-            AudioGraph {
-                nodes: vec![Box::new(OutputNode::new())],
-                graph_map: NodeTree {
-                    nodes: vec![MapNode {parent: None, children: vec![]}]
-                },
-                iter_stack: vec![(0, 0, 0)],
-                iter_stack_size: 0,
-                audio_runtime_params: AudioRuntimeParameters {
-                                            sampling_freq: 0.0,
-                                            buffer_size: 0
-                },
-                graph_running: false
-            }
-        }
+    /// Undo and redo stacks of groups of edits.  Each group is undone or redone as a whole
+    struct EditHistory {
+        undo_stack: VecDeque<Vec<GraphEdit>>,
+        redo_stack: Vec<Vec<GraphEdit>>,
+        max_length: usize
+    }
 
-        /// Add an AudioNode to the graph.  
-        /// NOTE that calling this function will NOT establish any connections to other nodes.  It simply transfers ownership of the node to the graph.  
-        /// This function will return an identification number that the user can then use to reference the added node when making connections/disconnections
-        pub fn add_new_node(&mut self, n: Box<dyn AudioNode + 'static>) -> Result<usize, Error> {
-            if self.graph_running == true {
-                return Err( Error {
-                    code: ErrorCodes::AudioGraphRunning,
-                    message: String::from("Audio Graph is running!")
-                });
+    impl EditHistory {
+        fn new(max_length: usize) -> EditHistory {
+            EditHistory {
+                undo_stack: VecDeque::new(),
+                redo_stack: vec![],
+                max_length: max_length.max(1)
             }
+        }
 
-            match n.get_node_type() {
-                AudioNodeType::Output => { return Err(Error {
-                    code: ErrorCodes::CannotAddOutputTypeNode,
-                    message: String::from("Cannot add output type node to graph")
-                }); },
+        /// Record a new group of edits.  Anything that was undone can no longer be redone, and the oldest group is forgotten once the history is full
+        fn push(&mut self, edits: Vec<GraphEdit>) {
+            self.redo_stack.clear();
+            self.undo_stack.push_back(edits);
 
-                _ => {}
+            while self.undo_stack.len() > self.max_length {
+                self.undo_stack.pop_front();
             }
-            
-            self.nodes.push(n);
-            self.graph_map.nodes.push(MapNode::new());
-            self.iter_stack.push((0, 0, 0));
-
-            Ok(self.nodes.len() - 1)
         }
+    }
 
-        /// Connect a node to the output node.  
-        /// Note that the output node can only have one child.
-        pub fn connect_node_to_output(&mut self, node_out_id: usize) -> Result<(), Error> {
-            if self.graph_running == true {
-                return Err( Error {
-                    code: ErrorCodes::AudioGraphRunning,
-                    message: String::from("Audio Graph is running!")
-                });
+
+    /// A fixed length ring of block timings.  The profiler keeps one of these per node and one for the whole graph.
+    /// All memory is allocated up front so that recording a timing never allocates on the audio thread
+    struct TimingWindow {
+        timings: Vec<Duration>,
+        position: usize,
+        count: usize
+    }
+
+    impl TimingWindow {
+        fn new(length: usize) -> TimingWindow {
+            TimingWindow {
+                timings: vec![Duration::ZERO; length.max(1)],
+                position: 0,
+                count: 0
             }
+        }
 
-            match self.nodes[0].get_next_available_input() {
-                Some(_) => {
-                    self.graph_map.nodes[0].children.push(node_out_id);
-                    self.nodes[0].connect_input();
+        fn record(&mut self, timing: Duration) {
+            self.timings[self.position] = timing;
+            self.position = (self.position + 1) % self.timings.len();
 
-                    return Ok(());
-                },
-                None => return Err(Error {
-                                code: ErrorCodes::NodeNoMoreInputs,
-                                message: String::from("Output node already has a connection to a child node")
-                })
+            if self.count < self.timings.len() {
+                self.count += 1;
             }
         }
 
-        /// Connect two nodes together.  
-        /// The output of node1 is connected to the input port of node2:
-        /// 
-        /// [node_out]->[node_in]
-        pub fn connect_node(&mut self, node_out_id: usize, node_in_id: usize, node_in_input_port: usize) -> Result<(), Error> {
-            if self.graph_running == true {
-                return Err( Error {
-                    code: ErrorCodes::AudioGraphRunning,
-                    message: String::from("Audio Graph is running!")
-                });
+        fn get_statistics(&self) -> Option<TimingStatistics> {
+            if self.count == 0 {
+                return None;
             }
 
-            let validation_result = self.validate_node_inputs(node_out_id, node_in_id, node_in_input_port);
-            match validation_result {
-                Err(e) => {return Err(e);},
-                Ok(()) => {
-                    // Find the associated MapNodes in the Tree and set parent/children
-                    // You cannot assign more than one parent for a given node 
-                    match self.graph_map.nodes[node_out_id].parent {
-                        None => {
-                            self.graph_map.nodes[node_in_id].children.push(node_out_id);
-                            self.nodes[node_in_id].connect_input();
+            //  Until the window wraps around, only the first `count` entries hold valid timings
+            let mut sorted_timings = self.timings[..self.count].to_vec();
+            sorted_timings.sort();
 
-                            self.graph_map.nodes[node_out_id].parent = Some(node_in_id);
-                        }
+            Some(TimingStatistics { sorted_timings })
+        }
+    }
 
-                        Some(_i) => {return Err(Error {
-                                        code: ErrorCodes::NodeParentAlreadyExists,
-                                        message: String::from("node_out already has a parent: ")
-                                    });
-                                    }
-                        }
-                    }
-            }
+    /// Rolling timing statistics over the most recent blocks processed by the audio graph.
+    /// Obtained from `AudioGraph::get_node_timing()` and `AudioGraph::get_graph_timing()` while profiling is enabled
+    pub struct TimingStatistics {
+        sorted_timings: Vec<Duration>
+    }
 
-            Ok(())
+    impl TimingStatistics {
+        /// Number of blocks the statistics were computed over
+        pub fn get_number_of_blocks(&self) -> usize {
+            self.sorted_timings.len()
         }
 
-        pub fn disconnect_node_from_output(&mut self, node_out_id: usize) {
-            if self.graph_running == true {
-                return;
-            }
-
-            if self.check_node_exists(&node_out_id) == false {
-                return;
-            }
+        /// Mean processing time per block
+        pub fn mean(&self) -> Duration {
+            let total: Duration = self.sorted_timings.iter().sum();
+            total / self.sorted_timings.len() as u32
+        }
 
-            //  Ensure that the node is connected to the output node
-            if self.graph_map.nodes[node_out_id].parent != Some(0) {
-                return;
-            }
+        /// Longest processing time of a single block
+        pub fn max(&self) -> Duration {
+            self.sorted_timings[self.sorted_timings.len() - 1]
+        }
 
-            //  Remove connections
-            self.graph_map.nodes[0].children.pop();
-            self.graph_map.nodes[node_out_id].parent = None;
+        /// Processing time below which `percentile` percent of the blocks fall (nearest-rank method).
+        /// `percentile` is clamped to the range [0, 100]
+        pub fn percentile(&self, percentile: f32) -> Duration {
+            let percentile = percentile.clamp(0.0, 100.0);
+            let rank = (percentile / 100.0 * self.sorted_timings.len() as f32).ceil() as usize;
 
-            self.nodes[0].disconnect_input();
+            self.sorted_timings[rank.clamp(1, self.sorted_timings.len()) - 1]
         }
+    }
 
-        /// Remove connections between two nodes
-        pub fn disconnect_node(&mut self, node_out_id: usize, node_in_id: usize) {
-            if self.graph_running == true {
-                return;
-            }
+    /// DSP load of the audio graph expressed as a fraction of the real-time budget of one block (`buffer_size / sampling_freq`).
+    /// A load of 1.0 means that computing a block takes exactly as long as playing it back
+    pub struct DspLoad {
+        pub mean: f32,
+        pub max: f32
+    }
 
-            //  Make sure nodes actually exist
-            if self.check_node_exists(&node_out_id) == false || self.check_node_exists(&node_in_id) == false {
-                return;
-            }
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum ValidationSeverity {
+        /// The graph runs, but probably not as intended
+        Warning,
+        /// The graph cannot produce sensible output
+        Error
+    }
 
-            //  Ensure that the connection between the two nodes actually exist
-            if self.graph_map.nodes[node_out_id].parent != Some(node_in_id) {
-                return;
-            }
+    /// A single finding of `AudioGraph::validate()`
+    pub struct ValidationIssue {
+        pub severity: ValidationSeverity,
+        pub code: ErrorCodes,
+        /// The node the issue is about, if it is about a specific node
+        pub node_id: Option<usize>,
+        pub message: String
+    }
 
-            //  Remove connections
-            for i in 0..self.graph_map.nodes[node_in_id].children.len() {
-                if i == node_out_id {
-                    self.graph_map.nodes[node_in_id].children.remove(i);
-                }
-            }
+    /// Everything `AudioGraph::validate()` found wrong with a graph.  Printing the report lists one issue per line
+    pub struct ValidationReport {
+        pub issues: Vec<ValidationIssue>
+    }
 
-            self.graph_map.nodes[node_out_id].parent = None;
-            self.nodes[node_in_id].disconnect_input();
+    impl ValidationReport {
+        /// Check whether the graph is free of errors (warnings are allowed)
+        pub fn is_valid(&self) -> bool {
+            !self.issues.iter().any(|issue| issue.severity == ValidationSeverity::Error)
         }
 
-        /// Get a reference to a node in the audio graph
-        pub fn get_node(&self, node_id: usize) -> Option<&Box<dyn AudioNode>> {
-            if self.check_node_exists(&node_id) == false {
-                return None;
-            }
+        pub fn get_errors(&self) -> Vec<&ValidationIssue> {
+            self.issues.iter().filter(|issue| issue.severity == ValidationSeverity::Error).collect()
+        }
 
-            Some(&self.nodes[node_id])
+        pub fn get_warnings(&self) -> Vec<&ValidationIssue> {
+            self.issues.iter().filter(|issue| issue.severity == ValidationSeverity::Warning).collect()
         }
+    }
 
-        /// Ensures that connecting nodes are valid and do not already have connections between them
-        fn validate_node_inputs(&self, node_out_id: usize, node_in_id: usize, node_in_input_port: usize) -> Result<(), Error> {
-            //  Make sure node actually exists in graph
-            if self.check_node_exists(&node_in_id) == false || self.check_node_exists(&node_out_id) == false {
-                return Err(Error{
-                    code: ErrorCodes::NodeIDNonExistent,
-                    message: String::from("Node ID does not exist in graph")
-                });
+    impl std::fmt::Display for ValidationReport {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            for issue in &self.issues {
+                let severity = match issue.severity {
+                    ValidationSeverity::Warning => "warning",
+                    ValidationSeverity::Error => "error"
+                };
+                writeln!(f, "{}: {}", severity, issue.message)?;
             }
 
-            //  Ensure that a valid input port is passed in
-            if node_in_input_port >= self.nodes[node_in_id].get_number_of_inputs() {
-                return Err(Error {
-                    code: ErrorCodes::NodeInputPortInvalid,
-                    message: String::from("Node input port not valid")
+            Ok(())
+        }
+    }
+
+    /// Collects per-node and whole graph timings while the audio graph is running
+    struct Profiler {
+        node_timings: Vec<TimingWindow>,
+        graph_timings: TimingWindow,
+        window_length: usize
+    }
+
+    impl Profiler {
+        fn new(num_nodes: usize, window_length: usize) -> Profiler {
+            Profiler {
+                node_timings: (0..num_nodes).map(|_| TimingWindow::new(window_length)).collect(),
+                graph_timings: TimingWindow::new(window_length),
+                window_length
+            }
+        }
+    }
+
+
+    /// Graphs with fewer scheduled nodes than this are processed on the calling thread even when parallel processing is enabled.
+    /// Below this size, waking up the worker threads costs more than it saves
+    const DEFAULT_PARALLEL_NODE_THRESHOLD: usize = 16;
+
+    /// Number of times a thread of the parallel executor spins waiting for a ready node before it starts yielding
+    const MAX_IDLE_SPINS: usize = 128;
+
+    /// Length of the crossfades used when a node is bypassed, muted or soloed while the graph is running
+    const CROSSFADE_TIME_SECONDS: f32 = 0.01;
+
+    /// Name of the output node, which is always node 0
+    pub const OUTPUT_NODE_NAME: &str = "output";
+
+    /// The processing schedule of an audio graph, built once by `AudioGraph::prepare()`.
+    /// Every node that is reachable from the output node gets its own output buffer.  A node's input is the sum of the output buffers of its children,
+    /// so independent branches of the graph do not share any state and may be processed in any order
+    struct CompiledGraph {
+        order: Vec<usize>,
+        buffers: Vec<Vec<f32>>,
+        output_lengths: Vec<usize>,
+        compensation_delays: Vec<CompensationDelay>,
+        faders: Vec<NodeFader>,
+        job: ParallelJob
+    }
+
+    impl CompiledGraph {
+        fn new() -> CompiledGraph {
+            CompiledGraph {
+                order: vec![],
+                buffers: vec![],
+                output_lengths: vec![],
+                compensation_delays: vec![],
+                faders: vec![],
+                job: ParallelJob::new(0, 0)
+            }
+        }
+    }
+
+    /// A plain delay line applied to the output of a node whose branch has less latency than the other branches it is mixed with.
+    /// Planar buffers are delayed channel by channel
+    struct CompensationDelay {
+        delay_lines: Vec<Vec<f32>>,
+        position: usize
+    }
+
+    impl CompensationDelay {
+        fn new(delay_samples: usize, num_channels: usize) -> CompensationDelay {
+            CompensationDelay {
+                delay_lines: (0..num_channels.max(1)).map(|_| vec![0.0; delay_samples]).collect(),
+                position: 0
+            }
+        }
+
+        fn get_delay(&self) -> usize {
+            self.delay_lines[0].len()
+        }
+
+        fn process(&mut self, buffer: &mut [f32]) {
+            let delay = self.get_delay();
+            if delay == 0 {
+                return;
+            }
+
+            let channel_length = buffer.len() / self.delay_lines.len();
+            let mut position = self.position;
+
+            for (channel, delay_line) in buffer.chunks_mut(channel_length.max(1)).zip(self.delay_lines.iter_mut()) {
+                position = self.position;
+                for sample in channel.iter_mut() {
+                    std::mem::swap(&mut delay_line[position], sample);
+                    position = (position + 1) % delay;
+                }
+            }
+
+            self.position = position;
+        }
+    }
+
+    /// A linear ramp used to fade between two states without clicks
+    struct Ramp {
+        value: f32,
+        step: f32
+    }
+
+    impl Ramp {
+        fn next(&mut self, target: f32) -> f32 {
+            if self.value < target {
+                self.value = (self.value + self.step).min(target);
+            } else if self.value > target {
+                self.value = (self.value - self.step).max(target);
+            }
+
+            self.value
+        }
+    }
+
+    /// Runtime state used to apply a node's bypass, mute and solo settings to its output.
+    /// The dry signal is delayed by the node's own latency so that a bypassed node still lines up with the compensated branches around it
+    struct NodeFader {
+        bypass_mix: Ramp,
+        gain: Ramp,
+        dry_buffer: Vec<f32>,
+        dry_delay: CompensationDelay
+    }
+
+    impl NodeFader {
+        fn new(map_node: &MapNode, output_length: usize, num_channels: usize, latency: usize, fade_samples: usize) -> NodeFader {
+            let step = 1.0 / fade_samples.max(1) as f32;
+
+            NodeFader {
+                bypass_mix: Ramp { value: NodeFader::get_bypass_target(map_node), step },
+                gain: Ramp { value: NodeFader::get_gain_target(map_node), step },
+                dry_buffer: vec![0.0; output_length],
+                dry_delay: CompensationDelay::new(latency, num_channels)
+            }
+        }
+
+        fn get_bypass_target(map_node: &MapNode) -> f32 {
+            if map_node.bypassed { 1.0 } else { 0.0 }
+        }
+
+        fn get_gain_target(map_node: &MapNode) -> f32 {
+            if map_node.muted || map_node.silenced_by_solo { 0.0 } else { 1.0 }
+        }
+
+        /// The dry delay line is always fed when the node has latency, so that it holds the right samples the moment the node gets bypassed
+        fn needs_dry_signal(&self, bypass_target: f32) -> bool {
+            bypass_target > 0.0 || self.bypass_mix.value > 0.0 || self.dry_delay.get_delay() > 0
+        }
+
+        /// Copy the node's input into the dry buffer.  When the node has fewer input than output channels, the input channels are repeated
+        fn capture_dry_signal(&mut self, input: &[f32], num_input_channels: usize, buffer_size: usize) {
+            for (channel, dry_channel) in self.dry_buffer.chunks_mut(buffer_size.max(1)).enumerate() {
+                let input_start = (channel % num_input_channels.max(1)) * buffer_size;
+                dry_channel.copy_from_slice(&input[input_start..input_start + dry_channel.len()]);
+            }
+
+            self.dry_delay.process(&mut self.dry_buffer);
+        }
+
+        fn is_fully_bypassed(&self, bypass_target: f32) -> bool {
+            bypass_target == 1.0 && self.bypass_mix.value == 1.0
+        }
+
+        fn apply_bypass(&mut self, buffer: &mut [f32], bypass_target: f32) {
+            if bypass_target == 0.0 && self.bypass_mix.value == 0.0 {
+                return;
+            }
+
+            for (sample, dry_sample) in buffer.iter_mut().zip(self.dry_buffer.iter()) {
+                let mix = self.bypass_mix.next(bypass_target);
+                *sample = *sample * (1.0 - mix) + *dry_sample * mix;
+            }
+        }
+
+        fn apply_gain(&mut self, buffer: &mut [f32], gain_target: f32) {
+            if gain_target == 1.0 && self.gain.value == 1.0 {
+                return;
+            }
+
+            for sample in buffer.iter_mut() {
+                *sample *= self.gain.next(gain_target);
+            }
+        }
+    }
+
+    /// Latency of every node in a graph, computed from the processing order.
+    /// `path_latency` is the latency accumulated from the leaves of the graph up to and including a node.
+    /// `compensation` is the delay that must be added to a node's output so that it lines up with the slowest of its siblings
+    struct LatencyMap {
+        path_latency: Vec<usize>,
+        compensation: Vec<usize>
+    }
+
+    /// Raw views into the graph that are needed to process a single node.
+    /// These are shared between the calling thread and the worker threads for the duration of one `AudioGraph::process_block()` call
+    struct BlockContext {
+        nodes: *mut Box<dyn AudioNode>,
+        map_nodes: *const MapNode,
+        buffers: *mut Vec<f32>,
+        output_lengths: *const usize,
+        buffer_size: usize,
+        compensation_delays: *mut CompensationDelay,
+        faders: *mut NodeFader,
+        node_timings: *mut TimingWindow
+    }
+
+    //  SAFETY: The pointers are only set for the duration of `AudioGraph::process_block()`, which borrows the graph mutably and resets the context
+    //  to `BlockContext::empty()` before returning.  Between blocks the context holds only null pointers, so moving the graph to another thread
+    //  (e.g. into an audio callback) cannot leave a dangling view behind
+    unsafe impl Send for BlockContext {}
+
+    impl BlockContext {
+        fn empty() -> BlockContext {
+            BlockContext {
+                nodes: std::ptr::null_mut(),
+                map_nodes: std::ptr::null(),
+                buffers: std::ptr::null_mut(),
+                output_lengths: std::ptr::null(),
+                buffer_size: 0,
+                compensation_delays: std::ptr::null_mut(),
+                faders: std::ptr::null_mut(),
+                node_timings: std::ptr::null_mut()
+            }
+        }
+
+        /// Sum the outputs of a node's children into its buffer, run the node over it and apply the node's bypass, mute and solo settings.
+        /// Both the serial and the parallel executor go through this function, which keeps their output bit-identical
+        ///
+        /// # Safety
+        /// All children of `node_id` must have been processed, and no other thread may be processing `node_id` or any of its children
+        unsafe fn process_node(&self, node_id: usize) {
+            let map_node = &*self.map_nodes.add(node_id);
+            let buffer = &mut *self.buffers.add(node_id);
+            let node = &mut *self.nodes.add(node_id);
+
+            buffer.fill(0.0);
+            for child in &map_node.children {
+                let child_buffer = &*self.buffers.add(*child);
+                let child_output = &child_buffer[..*self.output_lengths.add(*child)];
+                let port_offset = node.get_input_port_channel((*self.map_nodes.add(*child)).input_port) * self.buffer_size;
+
+                for (sample, child_sample) in buffer.iter_mut().skip(port_offset).zip(child_output.iter()) {
+                    *sample += *child_sample;
+                }
+            }
+
+            let fader = &mut *self.faders.add(node_id);
+            let bypass_target = NodeFader::get_bypass_target(map_node);
+
+            if fader.needs_dry_signal(bypass_target) {
+                fader.capture_dry_signal(buffer, node.get_number_of_input_channels(), self.buffer_size);
+            }
+
+            //  A fully bypassed node is not processed at all
+            if !fader.is_fully_bypassed(bypass_target) {
+                if self.node_timings.is_null() {
+                    node.process_block(buffer);
+                } else {
+                    let node_start = Instant::now();
+                    node.process_block(buffer);
+                    (*self.node_timings.add(node_id)).record(node_start.elapsed());
+                }
+            }
+
+            let output = &mut buffer[..*self.output_lengths.add(node_id)];
+            fader.apply_bypass(output, bypass_target);
+            fader.apply_gain(output, NodeFader::get_gain_target(map_node));
+
+            (*self.compensation_delays.add(node_id)).process(output);
+        }
+    }
+
+    /// Per-block state of the parallel executor.
+    /// Every scheduled node has an atomic count of children that still have to be processed.  Whichever thread finishes the last child of a node
+    /// pushes that node onto the ready queue, so no locks are taken while a block is being processed
+    struct ParallelJob {
+        context: BlockContext,
+        pending_children: Vec<AtomicUsize>,
+        ready_queue: Vec<AtomicUsize>,
+        ready_head: AtomicUsize,
+        ready_tail: AtomicUsize,
+        completed: AtomicUsize,
+        num_scheduled: usize
+    }
+
+    impl ParallelJob {
+        fn new(num_nodes: usize, num_scheduled: usize) -> ParallelJob {
+            ParallelJob {
+                context: BlockContext::empty(),
+                pending_children: (0..num_nodes).map(|_| AtomicUsize::new(0)).collect(),
+                ready_queue: (0..num_scheduled).map(|_| AtomicUsize::new(0)).collect(),
+                ready_head: AtomicUsize::new(0),
+                ready_tail: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                num_scheduled
+            }
+        }
+
+        /// Queue slots hold `node_id + 1` so that an empty slot can be told apart from node 0
+        fn push_ready(&self, node_id: usize) {
+            let slot = self.ready_tail.fetch_add(1, Ordering::AcqRel);
+            self.ready_queue[slot].store(node_id + 1, Ordering::Release);
+        }
+
+        fn pop_ready(&self) -> Option<usize> {
+            loop {
+                let head = self.ready_head.load(Ordering::Acquire);
+                if head >= self.ready_tail.load(Ordering::Acquire) {
+                    return None;
+                }
+
+                if self.ready_head.compare_exchange_weak(head, head + 1, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                    //  The slot has been claimed by a pushing thread but may not have been written yet
+                    loop {
+                        let value = self.ready_queue[head].load(Ordering::Acquire);
+                        if value != 0 {
+                            return Some(value - 1);
+                        }
+                        std::hint::spin_loop();
+                    }
+                }
+            }
+        }
+
+        /// Process ready nodes until every scheduled node of the block has been processed
+        ///
+        /// # Safety
+        /// `context` must point into a graph that outlives the call and is not otherwise accessed until the job has completed
+        unsafe fn run(&self) {
+            let mut idle_spins = 0;
+
+            while self.completed.load(Ordering::Acquire) < self.num_scheduled {
+                match self.pop_ready() {
+                    Some(node_id) => {
+                        idle_spins = 0;
+                        self.context.process_node(node_id);
+
+                        if let Some(parent) = (*self.context.map_nodes.add(node_id)).parent {
+                            if self.pending_children[parent].fetch_sub(1, Ordering::AcqRel) == 1 {
+                                self.push_ready(parent);
+                            }
+                        }
+
+                        self.completed.fetch_add(1, Ordering::AcqRel);
+                    },
+                    //  Spin briefly since ready nodes usually follow quickly, then give the core away so that long serial chains
+                    //  do not keep every worker busy for the whole block
+                    None => {
+                        if idle_spins < MAX_IDLE_SPINS {
+                            idle_spins += 1;
+                            std::hint::spin_loop();
+                        } else {
+                            std::thread::yield_now();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// State shared between an `AudioGraph` and its worker threads
+    struct WorkerPoolShared {
+        generation: AtomicUsize,
+        active_workers: AtomicUsize,
+        shutdown: AtomicBool,
+        job: AtomicPtr<ParallelJob>
+    }
+
+    /// A fixed pool of worker threads used by the parallel executor.
+    /// Workers sleep until `run()` publishes a new job, help process it and go back to sleep
+    struct WorkerPool {
+        shared: Arc<WorkerPoolShared>,
+        workers: Vec<JoinHandle<()>>
+    }
+
+    impl WorkerPool {
+        fn new(num_threads: usize) -> WorkerPool {
+            let shared = Arc::new(WorkerPoolShared {
+                generation: AtomicUsize::new(0),
+                active_workers: AtomicUsize::new(0),
+                shutdown: AtomicBool::new(false),
+                job: AtomicPtr::new(std::ptr::null_mut())
+            });
+
+            let workers = (0..num_threads).map(|_| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || WorkerPool::worker_loop(shared))
+            }).collect();
+
+            WorkerPool { shared, workers }
+        }
+
+        fn worker_loop(shared: Arc<WorkerPoolShared>) {
+            let mut seen_generation = 0;
+
+            loop {
+                if shared.shutdown.load(Ordering::Acquire) {
+                    return;
+                }
+
+                let generation = shared.generation.load(Ordering::Acquire);
+                if generation == seen_generation {
+                    std::thread::park();
+                    continue;
+                }
+
+                seen_generation = generation;
+
+                //  SAFETY: The job stays valid until every worker has decremented `active_workers`, which `run()` waits for
+                unsafe { (*shared.job.load(Ordering::Acquire)).run(); }
+                shared.active_workers.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+
+        /// Process a job on the calling thread and all worker threads, returning once every worker has let go of it
+        ///
+        /// # Safety
+        /// Same requirements as `ParallelJob::run()`
+        unsafe fn run(&self, job: &ParallelJob) {
+            self.shared.active_workers.store(self.workers.len(), Ordering::Release);
+            self.shared.job.store(job as *const ParallelJob as *mut ParallelJob, Ordering::Release);
+            self.shared.generation.fetch_add(1, Ordering::AcqRel);
+
+            for worker in &self.workers {
+                worker.thread().unpark();
+            }
+
+            job.run();
+
+            while self.shared.active_workers.load(Ordering::Acquire) > 0 {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    impl Drop for WorkerPool {
+        fn drop(&mut self) {
+            self.shared.shutdown.store(true, Ordering::Release);
+
+            for worker in self.workers.drain(..) {
+                worker.thread().unpark();
+                let _ = worker.join();
+            }
+        }
+    }
+
+
+
+    /// A structure that holds audio nodes and obtains audio samples from them via calls to `process_block()`
+    /// Nodes are created by the user and registered into the graph using `add_new_node()`.  
+    /// `Builder::GraphBuilder` and the `graph!` macro do the same by node name in far fewer lines.
+    /// 
+    /// ## Example Routine
+    /// 
+    /// ```
+    /// use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+    /// use audio_graph::ModelNodes::*;
+    /// 
+    /// let mut graph = AudioGraph::new();
+    /// 
+    /// //  Create a generator node and test node.  Connections will look like this:
+    /// //  [n1] -> [n2] -> [Output]
+    /// let n1 = Box::new(TestGenNode::new());
+    /// let n2 = Box::new(TestFXNode::new());
+    /// 
+    /// //  Add nodes to graph and get their ids
+    /// let mut n1_id: usize = 0;
+    /// let mut n2_id: usize = 0;
+    /// 
+    /// match graph.add_new_node(n1) {
+    ///     Ok(i) => {n1_id = i;},
+    ///     Err(e) => {}
+    /// }
+    /// 
+    /// match graph.add_new_node(n2) {
+    ///     Ok(i) => {n2_id = i;},
+    ///     Err(e) => {}
+    /// }
+    /// 
+    /// //  Establish connections between nodes
+    /// graph.connect_node(n1_id, n2_id, 0);
+    /// graph.connect_node_to_output(n2_id);
+    /// 
+    /// //  Create AudioRuntimeParameters
+    /// let runtime_params = AudioRuntimeParameters {
+    ///     sampling_freq: 44_100.0,
+    ///     buffer_size: 512
+    /// };
+    /// 
+    /// //  Prepare graph for operation
+    /// match graph.prepare(runtime_params) {
+    ///     Ok(()) => {
+    ///         //  Get samples from graph
+    ///         let mut buffer = [0.0; 512];
+    ///         
+    ///         //  process_block() would be called in a callback, loop etc
+    ///         let result = graph.process_block(&mut buffer);
+    ///         match result {
+    ///             Ok(buf) => {},
+    ///             Err(e) => {}
+    ///         }
+    ///     },
+    ///     Err(e) => {}
+    /// }
+    /// 
+    /// ```
+    pub struct AudioGraph {
+        nodes: Vec<Box<dyn AudioNode + 'static>>,
+        graph_map: NodeTree,
+        compiled: CompiledGraph,
+        audio_runtime_params: AudioRuntimeParameters,
+        graph_running: bool,
+        profiler: Option<Profiler>,
+        worker_pool: Option<WorkerPool>,
+        parallel_node_threshold: usize,
+        history: Option<EditHistory>,
+        transaction_depth: usize,
+        transaction_edits: Vec<GraphEdit>,
+        validate_on_prepare: bool
+    }
+
+
+    impl AudioGraph {
+        /// Create a new audio graph instance
+        pub fn new() -> AudioGraph {
+            AudioGraph {
+                nodes: vec![Box::new(OutputNode::new(1))],
+                graph_map: NodeTree {
+                    nodes: vec![MapNode { name: Some(String::from(OUTPUT_NODE_NAME)), ..MapNode::new() }]
+                },
+                compiled: CompiledGraph::new(),
+                audio_runtime_params: AudioRuntimeParameters {
+                                            sampling_freq: 0.0,
+                                            buffer_size: 0
+                },
+                graph_running: false,
+                profiler: None,
+                worker_pool: None,
+                parallel_node_threshold: DEFAULT_PARALLEL_NODE_THRESHOLD,
+                history: None,
+                transaction_depth: 0,
+                transaction_edits: vec![],
+                validate_on_prepare: false
+            }
+        }
+
+        /// Add an AudioNode to the graph.  
+        /// NOTE that calling this function will NOT establish any connections to other nodes.  It simply transfers ownership of the node to the graph.  
+        /// This function will return an identification number that the user can then use to reference the added node when making connections/disconnections
+        pub fn add_new_node(&mut self, n: Box<dyn AudioNode + 'static>) -> Result<usize, Error> {
+            if self.graph_running == true {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                });
+            }
+
+            match n.get_node_type() {
+                AudioNodeType::Output => { return Err(Error {
+                    code: ErrorCodes::CannotAddOutputTypeNode,
+                    message: String::from("Cannot add output type node to graph")
+                }); },
+
+                _ => {}
+            }
+            
+            self.nodes.push(n);
+            self.graph_map.nodes.push(MapNode::new());
+            self.sync_profiler_with_nodes();
+
+            let node_id = self.nodes.len() - 1;
+            self.record_edit(GraphEdit::AddNode { node_id, removed: None });
+
+            Ok(node_id)
+        }
+
+        /// Remove a node from the graph together with all of its connections.
+        /// The IDs of the other nodes do not change.  With the edit history enabled the removal can be undone, node state and connections included
+        pub fn remove_node(&mut self, node_id: usize) -> Result<(), Error> {
+            if self.graph_running == true {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                });
+            }
+
+            self.validate_mixing_target(node_id)?;
+
+            let removed = self.take_node(node_id);
+            self.record_edit(GraphEdit::RemoveNode { node_id, removed: Some(removed) });
+
+            Ok(())
+        }
+
+        /// Add an AudioNode to the graph and give it a name in one go.  See `AudioGraph::set_node_name()` for the rules names have to follow.
+        /// The node is not added if the name is rejected
+        pub fn add_new_named_node(&mut self, name: &str, n: Box<dyn AudioNode + 'static>) -> Result<usize, Error> {
+            self.validate_node_name(name)?;
+
+            let node_id = self.add_new_node(n)?;
+            self.graph_map.nodes[node_id].name = Some(String::from(name));
+
+            Ok(node_id)
+        }
+
+        /// Connect a node to the output node.  
+        /// Note that the output node can only have one child.
+        pub fn connect_node_to_output(&mut self, node_out_id: usize) -> Result<(), Error> {
+            if self.graph_running == true {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                });
+            }
+
+            if !self.check_node_exists(&node_out_id) || node_out_id == 0 {
+                return Err(Error {
+                    code: ErrorCodes::NodeIDNonExistent,
+                    message: format!("Node ID {} does not exist in graph", node_out_id)
+                });
+            }
+
+            if let Some(parent) = self.graph_map.nodes[node_out_id].parent {
+                return Err(Error {
+                    code: ErrorCodes::NodeParentAlreadyExists,
+                    message: format!("Node {} already has a parent: {}", self.describe_node(node_out_id), self.describe_node(parent))
+                });
+            }
+
+            self.check_channel_counts(node_out_id, 0)?;
+
+            match self.nodes[0].get_next_available_input() {
+                Some(_) => {
+                    let connection = Connection {
+                        node_out_id,
+                        node_in_id: 0,
+                        node_in_input_port: 0
+                    };
+                    let index = self.attach_connection(connection, usize::MAX);
+                    self.record_edit(GraphEdit::Connect { connection, index });
+
+                    return Ok(());
+                },
+                None => return Err(Error {
+                                code: ErrorCodes::NodeNoMoreInputs,
+                                message: format!("Output node already has a connection to node {}", self.describe_node(self.graph_map.nodes[0].children[0]))
+                })
+            }
+        }
+
+        /// Connect two nodes together.  
+        /// The output of node1 is connected to the input port of node2:
+        /// 
+        /// [node_out]->[node_in]
+        pub fn connect_node(&mut self, node_out_id: usize, node_in_id: usize, node_in_input_port: usize) -> Result<(), Error> {
+            if self.graph_running == true {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                });
+            }
+
+            let validation_result = self.validate_node_inputs(node_out_id, node_in_id, node_in_input_port);
+            match validation_result {
+                Err(e) => {return Err(e);},
+                Ok(()) => {
+                    // Find the associated MapNodes in the Tree and set parent/children
+                    // You cannot assign more than one parent for a given node 
+                    match self.graph_map.nodes[node_out_id].parent {
+                        None => {
+                            let connection = Connection {
+                                node_out_id,
+                                node_in_id,
+                                node_in_input_port
+                            };
+                            let index = self.attach_connection(connection, usize::MAX);
+                            self.record_edit(GraphEdit::Connect { connection, index });
+                        }
+
+                        Some(i) => {return Err(Error {
+                                        code: ErrorCodes::NodeParentAlreadyExists,
+                                        message: format!("Node {} already has a parent: {}", self.describe_node(node_out_id), self.describe_node(i))
+                                    });
+                                    }
+                        }
+                    }
+            }
+
+            Ok(())
+        }
+
+        pub fn disconnect_node_from_output(&mut self, node_out_id: usize) {
+            if self.graph_running == true {
+                return;
+            }
+
+            if self.check_node_exists(&node_out_id) == false {
+                return;
+            }
+
+            //  Ensure that the node is connected to the output node
+            if self.graph_map.nodes[node_out_id].parent != Some(0) {
+                return;
+            }
+
+            //  Remove connections
+            if let Some((connection, index)) = self.detach_connection(node_out_id) {
+                self.record_edit(GraphEdit::Disconnect { connection, index });
+            }
+        }
+
+        /// Remove connections between two nodes
+        pub fn disconnect_node(&mut self, node_out_id: usize, node_in_id: usize) {
+            if self.graph_running == true {
+                return;
+            }
+
+            //  Make sure nodes actually exist
+            if self.check_node_exists(&node_out_id) == false || self.check_node_exists(&node_in_id) == false {
+                return;
+            }
+
+            //  Ensure that the connection between the two nodes actually exist
+            if self.graph_map.nodes[node_out_id].parent != Some(node_in_id) {
+                return;
+            }
+
+            //  Remove connections
+            if let Some((connection, index)) = self.detach_connection(node_out_id) {
+                self.record_edit(GraphEdit::Disconnect { connection, index });
+            }
+        }
+
+        /// Get a reference to a node in the audio graph
+        pub fn get_node(&self, node_id: usize) -> Option<&Box<dyn AudioNode>> {
+            if self.check_node_exists(&node_id) == false {
+                return None;
+            }
+
+            Some(&self.nodes[node_id])
+        }
+
+        /// Get the number of nodes in the graph, including the output node
+        pub fn get_number_of_nodes(&self) -> usize {
+            self.graph_map.nodes.iter().filter(|map_node| !map_node.vacant).count()
+        }
+
+        /// Get the IDs of all nodes in the graph in ascending order, starting with the output node.
+        /// IDs of removed nodes are skipped, so the IDs are not necessarily contiguous
+        pub fn get_node_ids(&self) -> Vec<usize> {
+            (0..self.nodes.len()).filter(|node_id| self.check_node_exists(node_id)).collect()
+        }
+
+        /// Change the parameters of a node (see `AudioNode::change_parameters()`).  Can be called while the graph is running.
+        /// The change is recorded in the edit history if the node reports its parameters through `AudioNode::get_parameters()`
+        pub fn set_node_parameters(&mut self, node_id: usize, parameters: &[f32]) -> Result<(), Error> {
+            if !self.check_node_exists(&node_id) {
+                return Err(Error {
+                    code: ErrorCodes::NodeIDNonExistent,
+                    message: format!("Node ID {} does not exist in graph", node_id)
+                });
+            }
+
+            let old_parameters = self.nodes[node_id].get_parameters();
+            self.nodes[node_id].change_parameters(parameters);
+            let new_parameters = self.nodes[node_id].get_parameters();
+
+            if !old_parameters.is_empty() && old_parameters != new_parameters {
+                self.record_edit(GraphEdit::SetParameters { node_id, old_parameters, new_parameters });
+            }
+
+            Ok(())
+        }
+
+        /// Open or close the gate of a node (see `AudioNode::set_gate()`).  Can be called while the graph is running and is not recorded in the edit history
+        pub fn set_node_gate(&mut self, node_id: usize, open: bool) -> Result<(), Error> {
+            if !self.check_node_exists(&node_id) {
+                return Err(Error {
+                    code: ErrorCodes::NodeIDNonExistent,
+                    message: format!("Node ID {} does not exist in graph", node_id)
+                });
+            }
+
+            self.nodes[node_id].set_gate(open);
+
+            Ok(())
+        }
+
+        /// Get every connection in the graph.
+        /// Connections are listed by receiving node and, for each receiving node, in the order they were made (which is also the order in which inputs are summed)
+        pub fn get_connections(&self) -> Vec<Connection> {
+            let mut connections = vec![];
+
+            for (node_in_id, map_node) in self.graph_map.nodes.iter().enumerate() {
+                for child in &map_node.children {
+                    connections.push(Connection {
+                        node_out_id: *child,
+                        node_in_id,
+                        node_in_input_port: self.graph_map.nodes[*child].input_port
+                    });
+                }
+            }
+
+            connections
+        }
+
+        /// Ensures that connecting nodes are valid and do not already have connections between them
+        fn validate_node_inputs(&self, node_out_id: usize, node_in_id: usize, node_in_input_port: usize) -> Result<(), Error> {
+            //  Make sure node actually exists in graph
+            if self.check_node_exists(&node_in_id) == false || self.check_node_exists(&node_out_id) == false {
+                let missing_id = if self.check_node_exists(&node_in_id) { node_out_id } else { node_in_id };
+                return Err(Error{
+                    code: ErrorCodes::NodeIDNonExistent,
+                    message: format!("Node ID {} does not exist in graph", missing_id)
+                });
+            }
+
+            //  The output node is the sink of the graph and cannot feed another node
+            if node_out_id == 0 {
+                return Err(Error {
+                    code: ErrorCodes::CannotConnectOutputNode,
+                    message: format!("Cannot connect the output node to node {}", self.describe_node(node_in_id))
+                });
+            }
+
+            //  Ensure that a valid input port is passed in
+            if node_in_input_port >= self.nodes[node_in_id].get_number_of_inputs() {
+                return Err(Error {
+                    code: ErrorCodes::NodeInputPortInvalid,
+                    message: format!("Node {} has no input port {}", self.describe_node(node_in_id), node_in_input_port)
+                });
+            }
+
+            //  Ensure that unconnected inputs are actually available
+            if self.nodes[node_in_id].get_next_available_input() == None {
+                return Err(Error {
+                    code: ErrorCodes::NodeNoMoreInputs,
+                    message: format!("Node {} has no more available inputs", self.describe_node(node_in_id))
+                });
+            }
+
+            //  Ensure that a node is not being connected to itself
+            if node_out_id == node_in_id {
+                return Err(Error {
+                    code: ErrorCodes::NodeConnectingToItself,
+                    message: format!("Cannot connect node {} to itself", self.describe_node(node_in_id))
+                });
+            }
+
+            //  Check to make sure that the connection doesn't already exist
+            for child in &self.graph_map.nodes[node_out_id].children {
+                if *child == node_in_id {
+                    return Err( Error {
+                        code: ErrorCodes::ConnectionAlreadyExists,
+                        message: format!("The connection between nodes {} and {} already exists", self.describe_node(node_out_id), self.describe_node(node_in_id))
+                    });
+                }
+            }
+
+            //  Ensure that the port is not taken by another node
+            if let Some(error) = self.check_port_in_use(node_in_id, node_in_input_port) {
+                return Err(error);
+            }
+
+            //  Ensure that node_out is not already feeding node_in further down the line
+            if self.is_descendant(node_in_id, node_out_id) {
+                return Err(Error {
+                    code: ErrorCodes::ConnectionCreatesCycle,
+                    message: format!("Connecting node {} to node {} would create a cycle", self.describe_node(node_out_id), self.describe_node(node_in_id))
+                });
+            }
+
+            self.check_channel_counts(node_out_id, node_in_id)
+        }
+
+        fn check_port_in_use(&self, node_in_id: usize, node_in_input_port: usize) -> Option<Error> {
+            let child = self.graph_map.nodes[node_in_id].children.iter().find(|child| self.graph_map.nodes[**child].input_port == node_in_input_port)?;
+
+            Some(Error {
+                code: ErrorCodes::NodeInputPortInUse,
+                message: format!("Input port {} of node {} is already used by node {}", node_in_input_port, self.describe_node(node_in_id), self.describe_node(*child))
+            })
+        }
+
+        fn check_channel_counts(&self, node_out_id: usize, node_in_id: usize) -> Result<(), Error> {
+            let output_channels = self.nodes[node_out_id].get_number_of_output_channels();
+            let input_channels = self.nodes[node_in_id].get_number_of_input_channels();
+
+            if output_channels != input_channels {
+                return Err(Error {
+                    code: ErrorCodes::ChannelCountMismatch,
+                    message: format!("Node {} produces {} channel(s) but node {} expects {}", self.describe_node(node_out_id), output_channels, self.describe_node(node_in_id), input_channels)
+                });
+            }
+
+            Ok(())
+        }
+
+        /// Check whether `node_id` is fed by `ancestor_id`, directly or through other nodes.  Safe to call on graphs that contain cycles
+        fn is_descendant(&self, node_id: usize, ancestor_id: usize) -> bool {
+            let mut current = node_id;
+
+            for _ in 0..self.nodes.len() {
+                match self.graph_map.nodes[current].parent {
+                    Some(parent) if parent == ancestor_id => return true,
+                    Some(parent) => { current = parent; },
+                    None => return false
+                }
+            }
+
+            false
+        }
+
+        /// Check every connection of the graph: ports must exist and be used by one node at most, channel counts must match and there must be no cycles.
+        /// Returns one error per problem found
+        fn find_topology_problems(&self) -> Vec<Error> {
+            let mut problems = vec![];
+
+            for node_in_id in self.get_node_ids() {
+                let children = &self.graph_map.nodes[node_in_id].children;
+                let num_inputs = self.nodes[node_in_id].get_number_of_inputs();
+
+                if children.len() > num_inputs {
+                    problems.push(Error {
+                        code: ErrorCodes::NodeNoMoreInputs,
+                        message: format!("Node {} has {} connections but only {} input(s)", self.describe_node(node_in_id), children.len(), num_inputs)
+                    });
+                }
+
+                for (index, child) in children.iter().enumerate() {
+                    let port = self.graph_map.nodes[*child].input_port;
+
+                    if port >= num_inputs {
+                        problems.push(Error {
+                            code: ErrorCodes::NodeInputPortInvalid,
+                            message: format!("Node {} is connected to input port {} of node {}, which does not exist", self.describe_node(*child), port, self.describe_node(node_in_id))
+                        });
+                    }
+
+                    if let Some(other) = children[..index].iter().find(|other| self.graph_map.nodes[**other].input_port == port) {
+                        problems.push(Error {
+                            code: ErrorCodes::NodeInputPortInUse,
+                            message: format!("Nodes {} and {} are both connected to input port {} of node {}", self.describe_node(*other), self.describe_node(*child), port, self.describe_node(node_in_id))
+                        });
+                    }
+
+                    if let Err(error) = self.check_channel_counts(*child, node_in_id) {
+                        problems.push(error);
+                    }
+                }
+            }
+
+            //  Every node has one parent at most, so a cycle is a chain of parents leading back to where it started.  Each cycle is reported once, at its lowest ID
+            for node_id in self.get_node_ids() {
+                if !self.is_descendant(node_id, node_id) {
+                    continue;
+                }
+
+                let mut cycle = vec![node_id];
+                let mut current = self.graph_map.nodes[node_id].parent;
+                while let Some(parent) = current {
+                    if parent == node_id {
+                        break;
+                    }
+                    cycle.push(parent);
+                    current = self.graph_map.nodes[parent].parent;
+                }
+
+                if cycle.iter().all(|member| *member >= node_id) {
+                    let members: Vec<String> = cycle.iter().map(|member| self.describe_node(*member)).collect();
+                    problems.push(Error {
+                        code: ErrorCodes::ConnectionCreatesCycle,
+                        message: format!("Nodes {} form a cycle", members.join(" -> "))
+                    });
+                }
+            }
+
+            problems
+        }
+
+        fn check_node_exists(&self, node_id: &usize) -> bool {
+            if *node_id >= self.nodes.len() || self.graph_map.nodes[*node_id].vacant {
+                return false;
+            }
+
+            true
+        }
+
+        /// Set the number of channels the graph outputs (1 by default).  The node connected to the output has to produce this many channels
+        pub fn set_number_of_output_channels(&mut self, num_channels: usize) -> Result<(), Error> {
+            if self.graph_running == true {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                });
+            }
+
+            if num_channels == 0 {
+                return Err(Error {
+                    code: ErrorCodes::InvalidChannelCount,
+                    message: String::from("The graph needs at least one output channel")
+                });
+            }
+
+            self.nodes[0] = Box::new(OutputNode::new(num_channels));
+            self.resync_input_counters(0);
+
+            Ok(())
+        }
+
+        /// Get the number of channels the graph outputs
+        pub fn get_number_of_output_channels(&self) -> usize {
+            self.nodes[0].get_number_of_output_channels()
+        }
+
+        /// Get the parameters the graph was prepared with.  Returns None before `prepare()` has been called
+        pub fn get_audio_runtime_parameters(&self) -> Option<&AudioRuntimeParameters> {
+            if !self.graph_running {
+                return None;
+            }
+
+            Some(&self.audio_runtime_params)
+        }
+
+        /// Prepare the audio graph with a specified set of audio runtime parameters (sampling freq, buffer size etc).  
+        /// This function will call the initialization functions for all of the nodes.  
+        /// This function only needs to be called once.
+        pub fn prepare(&mut self, audio_parameters: AudioRuntimeParameters) -> Result<(), Error> {
+            if self.graph_running == true {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                });
+            }
+
+            //  Check that audio_parameters have valid inputs
+            if audio_parameters.buffer_size == 0 {
+                return Err(Error {
+                    code: ErrorCodes::InvalidBufferSize,
+                    message: String::from("Invalid buffer size")
+                });
+            }
+
+            if audio_parameters.sampling_freq <= 0.0 {
+                return Err( Error {
+                    code: ErrorCodes::InvalidSamplingFrequency,
+                    message: String::from("Invalid sampling frequency entered")
+                });
+            }
+
+            if self.validate_on_prepare {
+                let report = self.validate();
+                if !report.is_valid() {
+                    let errors: Vec<&str> = report.get_errors().iter().map(|issue| issue.message.as_str()).collect();
+                    return Err(Error {
+                        code: ErrorCodes::GraphInvalid,
+                        message: format!("Graph is invalid: {}", errors.join("; "))
+                    });
+                }
+            }
+
+            self.audio_runtime_params = audio_parameters;
+
+            for node in &mut self.nodes {
+                node.init(&self.audio_runtime_params);
+            }
+
+            self.compile();
+            self.graph_running = true;
+
+            Ok(())
+        }
+
+        /// Run the audio graph and get a buffer of samples.  
+        /// The audio graph performs a depth-first traversal when obtaining samples from nodes.  The input of each node is the sum of the outputs of its children.
+        /// With more than one output channel the samples are planar, one `buffer_size` block per channel, so `buffer` must hold exactly `buffer_size * channels` samples or an `InvalidBufferSize` error is returned
+        pub fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> Result<&'a mut [f32], Error> {
+
+            //  Ensure that prepare() has been called once before calling process_block().
+            if self.graph_running == false {
+                return Err( Error {
+                    code: ErrorCodes::AudioGraphNotPrepared,
+                    message: String::from("Must call prepare() before attempting to get samples from the audio graph")
+                })
+            }
+
+            let expected_length = self.audio_runtime_params.buffer_size * self.get_number_of_output_channels();
+            if buffer.len() != expected_length {
+                return Err(Error {
+                    code: ErrorCodes::InvalidBufferSize,
+                    message: format!("The buffer holds {} samples but the graph outputs {} ({} channel(s) of {} samples)",
+                                     buffer.len(), expected_length, self.get_number_of_output_channels(), self.audio_runtime_params.buffer_size)
+                });
+            }
+
+            let block_start = Instant::now();
+
+            self.compiled.job.context = BlockContext {
+                nodes: self.nodes.as_mut_ptr(),
+                map_nodes: self.graph_map.nodes.as_ptr(),
+                buffers: self.compiled.buffers.as_mut_ptr(),
+                output_lengths: self.compiled.output_lengths.as_ptr(),
+                buffer_size: self.audio_runtime_params.buffer_size,
+                compensation_delays: self.compiled.compensation_delays.as_mut_ptr(),
+                faders: self.compiled.faders.as_mut_ptr(),
+                node_timings: match &mut self.profiler {
+                    Some(profiler) => profiler.node_timings.as_mut_ptr(),
+                    None => std::ptr::null_mut()
+                }
+            };
+
+            match &self.worker_pool {
+                Some(pool) if self.compiled.order.len() >= self.parallel_node_threshold => {
+                    let job = &self.compiled.job;
+                    job.ready_head.store(0, Ordering::Relaxed);
+                    job.ready_tail.store(0, Ordering::Relaxed);
+                    job.completed.store(0, Ordering::Relaxed);
+
+                    for slot in &job.ready_queue {
+                        slot.store(0, Ordering::Relaxed);
+                    }
+
+                    for node_id in &self.compiled.order {
+                        let num_children = self.graph_map.nodes[*node_id].children.len();
+                        job.pending_children[*node_id].store(num_children, Ordering::Relaxed);
+
+                        if num_children == 0 {
+                            job.push_ready(*node_id);
+                        }
+                    }
+
+                    //  SAFETY: The graph is borrowed mutably for the whole call and the pool returns only after all workers are done with the job
+                    unsafe { pool.run(job); }
+                },
+
+                _ => {
+                    for node_id in &self.compiled.order {
+                        //  SAFETY: Nodes are processed one at a time and the schedule always places children before their parents
+                        unsafe { self.compiled.job.context.process_node(*node_id); }
+                    }
+                }
+            }
+
+            self.compiled.job.context = BlockContext::empty();
+
+            buffer.copy_from_slice(&self.compiled.buffers[0][..expected_length]);
+
+            if let Some(profiler) = &mut self.profiler {
+                profiler.graph_timings.record(block_start.elapsed());
+            }
+
+            Ok(buffer)
+        }
+
+
+        //  Validation
+        //  ==============================================================================================================  //
+        /// Check the graph for problems before running it.  Errors are things that keep the graph from producing sensible output:
+        /// nothing connected to the output, processed nodes whose required inputs are not connected, invalid ports, channel count mismatches and cycles.
+        /// Warnings are nodes that will never be processed, either on their own or as part of a sub-tree that does not lead to the output
+        pub fn validate(&self) -> ValidationReport {
+            let mut issues = vec![];
+            let reachable = self.get_reachable_nodes();
+
+            if self.graph_map.nodes[0].children.is_empty() {
+                issues.push(ValidationIssue {
+                    severity: ValidationSeverity::Error,
+                    code: ErrorCodes::OutputUnconnected,
+                    node_id: Some(0),
+                    message: String::from("Nothing is connected to the output node")
+                });
+            }
+
+            for problem in self.find_topology_problems() {
+                issues.push(ValidationIssue {
+                    severity: ValidationSeverity::Error,
+                    code: problem.code,
+                    node_id: None,
+                    message: problem.message
+                });
+            }
+
+            for node_id in self.get_node_ids().into_iter().skip(1) {
+                let map_node = &self.graph_map.nodes[node_id];
+                let node = &self.nodes[node_id];
+
+                if reachable[node_id] {
+                    for port in 0..node.get_number_of_inputs() {
+                        let is_connected = map_node.children.iter().any(|child| self.graph_map.nodes[*child].input_port == port);
+
+                        if node.is_input_required(port) && !is_connected {
+                            issues.push(ValidationIssue {
+                                severity: ValidationSeverity::Error,
+                                code: ErrorCodes::NodeInputUnfilled,
+                                node_id: Some(node_id),
+                                message: format!("Required input port {} of node {} is not connected", port, self.describe_node(node_id))
+                            });
+                        }
+                    }
+                }
+                else if map_node.parent.is_none() && map_node.children.is_empty() {
+                    issues.push(ValidationIssue {
+                        severity: ValidationSeverity::Warning,
+                        code: ErrorCodes::NodeUnconnected,
+                        node_id: Some(node_id),
+                        message: format!("Node {} is not connected to anything and will never be processed", self.describe_node(node_id))
+                    });
+                }
+                else if map_node.parent.is_none() {
+                    //  Report sub-trees once, at their root.  Cycles have no root and are already reported as errors
+                    let mut members = vec![];
+                    let mut stack = vec![node_id];
+                    while let Some(member) = stack.pop() {
+                        members.push(self.describe_node(member));
+                        stack.extend(self.graph_map.nodes[member].children.iter().rev());
+                    }
+
+                    issues.push(ValidationIssue {
+                        severity: ValidationSeverity::Warning,
+                        code: ErrorCodes::NodeUnreachable,
+                        node_id: Some(node_id),
+                        message: format!("Nodes {} are not connected to the output and will never be processed", members.join(", "))
+                    });
+                }
+            }
+
+            ValidationReport {
+                issues
+            }
+        }
+
+        /// Make `prepare()` run `validate()` first and refuse to prepare a graph that has errors.  Off by default
+        pub fn set_validate_on_prepare(&mut self, validate_on_prepare: bool) {
+            self.validate_on_prepare = validate_on_prepare;
+        }
+
+
+        //  Batch edits
+        //  ==============================================================================================================  //
+        /// Make a batch of edits that succeeds or fails as a whole.  `edits` receives a `GraphEditor` through which nodes can be added, removed and rewired.
+        /// Unlike the regular functions, the editor does not check ports, cycles or channel counts after every step, so a rewire can pass through
+        /// intermediate states that would otherwise be rejected.  Once `edits` returns, the resulting topology is checked as a whole.
+        /// If any problem was found the graph is restored to exactly how it was and every problem is returned, otherwise the edits are kept
+        /// (and recorded in the edit history as a single entry).
+        ///
+        /// ```
+        /// use audio_graph::AudioToolbox::AudioGraph;
+        /// use audio_graph::ModelNodes::*;
+        ///
+        /// let mut graph = AudioGraph::new();
+        /// let gen_id = graph.add_new_node(Box::new(TestGenNode::new())).unwrap();
+        /// graph.connect_node_to_output(gen_id).unwrap();
+        ///
+        /// //  Insert an effect between the generator and the output
+        /// let result = graph.edit(|editor| {
+        ///     let fx_id = editor.add_new_node(Box::new(TestFXNode::new())).unwrap();
+        ///     editor.disconnect_node_from_output(gen_id);
+        ///     editor.connect_node(gen_id, fx_id, 0);
+        ///     editor.connect_node_to_output(fx_id);
+        /// });
+        ///
+        /// assert!(result.is_ok());
+        /// assert_eq!(graph.get_connections().len(), 2);
+        /// ```
+        pub fn edit<F>(&mut self, edits: F) -> Result<(), Vec<Error>> where F: FnOnce(&mut GraphEditor) {
+            if self.graph_running == true {
+                return Err(vec![Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Audio Graph is running!")
+                }]);
+            }
+
+            //  The batch runs as a transaction so that it ends up as one entry in the history.  It may be nested in a transaction of the caller
+            self.begin_transaction();
+            let first_edit = self.transaction_edits.len();
+
+            let mut editor = GraphEditor {
+                graph: self,
+                problems: vec![]
+            };
+            edits(&mut editor);
+
+            let mut problems = editor.problems;
+            problems.extend(self.find_topology_problems());
+
+            if !problems.is_empty() {
+                let mut applied_edits = self.transaction_edits.split_off(first_edit);
+                for edit in applied_edits.iter_mut().rev() {
+                    self.apply_edit(edit, true);
+                }
+            }
+
+            //  The editor skips the checks of the regular functions, so the input counters of the nodes may have drifted
+            for node_id in self.get_node_ids() {
+                self.resync_input_counters(node_id);
+            }
+
+            let _ = self.commit_transaction();
+
+            if problems.is_empty() {
+                Ok(())
+            }
+            else {
+                Err(problems)
+            }
+        }
+
+        /// Bring the node's own count of used inputs in line with its connections
+        fn resync_input_counters(&mut self, node_id: usize) {
+            let node = &mut self.nodes[node_id];
+            for _ in 0..node.get_number_of_inputs() {
+                node.disconnect_input();
+            }
+
+            for _ in 0..self.graph_map.nodes[node_id].children.len() {
+                node.connect_input();
+            }
+        }
+
+
+        //  Names
+        //  ==============================================================================================================  //
+        /// Give a node a human-readable name so it can be found with `AudioGraph::find_node_by_name()` instead of by ID.
+        /// Names are shown in error messages, in the exported topology and in the profiling report.
+        /// Names must be non-empty and unique within the graph.  The output node is always called "output" and cannot be renamed
+        pub fn set_node_name(&mut self, node_id: usize, name: &str) -> Result<(), Error> {
+            if self.graph_map.nodes.get(node_id).and_then(|map_node| map_node.name.as_deref()) == Some(name) {
+                return Ok(());
+            }
+
+            self.validate_mixing_target(node_id)?;
+            self.validate_node_name(name)?;
+
+            self.graph_map.nodes[node_id].name = Some(String::from(name));
+            Ok(())
+        }
+
+        /// Remove the name of a node.  The output node keeps its name
+        pub fn clear_node_name(&mut self, node_id: usize) -> Result<(), Error> {
+            self.validate_mixing_target(node_id)?;
+
+            self.graph_map.nodes[node_id].name = None;
+            Ok(())
+        }
+
+        /// Get the name of a node, if it has one
+        pub fn get_node_name(&self, node_id: usize) -> Option<&str> {
+            self.graph_map.nodes.get(node_id)?.name.as_deref()
+        }
+
+        /// Get the ID of the node with the given name
+        pub fn find_node_by_name(&self, name: &str) -> Option<usize> {
+            self.graph_map.nodes.iter().position(|map_node| map_node.name.as_deref() == Some(name))
+        }
+
+        fn validate_node_name(&self, name: &str) -> Result<(), Error> {
+            if name.trim().is_empty() {
+                return Err(Error {
+                    code: ErrorCodes::NodeNameInvalid,
+                    message: String::from("Node names cannot be empty")
                 });
             }
 
-            //  Ensure that unconnected inputs are actually available
-            if self.nodes[node_in_id].get_next_available_input() == None {
+            if let Some(node_id) = self.find_node_by_name(name) {
                 return Err(Error {
-                    code: ErrorCodes::NodeNoMoreInputs,
-                    message: String::from("Input node has no more available inputs")
+                    code: ErrorCodes::NodeNameAlreadyExists,
+                    message: format!("Name \"{}\" is already used by node {}", name, node_id)
                 });
             }
 
-            //  Ensure that a node is not being connected to itself
-            if node_out_id == node_in_id {
+            Ok(())
+        }
+
+        /// Describe a node for error messages and reports, e.g. `3 ("lowpass")` or just `3` for unnamed nodes
+        fn describe_node(&self, node_id: usize) -> String {
+            match self.get_node_name(node_id) {
+                Some(name) => format!("{} (\"{}\")", node_id, name),
+                None => format!("{}", node_id)
+            }
+        }
+
+
+        //  Edit history
+        //  ==============================================================================================================  //
+        /// Start recording edits so that they can be undone and redone.  Adding and removing nodes, connecting and disconnecting nodes and
+        /// changing parameters through `set_node_parameters()` are recorded.  At most `max_length` edits (or transactions) are kept, older ones are forgotten.
+        /// Calling this function again discards the recorded history
+        pub fn enable_history(&mut self, max_length: usize) {
+            self.history = Some(EditHistory::new(max_length));
+        }
+
+        /// Stop recording edits and discard the recorded history
+        pub fn disable_history(&mut self) {
+            self.history = None;
+        }
+
+        /// Forget every recorded edit but keep recording new ones
+        pub fn clear_history(&mut self) {
+            if let Some(history) = &mut self.history {
+                history.undo_stack.clear();
+                history.redo_stack.clear();
+            }
+        }
+
+        /// Check whether there is an edit that can be undone
+        pub fn can_undo(&self) -> bool {
+            self.history.as_ref().is_some_and(|history| !history.undo_stack.is_empty())
+        }
+
+        /// Check whether there is an undone edit that can be redone
+        pub fn can_redo(&self) -> bool {
+            self.history.as_ref().is_some_and(|history| !history.redo_stack.is_empty())
+        }
+
+        /// Undo the most recent edit or transaction.  Returns false if there is nothing to undo.
+        /// Edits that change the topology cannot be undone once the graph is running
+        pub fn undo(&mut self) -> Result<bool, Error> {
+            self.validate_history_step()?;
+
+            let mut edits = match self.history.as_mut().and_then(|history| history.undo_stack.pop_back()) {
+                Some(edits) => edits,
+                None => return Ok(false)
+            };
+
+            if self.graph_running && edits.iter().any(GraphEdit::changes_topology) {
+                if let Some(history) = &mut self.history {
+                    history.undo_stack.push_back(edits);
+                }
+
                 return Err(Error {
-                    code: ErrorCodes::NodeConnectingToItself,
-                    message: String::from("Cannot connect a node to itself")
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Cannot undo changes to the graph topology while the graph is running")
                 });
             }
 
-            //  Check to make sure that the connection doesn't already exist
-            for child in &self.graph_map.nodes[node_out_id].children {
-                if *child == node_in_id {
-                    return Err( Error {
-                        code: ErrorCodes::ConnectionAlreadyExists,
-                        message: String::from("The node connection already exists")
-                    });
+            for edit in edits.iter_mut().rev() {
+                self.apply_edit(edit, true);
+            }
+
+            if let Some(history) = &mut self.history {
+                history.redo_stack.push(edits);
+            }
+
+            Ok(true)
+        }
+
+        /// Redo the most recently undone edit or transaction.  Returns false if there is nothing to redo.
+        /// Any new edit clears the edits that could be redone
+        pub fn redo(&mut self) -> Result<bool, Error> {
+            self.validate_history_step()?;
+
+            let mut edits = match self.history.as_mut().and_then(|history| history.redo_stack.pop()) {
+                Some(edits) => edits,
+                None => return Ok(false)
+            };
+
+            if self.graph_running && edits.iter().any(GraphEdit::changes_topology) {
+                if let Some(history) = &mut self.history {
+                    history.redo_stack.push(edits);
+                }
+
+                return Err(Error {
+                    code: ErrorCodes::AudioGraphRunning,
+                    message: String::from("Cannot redo changes to the graph topology while the graph is running")
+                });
+            }
+
+            for edit in edits.iter_mut() {
+                self.apply_edit(edit, false);
+            }
+
+            if let Some(history) = &mut self.history {
+                history.undo_stack.push_back(edits);
+            }
+
+            Ok(true)
+        }
+
+        /// Group the following edits into a single entry of the history, so that they are undone and redone together.
+        /// Transactions can be nested, in which case the edits are grouped when the outermost transaction is committed
+        pub fn begin_transaction(&mut self) {
+            self.transaction_depth += 1;
+        }
+
+        /// Close the transaction opened by the matching call to `begin_transaction()`
+        pub fn commit_transaction(&mut self) -> Result<(), Error> {
+            if self.transaction_depth == 0 {
+                return Err(Error {
+                    code: ErrorCodes::TransactionNotOpen,
+                    message: String::from("There is no open transaction to commit")
+                });
+            }
+
+            self.transaction_depth -= 1;
+            if self.transaction_depth == 0 {
+                let edits = std::mem::take(&mut self.transaction_edits);
+                if let (Some(history), false) = (&mut self.history, edits.is_empty()) {
+                    history.push(edits);
+                }
+            }
+
+            Ok(())
+        }
+
+        fn validate_history_step(&self) -> Result<(), Error> {
+            if self.transaction_depth > 0 {
+                return Err(Error {
+                    code: ErrorCodes::TransactionOpen,
+                    message: String::from("Cannot undo or redo while a transaction is open")
+                });
+            }
+
+            Ok(())
+        }
+
+        fn record_edit(&mut self, edit: GraphEdit) {
+            if self.transaction_depth > 0 {
+                self.transaction_edits.push(edit);
+            }
+            else if let Some(history) = &mut self.history {
+                history.push(vec![edit]);
+            }
+        }
+
+        /// Undo or redo a single edit.  Edits are only ever applied in the reverse order they were recorded in (or the same order for redo),
+        /// so the graph is always in the state the edit expects
+        fn apply_edit(&mut self, edit: &mut GraphEdit, undo: bool) {
+            let is_connect = matches!(edit, GraphEdit::Connect { .. });
+
+            match edit {
+                GraphEdit::AddNode { node_id, removed } | GraphEdit::RemoveNode { node_id, removed } => {
+                    //  Adding and removing are each other's inverse: take the node out if it is in the graph, put it back otherwise
+                    match removed.take() {
+                        Some(node) => self.restore_node(*node_id, node),
+                        None => *removed = Some(self.take_node(*node_id))
+                    }
+                },
+
+                GraphEdit::Connect { connection, index } | GraphEdit::Disconnect { connection, index } => {
+                    if is_connect != undo {
+                        self.attach_connection(*connection, *index);
+                    }
+                    else {
+                        self.detach_connection(connection.node_out_id);
+                    }
+                },
+
+                GraphEdit::SetParameters { node_id, old_parameters, new_parameters } => {
+                    let parameters = if undo { old_parameters } else { new_parameters };
+                    self.nodes[*node_id].change_parameters(parameters);
+                }
+            }
+        }
+
+        /// Take a node and its connections out of the graph, leaving a vacant slot behind
+        fn take_node(&mut self, node_id: usize) -> RemovedNode {
+            //  Children are stored with their original positions so that re-attaching them in order restores the summing order
+            let mut children = vec![];
+            for (index, child) in self.graph_map.nodes[node_id].children.clone().into_iter().enumerate() {
+                if let Some((connection, _)) = self.detach_connection(child) {
+                    children.push((connection, index));
+                }
+            }
+
+            let parent = self.detach_connection(node_id);
+            let map_node = std::mem::replace(&mut self.graph_map.nodes[node_id], MapNode { vacant: true, ..MapNode::new() });
+            let node = std::mem::replace(&mut self.nodes[node_id], Box::new(VacantNode::new()));
+
+            if let Some(profiler) = &mut self.profiler {
+                profiler.node_timings[node_id] = TimingWindow::new(profiler.window_length);
+            }
+
+            //  Trailing vacant slots are dropped so that the next node added gets the same ID again
+            while self.nodes.len() > 1 && self.graph_map.nodes[self.nodes.len() - 1].vacant {
+                self.nodes.pop();
+                self.graph_map.nodes.pop();
+            }
+            self.sync_profiler_with_nodes();
+            self.update_solo_state();
+
+            RemovedNode {
+                node,
+                name: map_node.name,
+                bypassed: map_node.bypassed,
+                muted: map_node.muted,
+                soloed: map_node.soloed,
+                parent,
+                children
+            }
+        }
+
+        /// Put a node taken out by `take_node()` back into its slot and restore its connections
+        fn restore_node(&mut self, node_id: usize, removed: RemovedNode) {
+            while self.nodes.len() <= node_id {
+                self.nodes.push(Box::new(VacantNode::new()));
+                self.graph_map.nodes.push(MapNode { vacant: true, ..MapNode::new() });
+            }
+
+            //  Names are not part of the history, so the name may have been given to another node in the meantime
+            let name = removed.name.filter(|name| self.find_node_by_name(name).is_none());
+
+            self.nodes[node_id] = removed.node;
+            self.graph_map.nodes[node_id] = MapNode {
+                name,
+                bypassed: removed.bypassed,
+                muted: removed.muted,
+                soloed: removed.soloed,
+                ..MapNode::new()
+            };
+            self.sync_profiler_with_nodes();
+
+            for (connection, index) in removed.children {
+                self.attach_connection(connection, index);
+            }
+
+            if let Some((connection, index)) = removed.parent {
+                self.attach_connection(connection, index);
+            }
+
+            self.update_solo_state();
+        }
+
+        /// Make a connection without any validation, inserting the node at `index` in the receiving node's children (or at the end).
+        /// Returns the index the node was inserted at
+        fn attach_connection(&mut self, connection: Connection, index: usize) -> usize {
+            let children = &mut self.graph_map.nodes[connection.node_in_id].children;
+            let index = index.min(children.len());
+            children.insert(index, connection.node_out_id);
+
+            self.graph_map.nodes[connection.node_out_id].parent = Some(connection.node_in_id);
+            self.graph_map.nodes[connection.node_out_id].input_port = connection.node_in_input_port;
+            self.nodes[connection.node_in_id].connect_input();
+
+            index
+        }
+
+        /// Remove the connection from a node to its parent.  Returns the removed connection and the node's position in the parent's children
+        fn detach_connection(&mut self, node_out_id: usize) -> Option<(Connection, usize)> {
+            let node_in_id = self.graph_map.nodes[node_out_id].parent?;
+            let index = self.graph_map.nodes[node_in_id].children.iter().position(|child| *child == node_out_id)?;
+
+            self.graph_map.nodes[node_in_id].children.remove(index);
+            self.graph_map.nodes[node_out_id].parent = None;
+            self.nodes[node_in_id].disconnect_input();
+
+            let connection = Connection {
+                node_out_id,
+                node_in_id,
+                node_in_input_port: self.graph_map.nodes[node_out_id].input_port
+            };
+
+            Some((connection, index))
+        }
+
+        fn sync_profiler_with_nodes(&mut self) {
+            let num_nodes = self.nodes.len();
+            if let Some(profiler) = &mut self.profiler {
+                let window_length = profiler.window_length;
+                profiler.node_timings.resize_with(num_nodes, || TimingWindow::new(window_length));
+            }
+        }
+
+
+        //  Latency
+        //  ==============================================================================================================  //
+        /// Get the total latency of the graph in samples, i.e. the latency of the slowest path from any node to the output.
+        /// Node latencies may depend on the runtime parameters, so this is only accurate once the graph has been prepared
+        pub fn get_total_latency(&self) -> usize {
+            let order = self.get_processing_order();
+            self.compute_latencies(&order).path_latency[0]
+        }
+
+        /// Get the delay in samples that the graph adds to the output of a node to line it up with the other branches it is mixed with.
+        /// Returns None if the node does not exist
+        pub fn get_compensation_delay(&self, node_id: usize) -> Option<usize> {
+            if !self.check_node_exists(&node_id) {
+                return None;
+            }
+
+            let order = self.get_processing_order();
+            Some(self.compute_latencies(&order).compensation[node_id])
+        }
+
+
+        //  Bypass, mute and solo
+        //  ==============================================================================================================  //
+        /// Bypass a node so that its input is passed through unchanged (delayed by the node's latency so that compensation still holds).
+        /// Can be called while the graph is running, in which case the node is crossfaded in or out
+        pub fn set_node_bypass(&mut self, node_id: usize, bypassed: bool) -> Result<(), Error> {
+            self.validate_mixing_target(node_id)?;
+            self.graph_map.nodes[node_id].bypassed = bypassed;
+
+            Ok(())
+        }
+
+        /// Mute the output of a node.
+        /// Can be called while the graph is running, in which case the output is faded in or out
+        pub fn set_node_mute(&mut self, node_id: usize, muted: bool) -> Result<(), Error> {
+            self.validate_mixing_target(node_id)?;
+            self.graph_map.nodes[node_id].muted = muted;
+
+            Ok(())
+        }
+
+        /// Solo the branch a node belongs to.
+        /// While any node is soloed, every branch that is mixed with a soloed branch but does not contain a soloed node itself is silenced
+        pub fn set_node_solo(&mut self, node_id: usize, soloed: bool) -> Result<(), Error> {
+            self.validate_mixing_target(node_id)?;
+            self.graph_map.nodes[node_id].soloed = soloed;
+            self.update_solo_state();
+
+            Ok(())
+        }
+
+        /// Check whether a node is bypassed.  Returns false if the node does not exist
+        pub fn is_node_bypassed(&self, node_id: usize) -> bool {
+            self.check_node_exists(&node_id) && self.graph_map.nodes[node_id].bypassed
+        }
+
+        /// Check whether a node is muted.  Returns false if the node does not exist
+        pub fn is_node_muted(&self, node_id: usize) -> bool {
+            self.check_node_exists(&node_id) && self.graph_map.nodes[node_id].muted
+        }
+
+        /// Check whether a node is soloed.  Returns false if the node does not exist
+        pub fn is_node_soloed(&self, node_id: usize) -> bool {
+            self.check_node_exists(&node_id) && self.graph_map.nodes[node_id].soloed
+        }
+
+        /// The output node cannot be bypassed, muted or soloed
+        fn validate_mixing_target(&self, node_id: usize) -> Result<(), Error> {
+            if node_id == 0 || !self.check_node_exists(&node_id) {
+                return Err(Error {
+                    code: ErrorCodes::NodeIDNonExistent,
+                    message: format!("Node ID {} does not exist in graph or is the output node", node_id)
+                });
+            }
+
+            Ok(())
+        }
+
+        /// Work out which nodes are silenced by the current solo settings.
+        /// A node is silenced when its parent leads to a soloed node but neither the node itself nor any of its ancestors or descendants is soloed
+        fn update_solo_state(&mut self) {
+            let num_nodes = self.graph_map.nodes.len();
+            let any_soloed = self.graph_map.nodes.iter().any(|map_node| map_node.soloed);
+
+            //  contains_solo: the node or one of its descendants is soloed.  within_solo: the node or one of its ancestors is soloed
+            let mut contains_solo = vec![false; num_nodes];
+            let mut within_solo = vec![false; num_nodes];
+
+            for node_id in 0..num_nodes {
+                if !self.graph_map.nodes[node_id].soloed {
+                    continue;
+                }
+
+                let mut ancestor = Some(node_id);
+                while let Some(id) = ancestor {
+                    if contains_solo[id] {
+                        break;
+                    }
+                    contains_solo[id] = true;
+                    ancestor = self.graph_map.nodes[id].parent;
+                }
+            }
+
+            for (node_id, within_solo) in within_solo.iter_mut().enumerate() {
+                let mut ancestor = Some(node_id);
+                for _ in 0..num_nodes {
+                    match ancestor {
+                        Some(id) if self.graph_map.nodes[id].soloed => {
+                            *within_solo = true;
+                            break;
+                        },
+                        Some(id) => { ancestor = self.graph_map.nodes[id].parent; },
+                        None => { break; }
+                    }
+                }
+            }
+
+            for node_id in 0..num_nodes {
+                let parent_leads_to_solo = match self.graph_map.nodes[node_id].parent {
+                    Some(parent) => contains_solo[parent],
+                    None => false
+                };
+
+                self.graph_map.nodes[node_id].silenced_by_solo = any_soloed && parent_leads_to_solo && !contains_solo[node_id] && !within_solo[node_id];
+            }
+        }
+
+
+        //  Parallel processing
+        //  ==============================================================================================================  //
+        /// Process independent branches of the graph concurrently on a fixed pool of `num_threads` worker threads.
+        /// The thread calling `process_block()` takes part in the processing as well.  The output is bit-identical to processing the graph on a single thread.
+        /// Passing 0 threads is the same as calling `disable_parallel_processing()`
+        pub fn enable_parallel_processing(&mut self, num_threads: usize) {
+            self.worker_pool = match num_threads {
+                0 => None,
+                _ => Some(WorkerPool::new(num_threads))
+            };
+        }
+
+        /// Stop the worker threads and go back to processing the graph on the calling thread only
+        pub fn disable_parallel_processing(&mut self) {
+            self.worker_pool = None;
+        }
+
+        /// Set the minimum number of nodes a graph must have for it to be processed in parallel.
+        /// Smaller graphs fall back to the single-threaded path since waking up the worker threads would cost more than it saves
+        pub fn set_parallel_node_threshold(&mut self, min_nodes: usize) {
+            self.parallel_node_threshold = min_nodes;
+        }
+
+
+        //  Profiling
+        //  ==============================================================================================================  //
+        /// Start timing every call to `process_block()` of every node as well as the graph as a whole.
+        /// Statistics are kept over the last `window_length` blocks.  Calling this function again discards any statistics collected so far
+        pub fn enable_profiling(&mut self, window_length: usize) {
+            self.profiler = Some(Profiler::new(self.nodes.len(), window_length));
+        }
+
+        /// Stop timing nodes and discard any statistics collected so far
+        pub fn disable_profiling(&mut self) {
+            self.profiler = None;
+        }
+
+        /// Get the rolling timing statistics of a node's `process_block()` call.
+        /// Returns None if profiling is disabled, the node does not exist or the node has not been processed yet
+        pub fn get_node_timing(&self, node_id: usize) -> Option<TimingStatistics> {
+            if !self.check_node_exists(&node_id) {
+                return None;
+            }
+
+            match &self.profiler {
+                Some(profiler) => profiler.node_timings.get(node_id)?.get_statistics(),
+                None => None
+            }
+        }
+
+        /// Get the rolling timing statistics of entire calls to `AudioGraph::process_block()`
+        pub fn get_graph_timing(&self) -> Option<TimingStatistics> {
+            match &self.profiler {
+                Some(profiler) => profiler.graph_timings.get_statistics(),
+                None => None
+            }
+        }
+
+        /// Get the total DSP load of the graph as a fraction of the real-time budget of a block.
+        /// The budget is derived from the `AudioRuntimeParameters` passed to `prepare()`, so this returns None before the graph is prepared
+        pub fn get_dsp_load(&self) -> Option<DspLoad> {
+            if !self.graph_running {
+                return None;
+            }
+
+            let statistics = self.get_graph_timing()?;
+            let budget = self.audio_runtime_params.buffer_size as f32 / self.audio_runtime_params.sampling_freq;
+
+            Some(DspLoad {
+                mean: statistics.mean().as_secs_f32() / budget,
+                max: statistics.max().as_secs_f32() / budget
+            })
+        }
+
+        /// Format the collected timings as a plain text table with one row per processed node (by name where the node has one),
+        /// followed by the timing of the whole graph and its DSP load.
+        /// Returns None if profiling is disabled
+        pub fn get_profiling_report(&self) -> Option<String> {
+            self.profiler.as_ref()?;
+
+            let mut report = format!("{:<24} {:>8} {:>12} {:>12} {:>12}\n", "node", "blocks", "mean (us)", "p95 (us)", "max (us)");
+            let mut write_row = |label: &str, statistics: &TimingStatistics| {
+                let _ = writeln!(report, "{:<24} {:>8} {:>12.2} {:>12.2} {:>12.2}",
+                                 label,
+                                 statistics.get_number_of_blocks(),
+                                 statistics.mean().as_secs_f64() * 1e6,
+                                 statistics.percentile(95.0).as_secs_f64() * 1e6,
+                                 statistics.max().as_secs_f64() * 1e6);
+            };
+
+            for node_id in 0..self.nodes.len() {
+                if let Some(statistics) = self.get_node_timing(node_id) {
+                    write_row(&self.describe_node(node_id), &statistics);
+                }
+            }
+
+            if let Some(statistics) = self.get_graph_timing() {
+                write_row("graph", &statistics);
+            }
+
+            if let Some(load) = self.get_dsp_load() {
+                let _ = writeln!(report, "DSP load: {:.1}% mean, {:.1}% max", load.mean * 100.0, load.max * 100.0);
+            }
+
+            Some(report)
+        }
+
+
+        //  Introspection
+        //  ==============================================================================================================  //
+        /// Export the topology of the graph in Graphviz DOT format, e.g. to render it with `dot -Tsvg`.
+        /// Every node is labelled with its id, type name, node type and input ports.  Edges are labelled with the input port they feed.
+        /// Bypassed nodes are drawn dashed, muted or solo-silenced nodes are greyed out and nodes that are never processed are drawn dotted
+        pub fn export_dot(&self) -> String {
+            let reachable = self.get_reachable_nodes();
+            let mut dot = String::from("digraph audio_graph {\n    rankdir=LR;\n    node [shape=box, fontname=\"Helvetica\"];\n\n");
+
+            for node_id in self.get_node_ids() {
+                let map_node = &self.graph_map.nodes[node_id];
+                let node = &self.nodes[node_id];
+
+                //  Only the name and type name are escaped; the rest of the label uses Graphviz line breaks on purpose
+                let type_name = escape_dot(node.get_type_name().unwrap_or("unnamed"));
+                let mut label = match &map_node.name {
+                    Some(name) => format!("{}: {}\\n{} ({:?})", node_id, escape_dot(name), type_name, node.get_node_type()),
+                    None => format!("{}: {}\\n{:?}", node_id, type_name, node.get_node_type())
+                };
+                if node.get_number_of_inputs() > 0 {
+                    let _ = write!(label, "\\ninputs: {}/{}", map_node.children.len(), node.get_number_of_inputs());
+                }
+
+                let mut styles = vec![];
+                let mut flags = vec![];
+                if map_node.bypassed {
+                    styles.push("dashed");
+                    flags.push("bypassed");
                 }
+                if map_node.muted {
+                    flags.push("muted");
+                }
+                if map_node.soloed {
+                    styles.push("bold");
+                    flags.push("soloed");
+                }
+                if !reachable[node_id] {
+                    styles.push("dotted");
+                }
+                if !flags.is_empty() {
+                    let _ = write!(label, "\\n[{}]", flags.join(", "));
+                }
+
+                let _ = write!(dot, "    n{} [label=\"{}\"", node_id, label);
+                if !styles.is_empty() {
+                    let _ = write!(dot, ", style=\"{}\"", styles.join(","));
+                }
+                if map_node.muted || map_node.silenced_by_solo {
+                    dot.push_str(", fontcolor=gray, color=gray");
+                }
+                if node_id == 0 {
+                    dot.push_str(", shape=doublecircle");
+                }
+                dot.push_str("];\n");
+            }
+
+            dot.push('\n');
+            for connection in self.get_connections() {
+                let _ = writeln!(dot, "    n{} -> n{} [label=\"{}\"];", connection.node_out_id, connection.node_in_id, connection.node_in_input_port);
+            }
+
+            dot.push_str("}\n");
+            dot
+        }
+
+        /// Export a structured JSON snapshot of the graph: every node with its type, ports, parameters, latency and mixing state,
+        /// every connection, and the total latency of the graph
+        pub fn export_json(&self) -> String {
+            let reachable = self.get_reachable_nodes();
+            let order = self.get_processing_order();
+            let latencies = self.compute_latencies(&order);
+
+            let nodes = self.get_node_ids().into_iter().map(|node_id| {
+                let map_node = &self.graph_map.nodes[node_id];
+                let node = &self.nodes[node_id];
+
+                JsonValue::Object(vec![
+                    (String::from("id"), JsonValue::Number(node_id as f64)),
+                    (String::from("name"), match &map_node.name {
+                        Some(name) => JsonValue::String(name.clone()),
+                        None => JsonValue::Null
+                    }),
+                    (String::from("type"), match node.get_type_name() {
+                        Some(type_name) => JsonValue::String(String::from(type_name)),
+                        None => JsonValue::Null
+                    }),
+                    (String::from("node_type"), JsonValue::String(format!("{:?}", node.get_node_type()))),
+                    (String::from("num_inputs"), JsonValue::Number(node.get_number_of_inputs() as f64)),
+                    (String::from("input_channels"), JsonValue::Number(node.get_number_of_input_channels() as f64)),
+                    (String::from("output_channels"), JsonValue::Number(node.get_number_of_output_channels() as f64)),
+                    (String::from("connected_inputs"), JsonValue::Number(map_node.children.len() as f64)),
+                    (String::from("parameter_names"), JsonValue::Array(node.get_parameter_names().iter().map(|name| JsonValue::String(String::from(*name))).collect())),
+                    (String::from("parameters"), JsonValue::Array(node.get_parameters().into_iter().map(JsonValue::from_f32).collect())),
+                    (String::from("latency"), JsonValue::Number(node.latency_samples() as f64)),
+                    (String::from("compensation_delay"), JsonValue::Number(latencies.compensation[node_id] as f64)),
+                    (String::from("processed"), JsonValue::Bool(reachable[node_id])),
+                    (String::from("bypassed"), JsonValue::Bool(map_node.bypassed)),
+                    (String::from("muted"), JsonValue::Bool(map_node.muted)),
+                    (String::from("soloed"), JsonValue::Bool(map_node.soloed))
+                ])
+            }).collect();
+
+            let connections = self.get_connections().into_iter().map(|connection| {
+                JsonValue::Object(vec![
+                    (String::from("from"), JsonValue::Number(connection.node_out_id as f64)),
+                    (String::from("to"), JsonValue::Number(connection.node_in_id as f64)),
+                    (String::from("port"), JsonValue::Number(connection.node_in_input_port as f64))
+                ])
+            }).collect();
+
+            let snapshot = JsonValue::Object(vec![
+                (String::from("nodes"), JsonValue::Array(nodes)),
+                (String::from("connections"), JsonValue::Array(connections)),
+                (String::from("total_latency"), JsonValue::Number(latencies.path_latency[0] as f64))
+            ]);
+
+            snapshot.to_pretty_string()
+        }
+
+        /// Flags every node that is reachable from the output node, i.e. every node that gets processed
+        fn get_reachable_nodes(&self) -> Vec<bool> {
+            let mut reachable = vec![false; self.nodes.len()];
+            for node_id in self.get_processing_order() {
+                reachable[node_id] = true;
+            }
+
+            reachable
+        }
+
+
+        //  Graph compilation
+        //  ==============================================================================================================  //
+        /// Build the processing schedule and allocate a buffer for every node reachable from the output node.
+        /// The schedule is a depth-first post-order traversal of the node tree, so children are always processed before their parents
+        fn compile(&mut self) {
+            let num_nodes = self.nodes.len();
+            let order = self.get_processing_order();
+            let latencies = self.compute_latencies(&order);
+            self.update_solo_state();
+
+            //  Buffers are planar and large enough for both the input channels of every port and the output channels of their node
+            let buffer_size = self.audio_runtime_params.buffer_size;
+            let output_channels: Vec<usize> = self.nodes.iter().map(|node| node.get_number_of_output_channels().max(1)).collect();
+            let output_lengths: Vec<usize> = output_channels.iter().map(|num_channels| num_channels * buffer_size).collect();
+
+            let mut buffers: Vec<Vec<f32>> = (0..num_nodes).map(|_| vec![]).collect();
+            for node_id in &order {
+                let node = &self.nodes[*node_id];
+                let num_input_channels = (0..node.get_number_of_inputs()).map(|port| node.get_input_port_channel(port)).max().unwrap_or(0) + node.get_number_of_input_channels();
+                let num_channels = num_input_channels.max(output_channels[*node_id]);
+                buffers[*node_id] = vec![0.0; num_channels * buffer_size];
+            }
+
+            let compensation_delays = (0..num_nodes).map(|node_id| CompensationDelay::new(latencies.compensation[node_id], output_channels[node_id])).collect();
+
+            let fade_samples = (CROSSFADE_TIME_SECONDS * self.audio_runtime_params.sampling_freq) as usize;
+            let faders = (0..num_nodes).map(|node_id| {
+                let output_length = if buffers[node_id].is_empty() { 0 } else { output_lengths[node_id] };
+                NodeFader::new(&self.graph_map.nodes[node_id], output_length, output_channels[node_id], self.nodes[node_id].latency_samples(), fade_samples)
+            }).collect();
+
+            self.compiled = CompiledGraph {
+                job: ParallelJob::new(num_nodes, order.len()),
+                order,
+                buffers,
+                output_lengths,
+                compensation_delays,
+                faders
+            };
+        }
+
+        /// Get the ids of all nodes reachable from the output node in a depth-first post-order, i.e. children always come before their parents
+        fn get_processing_order(&self) -> Vec<usize> {
+            let mut order = Vec::with_capacity(self.nodes.len());
+
+            //  Each stack entry holds a node and the index of the next child to visit
+            let mut stack: Vec<(usize, usize)> = vec![(0, 0)];
+            while let Some((node_id, child_index)) = stack.pop() {
+                match self.graph_map.nodes[node_id].children.get(child_index) {
+                    Some(child) => {
+                        stack.push((node_id, child_index + 1));
+                        stack.push((*child, 0));
+                    },
+                    None => { order.push(node_id); }
+                }
+            }
+
+            order
+        }
+
+        /// Work out how much every branch has to be delayed so that all branches meeting at a node arrive time-aligned
+        fn compute_latencies(&self, order: &[usize]) -> LatencyMap {
+            let mut latencies = LatencyMap {
+                path_latency: vec![0; self.nodes.len()],
+                compensation: vec![0; self.nodes.len()]
+            };
+
+            for node_id in order {
+                let children = &self.graph_map.nodes[*node_id].children;
+                let slowest_child = children.iter().map(|child| latencies.path_latency[*child]).max().unwrap_or(0);
+
+                for child in children {
+                    latencies.compensation[*child] = slowest_child - latencies.path_latency[*child];
+                }
+
+                latencies.path_latency[*node_id] = slowest_child + self.nodes[*node_id].latency_samples();
             }
 
-            Ok(())
+            latencies
+        }
+    }
+
+
+    /// Edits a graph on behalf of `AudioGraph::edit()`.  Every edit is applied straight away so that the IDs of added nodes can be used right after,
+    /// but only problems that make an edit impossible (missing nodes, a node that already has a parent etc) are caught here.
+    /// Those are collected rather than returned, and the whole batch is rolled back if there are any
+    pub struct GraphEditor<'a> {
+        graph: &'a mut AudioGraph,
+        problems: Vec<Error>
+    }
+
+    impl<'a> GraphEditor<'a> {
+        /// Get the graph as it looks with the edits made so far, e.g. to look up nodes by name
+        pub fn get_graph(&self) -> &AudioGraph {
+            self.graph
         }
 
-        fn check_node_exists(&self, node_id: &usize) -> bool {
-            if *node_id > self.nodes.len() {
-                return false;
+        /// Add a node.  Returns None if the node cannot be added
+        pub fn add_new_node(&mut self, n: Box<dyn AudioNode + 'static>) -> Option<usize> {
+            match self.graph.add_new_node(n) {
+                Ok(node_id) => Some(node_id),
+                Err(e) => { self.problems.push(e); None }
             }
+        }
 
-            true
+        /// Add a node with a name.  Returns None if the node cannot be added
+        pub fn add_new_named_node(&mut self, name: &str, n: Box<dyn AudioNode + 'static>) -> Option<usize> {
+            match self.graph.add_new_named_node(name, n) {
+                Ok(node_id) => Some(node_id),
+                Err(e) => { self.problems.push(e); None }
+            }
         }
 
-        /// Prepare the audio graph with a specified set of audio runtime parameters (sampling freq, buffer size etc).  
-        /// This function will call the initialization functions for all of the nodes.  
-        /// This function only needs to be called once.
-        pub fn prepare(&mut self, audio_parameters: AudioRuntimeParameters) -> Result<(), Error> {
-            if self.graph_running == true {
-                return Err( Error {
-                    code: ErrorCodes::AudioGraphRunning,
-                    message: String::from("Audio Graph is running!")
-                });
+        /// Remove a node and its connections
+        pub fn remove_node(&mut self, node_id: usize) {
+            if let Err(e) = self.graph.remove_node(node_id) {
+                self.problems.push(e);
             }
+        }
 
-            //  Check that audio_parameters have valid inputs
-            if audio_parameters.buffer_size == 0 {
-                return Err(Error {
-                    code: ErrorCodes::InvalidBufferSize,
-                    message: String::from("Invalid buffer size")
-                });
+        /// Connect the output of `node_out_id` to an input port of `node_in_id`.  Ports, cycles and channel counts are checked when the batch ends
+        pub fn connect_node(&mut self, node_out_id: usize, node_in_id: usize, node_in_input_port: usize) {
+            for node_id in [node_out_id, node_in_id] {
+                if !self.graph.check_node_exists(&node_id) {
+                    self.problems.push(Error {
+                        code: ErrorCodes::NodeIDNonExistent,
+                        message: format!("Node ID {} does not exist in graph", node_id)
+                    });
+                    return;
+                }
             }
 
-            if audio_parameters.sampling_freq <= 0.0 {
-                return Err( Error {
-                    code: ErrorCodes::InvalidSamplingFrequency,
-                    message: String::from("Invalid sampling frequency entered")
+            if node_out_id == node_in_id {
+                self.problems.push(Error {
+                    code: ErrorCodes::NodeConnectingToItself,
+                    message: format!("Cannot connect node {} to itself", self.graph.describe_node(node_in_id))
                 });
+                return;
             }
 
-            self.audio_runtime_params = audio_parameters;
-
-            for node in &mut self.nodes {
-                node.init(&self.audio_runtime_params);
+            if node_out_id == 0 {
+                self.problems.push(Error {
+                    code: ErrorCodes::CannotConnectOutputNode,
+                    message: format!("Cannot connect the output node to node {}", self.graph.describe_node(node_in_id))
+                });
+                return;
             }
 
-            self.graph_running = true;
+            if let Some(parent) = self.graph.graph_map.nodes[node_out_id].parent {
+                self.problems.push(Error {
+                    code: ErrorCodes::NodeParentAlreadyExists,
+                    message: format!("Node {} already has a parent: {}", self.graph.describe_node(node_out_id), self.graph.describe_node(parent))
+                });
+                return;
+            }
 
-            Ok(())
+            let connection = Connection {
+                node_out_id,
+                node_in_id,
+                node_in_input_port
+            };
+            let index = self.graph.attach_connection(connection, usize::MAX);
+            self.graph.record_edit(GraphEdit::Connect { connection, index });
         }
 
-        /// Run the audio graph and get a buffer of samples.  
-        /// The audio graph performs a depth-first traversal when obtaining samples from nodes.
-        pub fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> Result<&'a mut [f32], Error> {
+        /// Connect a node to the output node
+        pub fn connect_node_to_output(&mut self, node_out_id: usize) {
+            self.connect_node(node_out_id, 0, 0);
+        }
 
-            //  Ensure that prepare() has been called once before calling process_block().
-            if self.graph_running == false {
-                return Err( Error {
-                    code: ErrorCodes::AudioGraphNotPrepared,
-                    message: String::from("Must call prepare() before attempting to get samples from the audio graph")
-                })
+        /// Remove the connection between two nodes.  Unlike `AudioGraph::disconnect_node()`, asking for a connection that does not exist is a problem
+        pub fn disconnect_node(&mut self, node_out_id: usize, node_in_id: usize) {
+            if !self.graph.check_node_exists(&node_out_id) || self.graph.graph_map.nodes[node_out_id].parent != Some(node_in_id) {
+                self.problems.push(Error {
+                    code: ErrorCodes::ConnectionNonExistent,
+                    message: format!("There is no connection from node {} to node {}", self.graph.describe_node(node_out_id), self.graph.describe_node(node_in_id))
+                });
+                return;
             }
 
-            //  First initialize the stack used for graph traversal
-            self.go_to_branch_end(0);
-
-            while let Some(node) = self.next() {
-                self.nodes[node].process_block(buffer);
-                println!("Node: {}", node); //  For debugging
+            if let Some((connection, index)) = self.graph.detach_connection(node_out_id) {
+                self.graph.record_edit(GraphEdit::Disconnect { connection, index });
             }
-
-            Ok(buffer)
         }
 
-
-        //  Functions for iterating through the graph node
-        //  ==============================================================================================================  //
-        /// For a given node, go to the end of its branch and push the intermediate nodes onto the stack
-        fn go_to_branch_end(&mut self, node_id: usize) {
-            let mut current_id = node_id;
-            loop {
-                self.iter_stack[self.iter_stack_size] = (current_id, self.graph_map.nodes[current_id].children.len(), 0);
-                self.iter_stack_size += 1;
-
-                if self.graph_map.nodes[current_id].children.len() > 0 {
-                    current_id = self.graph_map.nodes[current_id].children[0];
-                } else {
-                    break;
-                }
-            }
+        /// Remove the connection between a node and the output node
+        pub fn disconnect_node_from_output(&mut self, node_out_id: usize) {
+            self.disconnect_node(node_out_id, 0);
         }
 
-        /// Retrieve the next node id in the audio graph chain.  Will return None if the entire graph has been processed
-        fn next(&mut self) -> Option<usize> {
-            if self.iter_stack_size > 0 {
-                //  First, check to see if there are any unvisited children nodes in the current node
-                //  If there are, then traverse to the end of the branch and push the nodes along the way onto the stack
-                let (node_id, num_children, child_index) = self.iter_stack[self.iter_stack_size - 1];
-                if child_index + 1 < num_children {
-                    //  Mark child as visited
-                    self.iter_stack[self.iter_stack_size].2 = child_index + 1;
-
-                    let child_node_id = self.graph_map.nodes[node_id].children[child_index + 1];
-                    self.go_to_branch_end(child_node_id);
-                }
-
-                let next_node_id = self.iter_stack[self.iter_stack_size - 1].0;
-                self.iter_stack_size -= 1;
-
-                return Some(next_node_id);
+        /// Change the parameters of a node
+        pub fn set_node_parameters(&mut self, node_id: usize, parameters: &[f32]) {
+            if let Err(e) = self.graph.set_node_parameters(node_id, parameters) {
+                self.problems.push(e);
             }
-            
-            None
         }
     }
 }
@@ -554,6 +2734,10 @@ pub mod ModelNodes {
             &self.node_type
         }
 
+        fn get_type_name(&self) -> Option<&str> {
+            Some("test")
+        }
+
         fn get_number_of_inputs(&self) -> usize {
             self.num_inputs
         }
@@ -609,6 +2793,10 @@ pub mod ModelNodes {
             &self.node_type
         }
 
+        fn get_type_name(&self) -> Option<&str> {
+            Some("test_gen")
+        }
+
         fn get_number_of_inputs(&self) -> usize {
             0
         }
@@ -643,31 +2831,273 @@ pub mod ModelNodes {
     /// Model Effects Node
     pub struct TestFXNode {
         node_type: AudioNodeType,
-        inputs: [i32; 1],
+        inputs: [i32; 1],
+        num_inputs: usize,
+        next_available_input: usize,
+        audio_runtime_params: AudioRuntimeParameters
+    }
+
+    impl AudioNode for TestFXNode {
+        fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+            println!("Preparing generator node with parameters, fs: {}, buffer_size: {}", audio_runtime_params.sampling_freq, audio_runtime_params.buffer_size);
+            self.audio_runtime_params.buffer_size = audio_runtime_params.buffer_size;
+            self.audio_runtime_params.sampling_freq = audio_runtime_params.sampling_freq;
+        }
+
+        fn get_node_type(&self) -> &AudioNodeType {
+            &self.node_type
+        }
+
+        fn get_type_name(&self) -> Option<&str> {
+            Some("test_fx")
+        }
+
+        fn get_number_of_inputs(&self) -> usize {
+            1
+        }
+
+        fn get_next_available_input(&self) -> Option<usize> {
+            if self.next_available_input >= self.num_inputs {
+                return None;
+            } 
+
+            Some(self.next_available_input)
+        }
+
+        fn connect_input(&mut self) {
+            if self.next_available_input < self.num_inputs {
+                self.next_available_input += 1;
+            }
+        }
+
+        fn disconnect_input(&mut self) {
+            if self.next_available_input > 0 {
+                self.next_available_input -= 1;
+            }
+        }
+
+        fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+            if self.audio_runtime_params.buffer_size > 0 {
+                for i in 0..self.audio_runtime_params.buffer_size {
+                    buffer[i] *= 0.5;
+                }
+            }
+
+            buffer
+        }
+    }
+
+    impl TestFXNode {
+        pub fn new() -> TestFXNode {
+            TestFXNode {
+                node_type: AudioNodeType::Effect,
+                inputs: [-1; 1],
+                num_inputs: 1,
+                next_available_input: 0,
+                audio_runtime_params: AudioRuntimeParameters {
+                    sampling_freq: 0.0,
+                    buffer_size: 0
+                }
+            }
+        }
+    }
+
+
+    /// Model multichannel node.
+    /// Takes a mono input and outputs two channels: the input unchanged on the left and halved on the right.
+    /// Buffers are planar, so the right channel starts `buffer_size` samples into the buffer
+    pub struct TestStereoNode {
+        node_type: AudioNodeType,
+        num_inputs: usize,
+        next_available_input: usize,
+        buffer_size: usize
+    }
+
+    impl AudioNode for TestStereoNode {
+        fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
+            self.buffer_size = audio_runtime_params.buffer_size;
+        }
+
+        fn get_node_type(&self) -> &AudioNodeType {
+            &self.node_type
+        }
+
+        fn get_type_name(&self) -> Option<&str> {
+            Some("test_stereo")
+        }
+
+        fn get_number_of_inputs(&self) -> usize {
+            self.num_inputs
+        }
+
+        fn get_next_available_input(&self) -> Option<usize> {
+            if self.next_available_input >= self.num_inputs {
+                return None;
+            }
+
+            Some(self.next_available_input)
+        }
+
+        fn connect_input(&mut self) {
+            if self.next_available_input < self.num_inputs {
+                self.next_available_input += 1;
+            }
+        }
+
+        fn disconnect_input(&mut self) {
+            if self.next_available_input > 0 {
+                self.next_available_input -= 1;
+            }
+        }
+
+        fn get_number_of_output_channels(&self) -> usize {
+            2
+        }
+
+        fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
+            let (left, right) = buffer.split_at_mut(self.buffer_size);
+            for (left_sample, right_sample) in left.iter().zip(right.iter_mut()) {
+                *right_sample = *left_sample * 0.5;
+            }
+
+            buffer
+        }
+    }
+
+    impl Default for TestStereoNode {
+        fn default() -> TestStereoNode {
+            TestStereoNode::new()
+        }
+    }
+
+    impl TestStereoNode {
+        pub fn new() -> TestStereoNode {
+            TestStereoNode {
+                node_type: AudioNodeType::Effect,
+                num_inputs: 1,
+                next_available_input: 0,
+                buffer_size: 0
+            }
+        }
+    }
+
+
+    /// Model Mixer node.
+    /// The audio graph sums the outputs of all children connected to a node before calling `process_block()`, so a mixer only needs to declare its inputs
+    pub struct TestMixerNode {
+        node_type: AudioNodeType,
+        num_inputs: usize,
+        next_available_input: usize
+    }
+
+    impl AudioNode for TestMixerNode {
+        fn get_node_type(&self) -> &AudioNodeType {
+            &self.node_type
+        }
+
+        fn get_type_name(&self) -> Option<&str> {
+            Some("test_mixer")
+        }
+
+        /// The only parameter is the number of inputs.  It cannot be reduced below the number of inputs already connected
+        fn change_parameters(&mut self, parameters: &[f32]) {
+            if let Some(num_inputs) = parameters.first() {
+                self.num_inputs = (num_inputs.max(0.0) as usize).max(self.next_available_input);
+            }
+        }
+
+        fn get_parameters(&self) -> Vec<f32> {
+            vec![self.num_inputs as f32]
+        }
+
+        fn get_parameter_names(&self) -> &[&str] {
+            &["num_inputs"]
+        }
+
+        fn get_number_of_inputs(&self) -> usize {
+            self.num_inputs
+        }
+
+        fn get_next_available_input(&self) -> Option<usize> {
+            if self.next_available_input >= self.num_inputs {
+                return None;
+            }
+
+            Some(self.next_available_input)
+        }
+
+        fn connect_input(&mut self) {
+            if self.next_available_input < self.num_inputs {
+                self.next_available_input += 1;
+            }
+        }
+
+        fn disconnect_input(&mut self) {
+            if self.next_available_input > 0 {
+                self.next_available_input -= 1;
+            }
+        }
+    }
+
+    impl TestMixerNode {
+        pub fn new(num_inputs: usize) -> TestMixerNode {
+            TestMixerNode {
+                node_type: AudioNodeType::Mixer,
+                num_inputs,
+                next_available_input: 0
+            }
+        }
+    }
+
+
+    /// Model Effects node with latency.
+    /// Delays its input by a fixed number of samples and reports it through `latency_samples()`, like a lookahead limiter or linear-phase filter would
+    pub struct TestLatencyNode {
+        node_type: AudioNodeType,
         num_inputs: usize,
         next_available_input: usize,
-        audio_runtime_params: AudioRuntimeParameters
+        latency: usize,
+        delay_line: Vec<f32>,
+        position: usize
     }
 
-    impl AudioNode for TestFXNode {
-        fn init(&mut self, audio_runtime_params: &AudioRuntimeParameters) {
-            println!("Preparing generator node with parameters, fs: {}, buffer_size: {}", audio_runtime_params.sampling_freq, audio_runtime_params.buffer_size);
-            self.audio_runtime_params.buffer_size = audio_runtime_params.buffer_size;
-            self.audio_runtime_params.sampling_freq = audio_runtime_params.sampling_freq;
+    impl AudioNode for TestLatencyNode {
+        fn init(&mut self, _audio_runtime_params: &AudioRuntimeParameters) {
+            self.delay_line = vec![0.0; self.latency];
+            self.position = 0;
         }
 
         fn get_node_type(&self) -> &AudioNodeType {
             &self.node_type
         }
 
+        fn get_type_name(&self) -> Option<&str> {
+            Some("test_latency")
+        }
+
+        /// The only parameter is the latency in samples, which takes effect the next time the node is initialised
+        fn change_parameters(&mut self, parameters: &[f32]) {
+            if let Some(latency) = parameters.first() {
+                self.latency = latency.max(0.0) as usize;
+            }
+        }
+
+        fn get_parameters(&self) -> Vec<f32> {
+            vec![self.latency as f32]
+        }
+
+        fn get_parameter_names(&self) -> &[&str] {
+            &["latency_samples"]
+        }
+
         fn get_number_of_inputs(&self) -> usize {
-            1
+            self.num_inputs
         }
 
         fn get_next_available_input(&self) -> Option<usize> {
             if self.next_available_input >= self.num_inputs {
                 return None;
-            } 
+            }
 
             Some(self.next_available_input)
         }
@@ -684,28 +3114,33 @@ pub mod ModelNodes {
             }
         }
 
+        fn latency_samples(&self) -> usize {
+            self.latency
+        }
+
         fn process_block<'a>(&mut self, buffer: &'a mut [f32]) -> &'a mut [f32] {
-            if self.audio_runtime_params.buffer_size > 0 {
-                for i in 0..self.audio_runtime_params.buffer_size {
-                    buffer[i] *= 0.5;
-                }
+            if self.delay_line.is_empty() {
+                return buffer;
+            }
+
+            for sample in buffer.iter_mut() {
+                std::mem::swap(&mut self.delay_line[self.position], sample);
+                self.position = (self.position + 1) % self.delay_line.len();
             }
 
             buffer
         }
     }
 
-    impl TestFXNode {
-        pub fn new() -> TestFXNode {
-            TestFXNode {
+    impl TestLatencyNode {
+        pub fn new(latency: usize) -> TestLatencyNode {
+            TestLatencyNode {
                 node_type: AudioNodeType::Effect,
-                inputs: [-1; 1],
                 num_inputs: 1,
                 next_available_input: 0,
-                audio_runtime_params: AudioRuntimeParameters {
-                    sampling_freq: 0.0,
-                    buffer_size: 0
-                }
+                latency,
+                delay_line: vec![],
+                position: 0
             }
         }
     }
@@ -742,6 +3177,22 @@ pub mod ModelNodes {
 
 
 
+mod Json;
+pub mod Patch;
+pub mod Builder;
+pub mod AudioFile;
+pub mod Render;
+pub mod Generators;
+pub mod Envelopes;
+pub mod Filters;
+pub mod Delays;
+pub mod Reverbs;
+pub mod Convolution;
+pub mod Dynamics;
+pub mod Utilities;
+
+
+#[cfg(test)]
 mod tests {
     use super::{AudioToolbox, ModelNodes};
 
@@ -935,4 +3386,687 @@ mod tests {
             Err(_) => {}
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn profile_audio_graph() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let n1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let n2_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+
+        if graph.connect_node(n1_id, n2_id, 0).is_err() { panic!(); }
+        if graph.connect_node_to_output(n2_id).is_err() { panic!(); }
+
+        //  Profiling can be enabled before the graph is prepared and keeps track of nodes added afterwards
+        graph.enable_profiling(8);
+        let n3_id = graph.add_new_node(Box::new(ModelNodes::TestNode::new())).ok().unwrap();
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        //  Nothing has been processed yet
+        assert!(graph.get_node_timing(n1_id).is_none());
+        assert!(graph.get_dsp_load().is_none());
+
+        let mut buffer = [0.0; 4];
+        for _ in 0..10 {
+            if graph.process_block(&mut buffer).is_err() { panic!(); }
+        }
+
+        //  Only the last 8 blocks are kept
+        let n1_timing = graph.get_node_timing(n1_id).unwrap();
+        assert_eq!(n1_timing.get_number_of_blocks(), 8);
+        assert!(n1_timing.percentile(50.0) <= n1_timing.max());
+        assert!(n1_timing.percentile(0.0) <= n1_timing.mean());
+        assert_eq!(n1_timing.percentile(100.0), n1_timing.max());
+
+        assert_eq!(graph.get_graph_timing().unwrap().get_number_of_blocks(), 8);
+
+        //  n3 is not connected, so it is never processed
+        assert!(graph.get_node_timing(n3_id).is_none());
+        assert!(graph.get_node_timing(42).is_none());
+
+        let load = graph.get_dsp_load().unwrap();
+        assert!(load.mean >= 0.0 && load.mean <= load.max);
+
+        graph.disable_profiling();
+        assert!(graph.get_graph_timing().is_none());
+    }
+
+    #[test]
+    fn mix_branches_in_audio_graph() {
+        //  [g1] -----------> [mixer] -> [Output]
+        //  [g2] -> [fx] ---> [     ]
+        //  [g3] -----------> [     ]
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(3))).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        let g3_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+
+        if graph.connect_node(g1_id, mixer_id, 0).is_err() { panic!(); }
+        if graph.connect_node(g2_id, fx_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx_id, mixer_id, 1).is_err() { panic!(); }
+        if graph.connect_node(g3_id, mixer_id, 2).is_err() { panic!(); }
+        if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+
+        //  Disconnecting g3 must remove it from the mixer so that it is no longer processed
+        graph.disconnect_node(g3_id, mixer_id);
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [1.5; 4]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    /// Build a tree of `num_mixers` mixers feeding a master mixer, where every mixer has `branches_per_mixer` [gen]->[fx] branches
+    fn build_mixer_tree(num_mixers: usize, branches_per_mixer: usize) -> AudioToolbox::AudioGraph {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let master_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(num_mixers))).ok().unwrap();
+        if graph.connect_node_to_output(master_id).is_err() { panic!(); }
+
+        for m in 0..num_mixers {
+            let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(branches_per_mixer))).ok().unwrap();
+            if graph.connect_node(mixer_id, master_id, m).is_err() { panic!(); }
+
+            for b in 0..branches_per_mixer {
+                let gen_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+                let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+                if graph.connect_node(gen_id, fx_id, 0).is_err() { panic!(); }
+                if graph.connect_node(fx_id, mixer_id, b).is_err() { panic!(); }
+            }
+        }
+
+        graph
+    }
+
+    #[test]
+    fn run_audio_graph_in_parallel() {
+        let mut serial_graph = build_mixer_tree(4, 5);
+        let mut parallel_graph = build_mixer_tree(4, 5);
+
+        parallel_graph.enable_parallel_processing(3);
+        parallel_graph.set_parallel_node_threshold(0);
+        parallel_graph.enable_profiling(4);
+
+        for graph in [&mut serial_graph, &mut parallel_graph] {
+            let runtime_params = AudioToolbox::AudioRuntimeParameters {
+                sampling_freq: 48_000.0,
+                buffer_size: 64
+            };
+
+            if graph.prepare(runtime_params).is_err() { panic!(); }
+        }
+
+        let mut serial_buffer = [0.0; 64];
+        let mut parallel_buffer = [0.0; 64];
+        for _ in 0..50 {
+            if serial_graph.process_block(&mut serial_buffer).is_err() { panic!(); }
+            if parallel_graph.process_block(&mut parallel_buffer).is_err() { panic!(); }
+
+            assert_eq!(serial_buffer, parallel_buffer);
+            assert_eq!(parallel_buffer[0], 10.0);
+        }
+
+        //  Every node was timed on whichever thread processed it
+        assert_eq!(parallel_graph.get_node_timing(1).unwrap().get_number_of_blocks(), 4);
+
+        //  Small graphs fall back to the calling thread
+        parallel_graph.set_parallel_node_threshold(1000);
+        if parallel_graph.process_block(&mut parallel_buffer).is_err() { panic!(); }
+        assert_eq!(serial_buffer, parallel_buffer);
+    }
+
+    #[test]
+    fn move_audio_graph_to_audio_thread() {
+        //  Fails to compile if the graph cannot be handed to an audio callback thread
+        fn assert_send<T: Send>() {}
+        assert_send::<AudioToolbox::AudioGraph>();
+
+        let mut graph = build_mixer_tree(2, 2);
+        graph.enable_parallel_processing(2);
+        graph.set_parallel_node_threshold(0);
+        if graph.prepare(AudioToolbox::AudioRuntimeParameters { sampling_freq: 48_000.0, buffer_size: 16 }).is_err() { panic!(); }
+
+        let audio_thread = std::thread::spawn(move || {
+            let mut buffer = [0.0; 16];
+            if graph.process_block(&mut buffer).is_err() { panic!(); }
+            buffer
+        });
+        assert_eq!(audio_thread.join().unwrap(), [2.0; 16]);
+    }
+
+    #[test]
+    fn compensate_latency_in_audio_graph() {
+        //  [g1] -> [latency(3)] -> [mixer] -> [Output]
+        //  [g2] ----------------> [     ]
+        //  [g3] -> [latency(1)] -> [     ]
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(3))).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let l1_id = graph.add_new_node(Box::new(ModelNodes::TestLatencyNode::new(3))).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let g3_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let l3_id = graph.add_new_node(Box::new(ModelNodes::TestLatencyNode::new(1))).ok().unwrap();
+
+        if graph.connect_node(g1_id, l1_id, 0).is_err() { panic!(); }
+        if graph.connect_node(l1_id, mixer_id, 0).is_err() { panic!(); }
+        if graph.connect_node(g2_id, mixer_id, 1).is_err() { panic!(); }
+        if graph.connect_node(g3_id, l3_id, 0).is_err() { panic!(); }
+        if graph.connect_node(l3_id, mixer_id, 2).is_err() { panic!(); }
+        if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        assert_eq!(graph.get_total_latency(), 3);
+        assert_eq!(graph.get_compensation_delay(l1_id), Some(0));
+        assert_eq!(graph.get_compensation_delay(g2_id), Some(3));
+        assert_eq!(graph.get_compensation_delay(l3_id), Some(2));
+        assert_eq!(graph.get_compensation_delay(42), None);
+
+        //  All three branches must reach the mixer at the same time
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [0.0, 0.0, 0.0, 3.0]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [3.0; 4]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    #[test]
+    fn bypass_node_in_audio_graph() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let n1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let n2_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+
+        if graph.connect_node(n1_id, n2_id, 0).is_err() { panic!(); }
+        if graph.connect_node_to_output(n2_id).is_err() { panic!(); }
+
+        //  The output node cannot be bypassed
+        if graph.set_node_bypass(0, true).is_ok() { panic!(); }
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 512
+        };
+
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 512];
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        assert_eq!(buffer, [0.5; 512]);
+
+        //  Bypassing the effect fades from the processed signal to the dry signal without jumping
+        if graph.set_node_bypass(n2_id, true).is_err() { panic!(); }
+        assert!(graph.is_node_bypassed(n2_id));
+
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        assert!(buffer[0] > 0.5 && buffer[0] < 0.51);
+        for i in 1..512 {
+            assert!(buffer[i] >= buffer[i - 1]);
+        }
+        assert_eq!(buffer[511], 1.0);
+
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        assert_eq!(buffer, [1.0; 512]);
+
+        if graph.set_node_bypass(n2_id, false).is_err() { panic!(); }
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+        assert_eq!(buffer, [0.5; 512]);
+    }
+
+    #[test]
+    fn bypass_node_with_latency() {
+        //  [g1] -> [latency(3)] -> [mixer] -> [Output]
+        //  [g2] ----------------> [     ]
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(2))).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let l1_id = graph.add_new_node(Box::new(ModelNodes::TestLatencyNode::new(3))).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+
+        if graph.connect_node(g1_id, l1_id, 0).is_err() { panic!(); }
+        if graph.connect_node(l1_id, mixer_id, 0).is_err() { panic!(); }
+        if graph.connect_node(g2_id, mixer_id, 1).is_err() { panic!(); }
+        if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+
+        if graph.set_node_bypass(l1_id, true).is_err() { panic!(); }
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        //  The bypassed node still delays its input by its latency, so both branches stay aligned
+        let mut buffer = [0.0; 4];
+        match graph.process_block(&mut buffer) {
+            Ok(buffer) => { assert_eq!(buffer, [0.0, 0.0, 0.0, 2.0]); },
+            Err(e) => { println!("{}", e.message); panic!(); }
+        }
+    }
+
+    #[test]
+    fn mute_and_solo_nodes_in_audio_graph() {
+        //  [g1] -> [fx] -> [mixer] -> [Output]
+        //  [g2] ---------> [     ]
+        //  [g3] ---------> [     ]
+        let build_graph = || {
+            let mut graph = AudioToolbox::AudioGraph::new();
+            let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(3))).ok().unwrap();
+            let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+            let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+            let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+            let g3_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+
+            if graph.connect_node(g1_id, fx_id, 0).is_err() { panic!(); }
+            if graph.connect_node(fx_id, mixer_id, 0).is_err() { panic!(); }
+            if graph.connect_node(g2_id, mixer_id, 1).is_err() { panic!(); }
+            if graph.connect_node(g3_id, mixer_id, 2).is_err() { panic!(); }
+            if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+
+            (graph, [g1_id, fx_id, g2_id, g3_id])
+        };
+
+        let render = |graph: &mut AudioToolbox::AudioGraph| {
+            let runtime_params = AudioToolbox::AudioRuntimeParameters {
+                sampling_freq: 44_100.0,
+                buffer_size: 4
+            };
+
+            if graph.prepare(runtime_params).is_err() { panic!(); }
+
+            let mut buffer = [0.0; 4];
+            if graph.process_block(&mut buffer).is_err() { panic!(); }
+            buffer[0]
+        };
+
+        let (mut graph, [_, _, g2_id, _]) = build_graph();
+        if graph.set_node_mute(g2_id, true).is_err() { panic!(); }
+        assert!(graph.is_node_muted(g2_id));
+        assert_eq!(render(&mut graph), 1.5);
+
+        //  Soloing the effect silences the other two branches
+        let (mut graph, [_, fx_id, _, _]) = build_graph();
+        if graph.set_node_solo(fx_id, true).is_err() { panic!(); }
+        assert!(graph.is_node_soloed(fx_id));
+        assert_eq!(render(&mut graph), 0.5);
+
+        //  Soloing a node inside a branch solos the whole branch
+        let (mut graph, [g1_id, _, _, g3_id]) = build_graph();
+        if graph.set_node_solo(g1_id, true).is_err() { panic!(); }
+        if graph.set_node_solo(g3_id, true).is_err() { panic!(); }
+        assert_eq!(render(&mut graph), 1.5);
+
+        let (mut graph, [g1_id, _, _, _]) = build_graph();
+        if graph.set_node_solo(g1_id, true).is_err() { panic!(); }
+        if graph.set_node_solo(g1_id, false).is_err() { panic!(); }
+        assert_eq!(render(&mut graph), 2.5);
+    }
+
+    #[test]
+    fn export_audio_graph_topology() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let n1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let n2_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        let n3_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+
+        if graph.connect_node(n1_id, n2_id, 0).is_err() { panic!(); }
+        if graph.connect_node_to_output(n2_id).is_err() { panic!(); }
+        if graph.set_node_bypass(n2_id, true).is_err() { panic!(); }
+
+        let dot = graph.export_dot();
+        assert!(dot.starts_with("digraph audio_graph {"));
+        assert!(dot.contains("    n2 [label=\"2: test_fx\\nEffect\\ninputs: 1/1\\n[bypassed]\", style=\"dashed\"];"));
+        assert!(dot.contains("    n1 -> n2 [label=\"0\"];"));
+        assert!(dot.contains("    n2 -> n0 [label=\"0\"];"));
+
+        //  n3 is not connected, so it is drawn dotted
+        assert!(dot.contains(&format!("    n{} [label=\"{}: test_gen\\nGenerator\", style=\"dotted\"];", n3_id, n3_id)));
+
+        let snapshot = match super::Json::parse(&graph.export_json()) {
+            Ok(snapshot) => snapshot,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+
+        let nodes = snapshot.get("nodes").and_then(|nodes| nodes.as_array()).unwrap();
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[0].get("node_type").and_then(|t| t.as_str()), Some("Output"));
+        assert_eq!(nodes[2].get("type").and_then(|t| t.as_str()), Some("test_fx"));
+        assert_eq!(nodes[2].get("bypassed").and_then(|b| b.as_bool()), Some(true));
+        assert_eq!(nodes[n3_id].get("processed").and_then(|p| p.as_bool()), Some(false));
+
+        let connections = snapshot.get("connections").and_then(|connections| connections.as_array()).unwrap();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[1].get("from").and_then(|id| id.as_usize()), Some(n1_id));
+        assert_eq!(snapshot.get("total_latency").and_then(|latency| latency.as_usize()), Some(0));
+    }
+
+    #[test]
+    fn name_nodes_in_audio_graph() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        let gen_id = match graph.add_new_named_node("osc", Box::new(ModelNodes::TestGenNode::new())) {
+            Ok(id) => id,
+            Err(e) => { println!("{}", e.message); panic!(); }
+        };
+        let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+
+        assert_eq!(graph.find_node_by_name("osc"), Some(gen_id));
+        assert_eq!(graph.find_node_by_name(AudioToolbox::OUTPUT_NODE_NAME), Some(0));
+        assert_eq!(graph.get_node_name(fx_id), None);
+
+        //  Names are unique and cannot be empty
+        match graph.set_node_name(fx_id, "osc") {
+            Err(e) => { assert_eq!(e.code, AudioToolbox::ErrorCodes::NodeNameAlreadyExists); },
+            Ok(()) => panic!()
+        }
+        match graph.add_new_named_node("", Box::new(ModelNodes::TestFXNode::new())) {
+            Err(e) => { assert_eq!(e.code, AudioToolbox::ErrorCodes::NodeNameInvalid); },
+            Ok(_) => panic!()
+        }
+        assert_eq!(graph.get_number_of_nodes(), 3);
+
+        if graph.set_node_name(fx_id, "lowpass").is_err() { panic!(); }
+        assert_eq!(graph.find_node_by_name("lowpass"), Some(fx_id));
+
+        //  Names show up in error messages and exported topology
+        if graph.connect_node(gen_id, fx_id, 0).is_err() { panic!(); }
+        match graph.connect_node(fx_id, gen_id, 0) {
+            Err(e) => { assert_eq!(e.message, "Node 1 (\"osc\") has no input port 0"); },
+            Ok(()) => panic!()
+        }
+        assert!(graph.export_dot().contains("    n2 [label=\"2: lowpass\\ntest_fx (Effect)\\ninputs: 1/1\", style=\"dotted\"];"));
+
+        //  Quotes, backslashes and line breaks in names cannot break out of the label
+        if graph.set_node_name(gen_id, "saw \"A\"\\1\nright\r").is_err() { panic!(); }
+        assert!(graph.export_dot().contains("    n1 [label=\"1: saw \\\"A\\\"\\\\1\\nright\\r\\ntest_gen (Generator)\""));
+        if graph.set_node_name(gen_id, "osc").is_err() { panic!(); }
+
+        if graph.clear_node_name(fx_id).is_err() { panic!(); }
+        assert_eq!(graph.find_node_by_name("lowpass"), None);
+        if graph.set_node_name(0, "master").is_ok() { panic!(); }
+
+        //  Profiling reports label nodes by name
+        if graph.connect_node_to_output(fx_id).is_err() { panic!(); }
+        graph.enable_profiling(8);
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        let mut buffer = [0.0; 4];
+        if graph.process_block(&mut buffer).is_err() { panic!(); }
+
+        let report = graph.get_profiling_report().unwrap();
+        assert!(report.contains("1 (\"osc\")"));
+        assert!(report.contains("graph"));
+    }
+
+    #[test]
+    fn undo_and_redo_graph_edits() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        graph.enable_history(16);
+
+        //  [g1] -> [fx] -> [mixer] -> [Output]
+        //  [g2] ---------> [     ]
+        graph.begin_transaction();
+        let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(2))).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        if graph.connect_node(g1_id, fx_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx_id, mixer_id, 0).is_err() { panic!(); }
+        if graph.connect_node(g2_id, mixer_id, 1).is_err() { panic!(); }
+        if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+        if graph.commit_transaction().is_err() { panic!(); }
+
+        let built = graph.export_json();
+
+        //  Removing a node takes its connections with it, undo puts everything back in place
+        if graph.remove_node(fx_id).is_err() { panic!(); }
+        assert!(graph.get_node(fx_id).is_none());
+        assert_eq!(graph.get_node_ids(), vec![0, mixer_id, g1_id, g2_id]);
+        assert_eq!(graph.get_connections().len(), 2);
+
+        if !matches!(graph.undo(), Ok(true)) { panic!(); }
+        assert_eq!(graph.export_json(), built);
+
+        if !matches!(graph.redo(), Ok(true)) { panic!(); }
+        assert_eq!(graph.get_connections().len(), 2);
+        if !matches!(graph.undo(), Ok(true)) { panic!(); }
+
+        //  The whole transaction is undone at once
+        if !matches!(graph.undo(), Ok(true)) { panic!(); }
+        assert_eq!(graph.get_number_of_nodes(), 1);
+        assert_eq!(graph.get_connections().len(), 0);
+        assert!(!graph.can_undo());
+
+        if !matches!(graph.redo(), Ok(true)) { panic!(); }
+        assert_eq!(graph.export_json(), built);
+
+        //  The removal is still waiting to be redone until the next edit
+        assert!(graph.can_redo());
+
+        //  Parameter changes can be undone, even while the graph is running, topology changes cannot
+        if graph.set_node_parameters(mixer_id, &[3.0]).is_err() { panic!(); }
+        graph.disconnect_node(g2_id, mixer_id);
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+
+        match graph.undo() {
+            Err(e) => { assert_eq!(e.code, AudioToolbox::ErrorCodes::AudioGraphRunning); },
+            Ok(_) => panic!()
+        }
+
+        if graph.set_node_parameters(mixer_id, &[4.0]).is_err() { panic!(); }
+        if !matches!(graph.undo(), Ok(true)) { panic!(); }
+        assert_eq!(graph.get_node(mixer_id).unwrap().get_parameters(), vec![3.0]);
+    }
+
+    #[test]
+    fn bound_edit_history_length() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        graph.enable_history(2);
+
+        for _ in 0..4 {
+            if graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).is_err() { panic!(); }
+        }
+
+        //  Only the last two additions are remembered
+        let mut num_undone = 0;
+        while let Ok(true) = graph.undo() {
+            num_undone += 1;
+        }
+
+        assert_eq!(num_undone, 2);
+        assert_eq!(graph.get_number_of_nodes(), 3);
+
+        //  A new edit clears the redo stack
+        assert!(graph.can_redo());
+        if graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).is_err() { panic!(); }
+        assert!(!graph.can_redo());
+
+        match graph.commit_transaction() {
+            Err(e) => { assert_eq!(e.code, AudioToolbox::ErrorCodes::TransactionNotOpen); },
+            Ok(()) => panic!()
+        }
+    }
+
+    #[test]
+    fn edit_audio_graph_in_batches() {
+        //  [g1] -> [fx] -> [mixer] -> [Output]
+        //  [g2] ---------> [     ]
+        let mut graph = AudioToolbox::AudioGraph::new();
+        graph.enable_history(8);
+
+        let mixer_id = graph.add_new_node(Box::new(ModelNodes::TestMixerNode::new(2))).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        if graph.connect_node(g1_id, fx_id, 0).is_err() { panic!(); }
+        if graph.connect_node(fx_id, mixer_id, 0).is_err() { panic!(); }
+        if graph.connect_node(g2_id, mixer_id, 1).is_err() { panic!(); }
+        if graph.connect_node_to_output(mixer_id).is_err() { panic!(); }
+
+        let before = graph.export_json();
+
+        //  A failed batch is rolled back completely and reports every problem
+        let result = graph.edit(|editor| {
+            let new_fx_id = editor.add_new_node(Box::new(ModelNodes::TestFXNode::new())).unwrap();
+            editor.disconnect_node(fx_id, mixer_id);
+            editor.remove_node(g2_id);
+            editor.connect_node(new_fx_id, mixer_id, 5);
+            editor.disconnect_node(g1_id, mixer_id);
+
+            //  fx -> new_fx -> ... and new_fx -> fx is a cycle once new_fx is disconnected again
+            editor.disconnect_node(new_fx_id, mixer_id);
+            editor.disconnect_node(g1_id, fx_id);
+            editor.connect_node(fx_id, new_fx_id, 0);
+            editor.connect_node(new_fx_id, fx_id, 0);
+        });
+
+        match result {
+            Err(problems) => {
+                let codes: Vec<AudioToolbox::ErrorCodes> = problems.iter().map(|problem| problem.code).collect();
+                assert_eq!(codes, vec![AudioToolbox::ErrorCodes::ConnectionNonExistent, AudioToolbox::ErrorCodes::ConnectionCreatesCycle]);
+            },
+            Ok(()) => panic!()
+        }
+
+        assert_eq!(graph.export_json(), before);
+        assert_eq!(graph.get_node(mixer_id).unwrap().get_next_available_input(), None);
+        assert!(!graph.can_redo());
+
+        let result = graph.edit(|editor| {
+            editor.connect_node(g1_id, mixer_id, 1);
+            editor.disconnect_node(g2_id, mixer_id);
+            editor.connect_node(g2_id, mixer_id, 3);
+        });
+
+        //  Problems found while editing come first, followed by those in the resulting topology
+        match result {
+            Err(problems) => {
+                assert_eq!(problems.len(), 2);
+                assert_eq!(problems[0].code, AudioToolbox::ErrorCodes::NodeParentAlreadyExists);
+                assert_eq!(problems[1].code, AudioToolbox::ErrorCodes::NodeInputPortInvalid);
+            },
+            Ok(()) => panic!()
+        }
+        assert_eq!(graph.export_json(), before);
+
+        //  Swap the two branches of the mixer, which passes through states the regular API would refuse
+        let result = graph.edit(|editor| {
+            editor.disconnect_node(fx_id, mixer_id);
+            editor.disconnect_node(g2_id, mixer_id);
+            editor.connect_node(g2_id, mixer_id, 0);
+            editor.connect_node(fx_id, mixer_id, 1);
+        });
+
+        if let Err(problems) = result {
+            for problem in problems { println!("{}", problem.message); }
+            panic!();
+        }
+
+        assert_eq!(graph.get_connections().iter().filter(|connection| connection.node_in_id == mixer_id).map(|connection| (connection.node_out_id, connection.node_in_input_port)).collect::<Vec<_>>(), vec![(g2_id, 0), (fx_id, 1)]);
+
+        //  The batch is undone in one step
+        if !matches!(graph.undo(), Ok(true)) { panic!(); }
+        assert_eq!(graph.export_json(), before);
+
+        //  The output node cannot feed another node, neither in a batch nor directly
+        let new_fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        let before = graph.export_json();
+        match graph.edit(|editor| editor.connect_node(0, new_fx_id, 0)) {
+            Err(problems) => assert_eq!(problems[0].code, AudioToolbox::ErrorCodes::CannotConnectOutputNode),
+            Ok(()) => panic!()
+        }
+        assert_eq!(graph.export_json(), before);
+
+        match graph.connect_node(0, new_fx_id, 0) {
+            Err(e) => assert_eq!(e.code, AudioToolbox::ErrorCodes::CannotConnectOutputNode),
+            Ok(_) => panic!()
+        }
+        assert_eq!(graph.export_json(), before);
+    }
+
+    #[test]
+    fn validate_audio_graph() {
+        let mut graph = AudioToolbox::AudioGraph::new();
+        graph.set_validate_on_prepare(true);
+
+        let report = graph.validate();
+        assert!(!report.is_valid());
+        assert_eq!(report.get_errors()[0].code, AudioToolbox::ErrorCodes::OutputUnconnected);
+
+        //  [fx] -> [Output]     [g1]     [g2] -> [fx2]
+        let fx_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        let g1_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let g2_id = graph.add_new_node(Box::new(ModelNodes::TestGenNode::new())).ok().unwrap();
+        let fx2_id = graph.add_new_node(Box::new(ModelNodes::TestFXNode::new())).ok().unwrap();
+        if graph.connect_node_to_output(fx_id).is_err() { panic!(); }
+        if graph.connect_node(g2_id, fx2_id, 0).is_err() { panic!(); }
+
+        let report = graph.validate();
+        let errors = report.get_errors();
+        let warnings = report.get_warnings();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, AudioToolbox::ErrorCodes::NodeInputUnfilled);
+        assert_eq!(errors[0].node_id, Some(fx_id));
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].code, AudioToolbox::ErrorCodes::NodeUnconnected);
+        assert_eq!(warnings[0].node_id, Some(g1_id));
+        assert_eq!(warnings[1].code, AudioToolbox::ErrorCodes::NodeUnreachable);
+        assert_eq!(warnings[1].message, "Nodes 4, 3 are not connected to the output and will never be processed");
+        assert_eq!(report.to_string().lines().count(), 3);
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+
+        match graph.prepare(runtime_params) {
+            Err(e) => { assert_eq!(e.code, AudioToolbox::ErrorCodes::GraphInvalid); },
+            Ok(()) => panic!()
+        }
+
+        //  Warnings alone do not keep the graph from being prepared
+        if graph.connect_node(g1_id, fx_id, 0).is_err() { panic!(); }
+        assert!(graph.validate().is_valid());
+
+        let runtime_params = AudioToolbox::AudioRuntimeParameters {
+            sampling_freq: 44_100.0,
+            buffer_size: 4
+        };
+        if graph.prepare(runtime_params).is_err() { panic!(); }
+    }
+}
@@ -1,3 +1,350 @@
-fn main() {
+//! # audio_graph
+//! Command line tool for working with patch files without writing any code.
+//!
+//! ```text
+//! audio_graph render patch.json -o out.wav --duration 10
+//! audio_graph inspect patch.json
+//! audio_graph bench patch.json --buffer-sizes 64,256,1024
+//! audio_graph validate patch.json
+//! ```
+//!
+//! Patches are loaded with every node type of `NodeRegistry::with_default_nodes()`
 
+use std::env;
+use std::process::ExitCode;
+
+use audio_graph::AudioToolbox::{AudioGraph, AudioRuntimeParameters};
+use audio_graph::AudioFile::SampleFormat;
+use audio_graph::Patch::{load_patch_from_file, NodeRegistry};
+use audio_graph::Render::{render_to_wav, RenderLength};
+
+const USAGE: &str = "\
+Usage: audio_graph <command> <patch.json> [options]
+
+Commands:
+  render     Render the patch offline into a WAV file
+  inspect    Print the nodes, connections, parameters and latency of the patch
+  bench      Measure the processing time per block of every node
+  validate   Check the patch for wiring problems
+
+Options:
+  -o, --output <file>        WAV file to render into (render, required)
+  --duration <seconds>       Length of the render [default: 10]
+  --format <format>          Sample format of the render: int16, int24 or float32 [default: int24]
+  --sample-rate <hz>         Sampling frequency [default: 48000]
+  --buffer-size <samples>    Buffer size [default: 512]
+  --buffer-sizes <list>      Comma separated buffer sizes to benchmark [default: 64,128,256,512,1024]
+  --blocks <count>           Number of blocks to benchmark per buffer size [default: 1000]";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Command {
+    Render,
+    Inspect,
+    Bench,
+    Validate
+}
+
+#[derive(PartialEq, Debug)]
+struct Options {
+    command: Command,
+    patch_path: String,
+    output_path: Option<String>,
+    duration: f32,
+    sample_format: SampleFormat,
+    sampling_freq: f32,
+    buffer_size: usize,
+    bench_buffer_sizes: Vec<usize>,
+    num_bench_blocks: usize
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let options = match parse_arguments(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = match options.command {
+        Command::Render => render(&options),
+        Command::Inspect => inspect(&options),
+        Command::Bench => bench(&options),
+        Command::Validate => validate(&options)
+    };
+
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_arguments(args: &[String]) -> Result<Options, String> {
+    let command = match args.first().map(String::as_str) {
+        Some("render") => Command::Render,
+        Some("inspect") => Command::Inspect,
+        Some("bench") => Command::Bench,
+        Some("validate") => Command::Validate,
+        Some(command) => return Err(format!("Unknown command \"{}\"", command)),
+        None => return Err(String::from("No command given"))
+    };
+
+    let mut options = Options {
+        command,
+        patch_path: String::new(),
+        output_path: None,
+        duration: 10.0,
+        sample_format: SampleFormat::Int24,
+        sampling_freq: 48_000.0,
+        buffer_size: 512,
+        bench_buffer_sizes: vec![64, 128, 256, 512, 1024],
+        num_bench_blocks: 1000
+    };
+    let mut patch_path = None;
+
+    let mut remaining = args[1..].iter();
+    while let Some(arg) = remaining.next() {
+        if !arg.starts_with('-') {
+            if patch_path.is_some() {
+                return Err(format!("Unexpected argument \"{}\"", arg));
+            }
+            patch_path = Some(arg.clone());
+            continue;
+        }
+
+        let value = match remaining.next() {
+            Some(value) => value.as_str(),
+            None => return Err(format!("Option {} needs a value", arg))
+        };
+
+        match arg.as_str() {
+            "-o" | "--output" => { options.output_path = Some(String::from(value)); },
+            "--duration" => { options.duration = parse_positive_number(arg, value)?; },
+            "--sample-rate" => { options.sampling_freq = parse_positive_number(arg, value)?; },
+            "--buffer-size" => { options.buffer_size = parse_number(arg, value)?; },
+            "--blocks" => { options.num_bench_blocks = parse_number(arg, value)?; },
+            "--buffer-sizes" => {
+                options.bench_buffer_sizes = value.split(',').map(|size| parse_number(arg, size.trim())).collect::<Result<Vec<usize>, String>>()?;
+            },
+            "--format" => {
+                options.sample_format = match value {
+                    "int16" => SampleFormat::Int16,
+                    "int24" => SampleFormat::Int24,
+                    "float32" => SampleFormat::Float32,
+                    _ => return Err(format!("Unknown sample format \"{}\"", value))
+                };
+            },
+            _ => return Err(format!("Unknown option {}", arg))
+        }
+    }
+
+    options.patch_path = match patch_path {
+        Some(patch_path) => patch_path,
+        None => return Err(String::from("No patch file given"))
+    };
+
+    if options.command == Command::Render && options.output_path.is_none() {
+        return Err(String::from("render needs an output file (-o <file>)"));
+    }
+
+    if options.buffer_size == 0 || options.bench_buffer_sizes.contains(&0) {
+        return Err(String::from("Buffer sizes must be at least 1"));
+    }
+
+    Ok(options)
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    match value.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => Err(format!("Invalid value \"{}\" for {}", value, option))
+    }
+}
+
+/// Parse a time or rate, which has to be a finite number above zero
+fn parse_positive_number(option: &str, value: &str) -> Result<f32, String> {
+    let number: f32 = parse_number(option, value)?;
+    if number.is_finite() && number > 0.0 {
+        Ok(number)
+    } else {
+        Err(format!("{} must be a number above 0, not \"{}\"", option, value))
+    }
+}
+
+fn load_graph(options: &Options) -> Result<AudioGraph, String> {
+    load_patch_from_file(&options.patch_path, &NodeRegistry::with_default_nodes()).map_err(|e| e.message)
+}
+
+fn prepare_graph(graph: &mut AudioGraph, options: &Options, buffer_size: usize) -> Result<(), String> {
+    let runtime_params = AudioRuntimeParameters {
+        sampling_freq: options.sampling_freq,
+        buffer_size
+    };
+
+    graph.prepare(runtime_params).map_err(|e| e.message)
+}
+
+/// Label a node by id and, if it has one, by name
+fn describe_node(graph: &AudioGraph, node_id: usize) -> String {
+    match graph.get_node_name(node_id) {
+        Some(name) => format!("{} (\"{}\")", node_id, name),
+        None => node_id.to_string()
+    }
+}
+
+fn render(options: &Options) -> Result<ExitCode, String> {
+    let output_path = options.output_path.as_deref().unwrap_or_default();
+
+    let mut graph = load_graph(options)?;
+    prepare_graph(&mut graph, options, options.buffer_size)?;
+
+    let summary = render_to_wav(&mut graph, output_path, RenderLength::Duration(options.duration), options.sample_format).map_err(|e| e.message)?;
+
+    println!("Rendered {:.2} s ({} frames, {} channels at {} Hz) to {}",
+             summary.get_duration(), summary.num_frames, summary.num_channels, summary.sampling_freq, output_path);
+    println!("Peak: {:.1} dBFS", 20.0 * summary.peak.max(1e-9).log10());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn inspect(options: &Options) -> Result<ExitCode, String> {
+    let mut graph = load_graph(options)?;
+    prepare_graph(&mut graph, options, options.buffer_size)?;
+
+    println!("{}: {} nodes, {} output channels", options.patch_path, graph.get_number_of_nodes(), graph.get_number_of_output_channels());
+
+    println!("\nNodes:");
+    println!("  {:<20} {:<14} {:<10} {:>6} {:>8} {:>8} {:>12}", "node", "type", "kind", "inputs", "channels", "latency", "compensation");
+    for node_id in graph.get_node_ids() {
+        let node = match graph.get_node(node_id) {
+            Some(node) => node,
+            None => continue
+        };
+
+        let type_name = if node_id == 0 { "output" } else { node.get_type_name().unwrap_or("-") };
+        let channels = format!("{}->{}", node.get_number_of_input_channels(), node.get_number_of_output_channels());
+
+        println!("  {:<20} {:<14} {:<10} {:>6} {:>8} {:>8} {:>12}",
+                 describe_node(&graph, node_id),
+                 type_name,
+                 format!("{:?}", node.get_node_type()),
+                 node.get_number_of_inputs(),
+                 channels,
+                 node.latency_samples(),
+                 graph.get_compensation_delay(node_id).unwrap_or(0));
+    }
+
+    println!("\nConnections:");
+    for connection in graph.get_connections() {
+        println!("  {} -> {} port {}", describe_node(&graph, connection.node_out_id), describe_node(&graph, connection.node_in_id), connection.node_in_input_port);
+    }
+
+    println!("\nParameters:");
+    for node_id in graph.get_node_ids().into_iter().skip(1) {
+        let node = match graph.get_node(node_id) {
+            Some(node) => node,
+            None => continue
+        };
+
+        let parameters = node.get_parameters();
+        if parameters.is_empty() {
+            continue;
+        }
+
+        let names = node.get_parameter_names();
+        let values: Vec<String> = parameters.iter().enumerate().map(|(index, value)| {
+            match names.get(index) {
+                Some(name) => format!("{} = {}", name, value),
+                None => format!("[{}] = {}", index, value)
+            }
+        }).collect();
+
+        println!("  {}: {}", describe_node(&graph, node_id), values.join(", "));
+    }
+
+    let total_latency = graph.get_total_latency();
+    println!("\nTotal latency: {} samples ({:.2} ms at {} Hz)", total_latency, total_latency as f32 * 1000.0 / options.sampling_freq, options.sampling_freq);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn bench(options: &Options) -> Result<ExitCode, String> {
+    for buffer_size in &options.bench_buffer_sizes {
+        //  Start from a fresh graph for every buffer size so that no state carries over
+        let mut graph = load_graph(options)?;
+        prepare_graph(&mut graph, options, *buffer_size)?;
+        graph.enable_profiling(options.num_bench_blocks.max(1));
+
+        let mut buffer = vec![0.0; buffer_size * graph.get_number_of_output_channels()];
+        for _ in 0..options.num_bench_blocks.max(1) {
+            graph.process_block(&mut buffer).map_err(|e| e.message)?;
+        }
+
+        println!("Buffer size {} ({:.2} ms at {} Hz)", buffer_size, *buffer_size as f32 * 1000.0 / options.sampling_freq, options.sampling_freq);
+        println!("{}", graph.get_profiling_report().unwrap_or_default());
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(options: &Options) -> Result<ExitCode, String> {
+    let graph = load_graph(options)?;
+    let report = graph.validate();
+
+    if report.issues.is_empty() {
+        println!("{}: no problems found", options.patch_path);
+    } else {
+        print!("{}", report);
+    }
+
+    if report.is_valid() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_command_lines() {
+        let options = match parse_arguments(&to_args("render patch.json -o out.wav --duration 2.5 --format float32")) {
+            Ok(options) => options,
+            Err(message) => { println!("{}", message); panic!(); }
+        };
+        assert_eq!(options.command, Command::Render);
+        assert_eq!(options.patch_path, "patch.json");
+        assert_eq!(options.output_path.as_deref(), Some("out.wav"));
+        assert_eq!(options.duration, 2.5);
+        assert_eq!(options.sample_format, SampleFormat::Float32);
+
+        let options = parse_arguments(&to_args("bench patch.json --buffer-sizes 32,64 --blocks 10")).ok().unwrap();
+        assert_eq!(options.bench_buffer_sizes, vec![32, 64]);
+        assert_eq!(options.num_bench_blocks, 10);
+
+        assert_eq!(parse_arguments(&to_args("render patch.json")), Err(String::from("render needs an output file (-o <file>)")));
+        assert_eq!(parse_arguments(&to_args("inspect")), Err(String::from("No patch file given")));
+        assert_eq!(parse_arguments(&to_args("inspect patch.json --buffer-size big")), Err(String::from("Invalid value \"big\" for --buffer-size")));
+        assert_eq!(parse_arguments(&to_args("play patch.json")), Err(String::from("Unknown command \"play\"")));
+        assert_eq!(parse_arguments(&to_args("inspect patch.json --sample-rate 0")), Err(String::from("--sample-rate must be a number above 0, not \"0\"")));
+        assert_eq!(parse_arguments(&to_args("render patch.json -o out.wav --duration NaN")), Err(String::from("--duration must be a number above 0, not \"NaN\"")));
+        assert_eq!(parse_arguments(&to_args("render patch.json -o out.wav --duration inf")), Err(String::from("--duration must be a number above 0, not \"inf\"")));
+    }
 }